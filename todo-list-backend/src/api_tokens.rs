@@ -0,0 +1,148 @@
+use actix_web::{HttpResponse, delete, get, post, web};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use todo_list_common::{ApiError, ApiTokenInfo, CreatedApiToken, NewApiToken, TokenScope};
+
+use crate::auth::{Caller, Credential, hash_token, new_token};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::storage::JsonCollection;
+
+/// Every API token starts with this, which tells them apart from the tokens of a login
+/// (and makes them easy to spot should one end up somewhere it shouldn't).
+pub const API_TOKEN_PREFIX: &str = "todo_";
+
+/// A personal API token, kept in `api_tokens.json`. Like sessions, only a digest of it is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub user_id: u32,
+    name: String,
+    token_hash: String,
+    pub scopes: Vec<TokenScope>,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The token `token` is a digest of, unless it has expired.
+pub fn find(tokens: &JsonCollection<ApiToken>, token: &str) -> Option<(u32, ApiToken)> {
+    let token_hash = hash_token(token);
+    tokens.list().into_iter().find(|(_, api_token)| {
+        api_token.token_hash == token_hash && api_token.expires_at.is_none_or(|expires_at| expires_at > Utc::now())
+    })
+}
+
+fn info(id: u32, api_token: ApiToken) -> ApiTokenInfo {
+    ApiTokenInfo {
+        id,
+        name: api_token.name,
+        scopes: api_token.scopes,
+        created_at: api_token.created_at,
+        expires_at: api_token.expires_at,
+    }
+}
+
+// Tokens are managed from a login. An API token that could make more of them would be as good
+// as one with every scope, whatever its own say.
+fn logged_in_user(caller: &Caller) -> Result<u32, AppError> {
+    match (&caller.credential, caller.user_id) {
+        (Credential::Session(_), Some(user_id)) => Ok(user_id),
+        (Credential::ApiToken { .. }, _) => Err(ApiError::forbidden("API tokens can only be managed after logging in").into()),
+        _ => Err(ApiError::unauthorized("Log in to manage API tokens").into()),
+    }
+}
+
+fn token_not_found(id: u32) -> AppError {
+    ApiError::not_found(format!("API token with id {} not found", id)).into()
+}
+
+#[post("/auth/tokens")]
+async fn create_token(
+    state: web::Data<AppState>,
+    caller: Caller,
+    new_token_request: web::Json<NewApiToken>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = logged_in_user(&caller)?;
+    let NewApiToken { name, mut scopes, expires_in_days } = new_token_request.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiError::validation("Token name must not be empty").into());
+    }
+    scopes.sort_unstable();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(ApiError::validation("A token needs at least one scope").into());
+    }
+    if expires_in_days == Some(0) {
+        return Err(ApiError::validation("expires_in_days must be at least 1, leave it out for a token that doesn't expire").into());
+    }
+
+    let token = format!("{}{}", API_TOKEN_PREFIX, new_token()?);
+    let api_token = ApiToken {
+        user_id,
+        name,
+        token_hash: hash_token(&token),
+        scopes,
+        created_at: Utc::now(),
+        expires_at: expires_in_days.map(|days| Utc::now() + Duration::days(days.into())),
+    };
+    let id = state.auth.api_tokens.insert(api_token.clone())?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken { info: info(id, api_token), token }))
+}
+
+#[get("/auth/tokens")]
+async fn list_tokens(state: web::Data<AppState>, caller: Caller) -> Result<HttpResponse, AppError> {
+    let user_id = logged_in_user(&caller)?;
+    let tokens: Vec<ApiTokenInfo> = state
+        .auth
+        .api_tokens
+        .list()
+        .into_iter()
+        .filter(|(_, api_token)| api_token.user_id == user_id)
+        .map(|(id, api_token)| info(id, api_token))
+        .collect();
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+// Takes effect right away, the next request with the token is refused
+#[delete("/auth/tokens/{id}")]
+async fn revoke_token(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let user_id = logged_in_user(&caller)?;
+    let id = *id;
+    match state.auth.api_tokens.get(id) {
+        Some(api_token) if api_token.user_id == user_id => {
+            state.auth.api_tokens.delete(id)?;
+            Ok(HttpResponse::Ok().finish())
+        }
+        _ => Err(token_not_found(id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_live_tokens_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api_tokens.json");
+        let tokens = JsonCollection::open(&path).unwrap();
+        let api_token = |token: &str, expires_at| ApiToken {
+            user_id: 1,
+            name: "cron".to_string(),
+            token_hash: hash_token(token),
+            scopes: vec![TokenScope::Read],
+            created_at: Utc::now(),
+            expires_at,
+        };
+        let forever = tokens.insert(api_token("todo_a", None)).unwrap();
+        tokens.insert(api_token("todo_b", Some(Utc::now() - Duration::days(1)))).unwrap();
+
+        assert_eq!(find(&tokens, "todo_a").map(|(id, _)| id), Some(forever));
+        assert!(find(&tokens, "todo_b").is_none());
+        assert!(find(&tokens, "todo_c").is_none());
+        // Only the digest ends up on disk
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("todo_a"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Replaces the file at `path` with `data` without ever leaving a half-written file behind.
+///
+/// The data goes into a sibling temp file which is fsynced and then renamed over the target,
+/// rename being atomic on the same filesystem. Before that happens, the current file is kept
+/// as `<name>.1` and older copies shift up to `<name>.<backups>`, the oldest one falling off.
+pub fn write_atomically(path: &Path, data: &[u8], backups: usize) -> io::Result<()> {
+    let tmp_path = sibling_path(path, "tmp");
+
+    {
+        let mut tmp_file = File::create(&tmp_path)?;
+        tmp_file.write_all(data)?;
+        tmp_file.sync_all()?;
+    }
+
+    if backups > 0 && path.exists() {
+        rotate_backups(path, backups)?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)?;
+
+    Ok(())
+}
+
+/// Path of the n-th backup of `path`, `.1` being the most recent one.
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    sibling_path(path, &n.to_string())
+}
+
+fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+
+    // Hard link where possible so the current file never disappears, even for a moment.
+    // Not every filesystem supports links though, so fall back to a plain copy.
+    let newest = backup_path(path, 1);
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+
+    Ok(())
+}
+
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+// The rename itself only survives a power loss once the directory entry is flushed too.
+// Windows doesn't let us open a directory like this, and NTFS doesn't need it anyway.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_rotating_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo_list.json");
+
+        for n in 0..5 {
+            write_atomically(&path, n.to_string().as_bytes(), 3).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "3");
+        assert_eq!(fs::read_to_string(backup_path(&path, 2)).unwrap(), "2");
+        assert_eq!(fs::read_to_string(backup_path(&path, 3)).unwrap(), "1");
+        assert!(!backup_path(&path, 4).exists());
+        assert!(!sibling_path(&path, "tmp").exists());
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    future::{Ready, ready},
+    num::NonZeroU32,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use actix_web::{
+    FromRequest, HttpMessage, HttpRequest, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    get,
+    http::{
+        Method,
+        header::{AUTHORIZATION, HeaderName, HeaderValue},
+    },
+    middleware::Next,
+    post, web,
+};
+use chrono::{DateTime, Duration, Utc};
+use ring::{
+    digest, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use todo_list_common::{ApiError, Credentials, LoginResponse, OPERATION_HEADER, TokenScope, UserInfo};
+
+use crate::api_tokens::{self, API_TOKEN_PREFIX, ApiToken};
+use crate::error::AppError;
+use crate::lists;
+use crate::state::AppState;
+use crate::storage::{JsonCollection, Operation, OwnedStore, StoreError, TodoStore};
+
+// OWASP's recommendation for PBKDF2-HMAC-SHA256. The count is stored with every hash, so it
+// can be raised later without invalidating the passwords hashed before.
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const TOKEN_LEN: usize = 32;
+const SESSION_DAYS: i64 = 30;
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// An account, kept in `users.json` next to the todos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// `pbkdf2-sha256$<iterations>$<salt>$<hash>`, salt and hash in hex
+    password_hash: String,
+    created_at: DateTime<Utc>,
+}
+
+// Only a digest of the token is stored, so the sessions file alone doesn't let anyone in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Session {
+    user_id: u32,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// The accounts, and the tokens handed out to them at login or for their scripts.
+pub struct Auth {
+    users: JsonCollection<User>,
+    sessions: JsonCollection<Session>,
+    pub api_tokens: JsonCollection<ApiToken>,
+    /// Refuse requests without a token, rather than treating them as nobody in particular
+    required: bool,
+}
+
+impl Auth {
+    pub fn open(data_dir: &Path, required: bool) -> Result<Self, StoreError> {
+        Ok(Auth {
+            users: JsonCollection::open(data_dir.join("users.json"))?,
+            sessions: JsonCollection::open(data_dir.join("sessions.json"))?,
+            api_tokens: JsonCollection::open(data_dir.join("api_tokens.json"))?,
+            required,
+        })
+    }
+
+    fn find_user(&self, username: &str) -> Option<(u32, User)> {
+        self.users.list().into_iter().find(|(_, user)| user.username == username)
+    }
+
+    /// The id of the user called `username`, going by the same rules as registering.
+    pub fn user_id(&self, username: &str) -> Option<u32> {
+        let username = check_username(username).ok()?;
+        self.find_user(&username).map(|(id, _)| id)
+    }
+
+    pub fn username(&self, user_id: u32) -> Option<String> {
+        self.users.get(user_id).map(|user| user.username)
+    }
+
+    fn find_session(&self, token: &str) -> Option<(u32, Session)> {
+        let token_hash = hash_token(token);
+        self.sessions
+            .list()
+            .into_iter()
+            .find(|(_, session)| session.token_hash == token_hash && session.expires_at > Utc::now())
+    }
+
+    // Whose token came with the request, if any. A token that's there has to be valid,
+    // falling back to no user would hide the other todos without saying why.
+    fn identify(&self, header: Option<&HeaderValue>) -> Result<Identity, AppError> {
+        let Some(header) = header else {
+            return match self.required {
+                true => Err(ApiError::unauthorized("Log in first, this backend only answers requests with a bearer token").into()),
+                false => Ok(Identity { user_id: None, credential: Credential::Anonymous }),
+            };
+        };
+        let identity = match header.to_str().ok().and_then(bearer_token) {
+            Some(token) if token.starts_with(API_TOKEN_PREFIX) => {
+                api_tokens::find(&self.api_tokens, token).map(|(id, api_token)| Identity {
+                    user_id: Some(api_token.user_id),
+                    credential: Credential::ApiToken { id, scopes: api_token.scopes },
+                })
+            }
+            Some(token) => self.find_session(token).map(|(id, session)| Identity {
+                user_id: Some(session.user_id),
+                credential: Credential::Session(id),
+            }),
+            None => None,
+        };
+        identity
+            .filter(|identity| identity.user_id.is_some_and(|id| self.users.get(id).is_some()))
+            .ok_or_else(|| ApiError::unauthorized("The token is invalid or has expired, log in again").into())
+    }
+
+    fn start_session(&self, user_id: u32) -> Result<(String, DateTime<Utc>), AppError> {
+        let token = new_token()?;
+        let expires_at = Utc::now() + Duration::days(SESSION_DAYS);
+        // Expired sessions are of no use to anyone, this is as good a time as any to drop them
+        for (id, session) in self.sessions.list() {
+            if session.expires_at <= Utc::now() {
+                self.sessions.delete(id)?;
+            }
+        }
+        self.sessions.insert(Session { user_id, token_hash: hash_token(&token), expires_at })?;
+        Ok((token, expires_at))
+    }
+
+    /// Registers `username` and logs them in, without the password hashing that's slow on purpose.
+    #[cfg(test)]
+    pub fn test_user(&self, username: &str) -> (u32, String) {
+        let user = User { username: username.to_string(), password_hash: String::new(), created_at: Utc::now() };
+        let id = self.users.insert(user).unwrap();
+        (id, self.start_session(id).unwrap().0)
+    }
+}
+
+fn bearer_token(header: &str) -> Option<&str> {
+    let (scheme, token) = header.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], AppError> {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| ApiError::storage("Could not generate random bytes"))?;
+    Ok(bytes)
+}
+
+/// A fresh random token, in hex.
+pub fn new_token() -> Result<String, AppError> {
+    Ok(hex::encode(random_bytes::<TOKEN_LEN>()?))
+}
+
+/// What's stored in place of a token, it can be compared but not turned back into one.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+fn hash_password(password: &str, iterations: NonZeroU32) -> Result<String, AppError> {
+    let salt = random_bytes::<SALT_LEN>()?;
+    let mut hash = [0; digest::SHA256_OUTPUT_LEN];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &mut hash);
+    Ok(format!("pbkdf2-sha256${}${}${}", iterations, hex::encode(salt), hex::encode(hash)))
+}
+
+// A hash that can't be parsed never matches, rather than failing the login with a 500
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    let parts: Vec<&str> = password_hash.split('$').collect();
+    let ["pbkdf2-sha256", iterations, salt, hash] = parts[..] else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) =
+        (iterations.parse().ok().and_then(NonZeroU32::new), hex::decode(salt), hex::decode(hash))
+    else {
+        return false;
+    };
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &hash).is_ok()
+}
+
+// Usernames are compared as given, so they're kept in one form: trimmed and lowercased
+fn check_username(username: &str) -> Result<String, AppError> {
+    let username = username.trim().to_lowercase();
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+    if username.is_empty() || username.len() > 32 || !username.chars().all(allowed) {
+        return Err(ApiError::validation("Usernames are 1 to 32 letters, digits, '_', '-' or '.'").into());
+    }
+    Ok(username)
+}
+
+fn iterations() -> NonZeroU32 {
+    NonZeroU32::new(PBKDF2_ITERATIONS).expect("the iteration count isn't zero")
+}
+
+// Hashing takes a while on purpose, which is time a worker shouldn't spend blocked
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, AppError> {
+    web::block(f).await.map_err(|err| {
+        eprintln!("Password hashing failed: {}", err);
+        ApiError::storage("Could not check the password").into()
+    })
+}
+
+/// How a request proved who it's from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    /// No token at all, only let through when the backend doesn't require one
+    Anonymous,
+    /// A token from `POST /auth/login`, with the id of its session
+    Session(u32),
+    /// A personal API token, which can only do what its scopes allow
+    ApiToken { id: u32, scopes: Vec<TokenScope> },
+}
+
+impl Credential {
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        match self {
+            Credential::ApiToken { scopes, .. } => scopes.contains(&scope),
+            Credential::Anonymous | Credential::Session(_) => true,
+        }
+    }
+}
+
+/// Who a request comes from. Worked out once by the `authenticate` middleware, which leaves it
+/// in the request's extensions for `Caller` to pick up.
+#[derive(Debug, Clone)]
+struct Identity {
+    user_id: Option<u32>,
+    credential: Credential,
+}
+
+// Registering and logging in is how a token is got in the first place, so they go without one.
+// A stale token a client still has lying around is ignored there, rather than refused.
+const PUBLIC_PATHS: [&str; 2] = ["/auth/register", "/auth/login"];
+
+/// Checks the bearer token of every request, and that its scopes cover the request, before any
+/// handler runs: `read` for `GET` and `HEAD`, `write` for everything else. A request that
+/// changed any todos answers with the operation they were recorded as, see `OPERATION_HEADER`.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if PUBLIC_PATHS.contains(&req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let state = req.app_data::<web::Data<AppState>>().expect("AppState is registered as app data").clone();
+    let scope = match *req.method() {
+        Method::GET | Method::HEAD => TokenScope::Read,
+        _ => TokenScope::Write,
+    };
+    let identity = state.auth.identify(req.headers().get(AUTHORIZATION)).and_then(|identity| {
+        match identity.credential.allows(scope) {
+            true => Ok(identity),
+            false => Err(ApiError::forbidden(format!("This API token doesn't have the {} scope", scope)).into()),
+        }
+    });
+
+    match identity {
+        Ok(identity) => {
+            // What the request changes is recorded as this, the client gets its id back to undo it by
+            let operation = Arc::new(Operation::new(identity.user_id));
+            req.extensions_mut().insert(identity);
+            req.extensions_mut().insert(operation.clone());
+            let mut res = next.call(req).await?;
+            if let Some(id) = operation.id() {
+                res.headers_mut().insert(HeaderName::from_static(OPERATION_HEADER), HeaderValue::from(id));
+            }
+            Ok(res.map_into_left_body())
+        }
+        Err(err) => Ok(req.error_response(err).map_into_right_body()),
+    }
+}
+
+/// Who a request comes from, and the todos it gets to see. Every handler that touches todos
+/// goes through `store` rather than `AppState::store`.
+pub struct Caller {
+    pub user_id: Option<u32>,
+    pub credential: Credential,
+    pub store: OwnedStore,
+    /// Everyone's todos, for what a change does to todos the caller doesn't own, like the
+    /// subtasks of one it deletes
+    pub everyone: Arc<dyn TodoStore>,
+    /// What this request changes, both stores record their changes as part of it
+    pub operation: Arc<Operation>,
+}
+
+impl Caller {
+    fn new(state: &AppState, identity: Identity, operation: Arc<Operation>) -> Self {
+        let everyone = state.recording(Some(operation.clone()));
+        Caller {
+            user_id: identity.user_id,
+            credential: identity.credential,
+            store: OwnedStore::new(everyone.clone(), identity.user_id, lists::shared_with(&state.lists, identity.user_id)),
+            everyone,
+            operation,
+        }
+    }
+
+    /// Works out the caller from the `Authorization` header again, for connections that outlive
+    /// the request that opened them. A token that was revoked or expired since is refused.
+    pub fn identify(state: &AppState, header: Option<&HeaderValue>) -> Result<Self, AppError> {
+        let identity = state.auth.identify(header)?;
+        let operation = Arc::new(Operation::new(identity.user_id));
+        Ok(Caller::new(state, identity, operation))
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = AppError;
+    type Future = Ready<Result<Self, AppError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let state = req.app_data::<web::Data<AppState>>().expect("AppState is registered as app data");
+        let extensions = req.extensions();
+        let caller = match (extensions.get::<Identity>(), extensions.get::<Arc<Operation>>()) {
+            (Some(identity), Some(operation)) => Ok(Caller::new(state, identity.clone(), operation.clone())),
+            // Only the public paths skip the middleware, and they don't ask for a caller
+            _ => Err(ApiError::unauthorized("The request wasn't authenticated").into()),
+        };
+        ready(caller)
+    }
+}
+
+#[post("/auth/register")]
+async fn register(state: web::Data<AppState>, credentials: web::Json<Credentials>) -> Result<HttpResponse, AppError> {
+    let username = check_username(&credentials.username)?;
+    if credentials.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::validation(format!("Passwords need at least {} characters", MIN_PASSWORD_LEN)).into());
+    }
+    let password = credentials.into_inner().password;
+    let password_hash = blocking(move || hash_password(&password, iterations())).await??;
+
+    let _guard = state.write();
+    if state.auth.find_user(&username).is_some() {
+        return Err(ApiError::conflict(format!("The username {} is taken", username)).into());
+    }
+    let id = state.auth.users.insert(User { username: username.clone(), password_hash, created_at: Utc::now() })?;
+
+    Ok(HttpResponse::Created().json(UserInfo { id, username }))
+}
+
+#[post("/auth/login")]
+async fn login(state: web::Data<AppState>, credentials: web::Json<Credentials>) -> Result<HttpResponse, AppError> {
+    let Credentials { username, password } = credentials.into_inner();
+    let username = username.trim().to_lowercase();
+    let user = state.auth.find_user(&username);
+
+    // An unknown username takes as long to refuse as a wrong password, so the timing doesn't
+    // give away which accounts exist
+    static UNKNOWN_USER_HASH: OnceLock<String> = OnceLock::new();
+    let password_hash = match &user {
+        Some((_, user)) => user.password_hash.clone(),
+        None => UNKNOWN_USER_HASH.get_or_init(|| hash_password("", iterations()).unwrap_or_default()).clone(),
+    };
+    let verified = blocking(move || verify_password(&password, &password_hash)).await?;
+    let Some((user_id, user)) = user.filter(|_| verified) else {
+        return Err(ApiError::unauthorized("Wrong username or password").into());
+    };
+
+    let (token, expires_at) = state.auth.start_session(user_id)?;
+    Ok(HttpResponse::Ok().json(LoginResponse { token, expires_at, user: UserInfo { id: user_id, username: user.username } }))
+}
+
+// Ends the session of the token the request came with, other sessions of the user stay valid
+#[post("/auth/logout")]
+async fn logout(state: web::Data<AppState>, caller: Caller) -> Result<HttpResponse, AppError> {
+    let Credential::Session(session_id) = caller.credential else {
+        return Err(ApiError::unauthorized("Not logged in, API tokens are revoked with DELETE /auth/tokens/{id}").into());
+    };
+    state.auth.sessions.delete(session_id)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/auth/me")]
+async fn me(state: web::Data<AppState>, caller: Caller) -> Result<HttpResponse, AppError> {
+    let user = caller.user_id.and_then(|id| Some((id, state.auth.users.get(id)?)));
+    match user {
+        Some((id, user)) => Ok(HttpResponse::Ok().json(UserInfo { id, username: user.username })),
+        None => Err(ApiError::unauthorized("Not logged in").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_and_tokens() {
+        let cheap = NonZeroU32::new(1_000).unwrap();
+        let hash = hash_password("correct horse", cheap).unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horsE", &hash));
+        assert!(!verify_password("correct horse", "plain text"));
+        // The salt makes every hash of the same password different
+        assert_ne!(hash, hash_password("correct horse", cheap).unwrap());
+
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(check_username(" Alice ").unwrap(), "alice");
+        assert!(check_username("al ice").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let auth = Auth::open(dir.path(), false).unwrap();
+        let (token, _) = auth.start_session(3).unwrap();
+        assert_eq!(auth.find_session(&token).unwrap().1.user_id, 3);
+        assert!(auth.find_session("not a token").is_none());
+
+        let read_only = Credential::ApiToken { id: 0, scopes: vec![TokenScope::Read] };
+        assert!(read_only.allows(TokenScope::Read));
+        assert!(!read_only.allows(TokenScope::Write));
+        assert!(Credential::Session(0).allows(TokenScope::Write));
+    }
+}
@@ -0,0 +1,150 @@
+use actix_web::{HttpResponse, delete, http::header::IfMatch, patch, post, web};
+use todo_list_common::{ApiError, ChecklistItem, ChecklistPatch, NewChecklistItem, TodoItem};
+
+use crate::auth::Caller;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::{entry_response, modify_todo};
+
+// Where the step with `item_id` sits in the todo's checklist
+fn position_of(item: &TodoItem, id: u32, item_id: u32) -> Result<usize, AppError> {
+    item.checklist
+        .iter()
+        .position(|entry| entry.id == item_id)
+        .ok_or_else(|| ApiError::not_found(format!("Todo item with id {} has no checklist item {}", id, item_id)).into())
+}
+
+fn check_position(position: usize, len: usize) -> Result<(), AppError> {
+    if position > len {
+        return Err(ApiError::validation(format!("Position {} is past the end of the checklist", position)).into());
+    }
+    Ok(())
+}
+
+// Like every checklist change, this answers with the whole todo, so the client has its new revision
+#[post("/todos/{id}/checklist")]
+async fn add_checklist_item(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    if_match: Option<web::Header<IfMatch>>,
+    new_item: web::Json<NewChecklistItem>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let (item, _) = modify_todo(&state, &caller, id, if_match.as_deref(), |item| {
+        let position = new_item.position.unwrap_or(item.checklist.len());
+        check_position(position, item.checklist.len())?;
+        // The id is handed out when the todo is stamped
+        item.checklist.insert(position, ChecklistItem { id: 0, text: new_item.text.clone(), done: false });
+        Ok(())
+    })?;
+
+    Ok(entry_response(id, item))
+}
+
+#[patch("/todos/{id}/checklist/{item_id}")]
+async fn patch_checklist_item(
+    state: web::Data<AppState>,
+    caller: Caller,
+    path: web::Path<(u32, u32)>,
+    if_match: Option<web::Header<IfMatch>>,
+    patch: web::Json<ChecklistPatch>,
+) -> Result<HttpResponse, AppError> {
+    let (id, item_id) = *path;
+    let (item, _) = modify_todo(&state, &caller, id, if_match.as_deref(), |item| {
+        let position = position_of(item, id, item_id)?;
+        let mut entry = item.checklist.remove(position);
+        if let Some(text) = &patch.text {
+            entry.text = text.clone();
+        }
+        if let Some(done) = patch.done {
+            entry.done = done;
+        }
+
+        let new_position = patch.position.unwrap_or(position);
+        check_position(new_position, item.checklist.len())?;
+        item.checklist.insert(new_position, entry);
+        Ok(())
+    })?;
+
+    Ok(entry_response(id, item))
+}
+
+#[delete("/todos/{id}/checklist/{item_id}")]
+async fn delete_checklist_item(
+    state: web::Data<AppState>,
+    caller: Caller,
+    path: web::Path<(u32, u32)>,
+    if_match: Option<web::Header<IfMatch>>,
+) -> Result<HttpResponse, AppError> {
+    let (id, item_id) = *path;
+    let (item, _) = modify_todo(&state, &caller, id, if_match.as_deref(), |item| {
+        let position = position_of(item, id, item_id)?;
+        item.checklist.remove(position);
+        Ok(())
+    })?;
+
+    Ok(entry_response(id, item))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use todo_list_common::{InsertResponse, TodoEntry, UpdateResponse};
+
+    use super::*;
+    use crate::testing::{app, state};
+
+    fn steps(entry: &TodoEntry) -> Vec<(u32, &str, bool)> {
+        entry.item.checklist.iter().map(|step| (step.id, step.text.as_str(), step.done)).collect()
+    }
+
+    #[actix_web::test]
+    async fn steps_keep_their_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = init_service(app(state(dir.path()))).await;
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+        let res = call_service(&app, TestRequest::post().uri("/insert").set_json(&item).to_request()).await;
+        let InsertResponse { id } = read_body_json(res).await;
+        let uri = format!("/todos/{}/checklist", id);
+        let send = async |req: TestRequest| {
+            let res = call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            read_body_json::<TodoEntry, _>(res).await
+        };
+        let add = |text: &str, position: Option<usize>| {
+            TestRequest::post().uri(&uri).set_json(NewChecklistItem { text: text.to_string(), position })
+        };
+        let change = |step: u32, patch: ChecklistPatch| TestRequest::patch().uri(&format!("{}/{}", uri, step)).set_json(patch);
+
+        send(add("buy", None)).await;
+        send(add("cook", None)).await;
+        let entry = send(add("plan", Some(0))).await;
+        assert_eq!(steps(&entry), vec![(3, "plan", false), (1, "buy", false), (2, "cook", false)]);
+
+        // Checking, editing and moving a step leaves the ids where they were
+        send(change(1, ChecklistPatch { done: Some(true), ..Default::default() })).await;
+        send(change(2, ChecklistPatch { text: Some("cook dinner".to_string()), ..Default::default() })).await;
+        let entry = send(change(3, ChecklistPatch { position: Some(2), ..Default::default() })).await;
+        assert_eq!(steps(&entry), vec![(1, "buy", true), (2, "cook dinner", false), (3, "plan", false)]);
+
+        // The id of a removed step isn't handed out again, not even the highest one
+        let entry = send(TestRequest::delete().uri(&format!("{}/3", uri))).await;
+        assert_eq!(steps(&entry), vec![(1, "buy", true), (2, "cook dinner", false)]);
+        let entry = send(add("wash up", None)).await;
+        assert_eq!(steps(&entry).last(), Some(&(4, "wash up", false)));
+        let res = call_service(&app, TestRequest::delete().uri(&format!("{}/3", uri)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // Ids the backend didn't hand out are replaced, rather than taken over
+        let mut item = entry.item;
+        item.checklist.push(ChecklistItem { id: u32::MAX, text: "made up".to_string(), done: false });
+        item.checklist.push(ChecklistItem { id: 1, text: "twice".to_string(), done: false });
+        let res = call_service(&app, TestRequest::put().uri(&format!("/update/{}", id)).set_json(item).to_request()).await;
+        let UpdateResponse { entry, .. } = read_body_json(res).await;
+        let ids: Vec<u32> = entry.item.checklist.iter().map(|step| step.id).collect();
+        assert_eq!(ids, vec![1, 2, 4, 5, 6]);
+        assert_eq!(entry.item.next_step_id, 7);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use actix_web::http::{Method, Uri, header::HeaderName};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+// Picked up from the working directory when no --config is given, but not required to exist
+const DEFAULT_CONFIG_FILE: &str = "todo-backend.toml";
+
+/// Todo list backend.
+///
+/// Every setting can come from a flag, an environment variable or the TOML config file,
+/// in that order of precedence. Anything not set anywhere falls back to its default.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+    /// TOML file to read settings from [default: ./todo-backend.toml, if it exists]
+    #[arg(long, env = "TODO_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long, env = "TODO_HOST")]
+    host: Option<String>,
+
+    /// Port to listen on [default: 8081]
+    #[arg(long, env = "TODO_PORT")]
+    port: Option<u16>,
+
+    /// Directory the todo list is stored in [default: ./.storage]
+    #[arg(long, env = "TODO_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// How the todo list is stored [default: json]
+    #[arg(long, env = "TODO_STORAGE")]
+    storage: Option<StorageBackend>,
+
+    /// Number of HTTP worker threads [default: number of CPUs]
+    #[arg(long, env = "TODO_WORKERS")]
+    workers: Option<usize>,
+
+    /// Origins allowed by CORS, comma separated. "*" allows any origin [default: http://localhost:8080]
+    #[arg(long, env = "TODO_ALLOWED_ORIGINS", value_delimiter = ',')]
+    allowed_origins: Option<Vec<String>>,
+
+    /// Methods allowed by CORS, comma separated [default: GET,POST,PUT,PATCH,DELETE]
+    #[arg(long, env = "TODO_ALLOWED_METHODS", value_delimiter = ',')]
+    allowed_methods: Option<Vec<String>>,
+
+    /// Request headers allowed by CORS, comma separated [default: authorization,accept,content-type,if-match]
+    #[arg(long, env = "TODO_ALLOWED_HEADERS", value_delimiter = ',')]
+    allowed_headers: Option<Vec<String>>,
+
+    /// Refuse requests that don't come with a bearer token from `POST /auth/login` [default: false]
+    #[arg(long, env = "TODO_REQUIRE_AUTH", num_args = 0..=1, default_missing_value = "true")]
+    require_auth: Option<bool>,
+
+    /// Let webhooks post to loopback and private network addresses, for receivers on this machine [default: false]
+    #[arg(long, env = "TODO_ALLOW_PRIVATE_WEBHOOKS", num_args = 0..=1, default_missing_value = "true")]
+    allow_private_webhooks: Option<bool>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// The whole list in one JSON file, rewritten on every change
+    Json,
+    /// A JSON snapshot plus an append-only journal of changes
+    Journal,
+    /// An embedded SQLite database
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub data_dir: PathBuf,
+    pub storage: StorageBackend,
+    pub workers: usize,
+    pub require_auth: bool,
+    pub allow_private_webhooks: bool,
+    pub cors: CorsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: 8081,
+            data_dir: PathBuf::from("./.storage"),
+            storage: StorageBackend::Json,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            require_auth: false,
+            allow_private_webhooks: false,
+            cors: CorsConfig::default(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            // Replace with your desired origin, specifically if served in the browser with this URL
+            allowed_origins: vec!["http://localhost:8080".to_string()],
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["authorization", "accept", "content-type", "if-match"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the effective configuration: defaults, overridden by the config file,
+    /// overridden by environment variables and flags (clap already ranks those two).
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let mut config = match &args.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+
+        if let Some(host) = &args.host {
+            config.host = host.clone();
+        }
+        if let Some(port) = args.port {
+            config.port = port;
+        }
+        if let Some(data_dir) = &args.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        if let Some(storage) = args.storage {
+            config.storage = storage;
+        }
+        if let Some(workers) = args.workers {
+            config.workers = workers;
+        }
+        if let Some(require_auth) = args.require_auth {
+            config.require_auth = require_auth;
+        }
+        if let Some(allow_private_webhooks) = args.allow_private_webhooks {
+            config.allow_private_webhooks = allow_private_webhooks;
+        }
+        if let Some(origins) = &args.allowed_origins {
+            config.cors.allowed_origins = origins.clone();
+        }
+        if let Some(methods) = &args.allowed_methods {
+            config.cors.allowed_methods = methods.clone();
+        }
+        if let Some(headers) = &args.allowed_headers {
+            config.cors.allowed_headers = headers.clone();
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("could not read config file {}: {}", path.display(), err))?;
+        toml::from_str(&contents).map_err(|err| format!("invalid config file {}: {}", path.display(), err))
+    }
+
+    // actix-cors fails on origins, methods or headers it can't parse, so catch those while we can still say why
+    fn validate(&self) -> Result<(), String> {
+        if self.workers == 0 {
+            return Err("workers must be at least 1".to_string());
+        }
+        for origin in self.cors.allowed_origins.iter().filter(|origin| *origin != "*") {
+            Uri::from_str(origin).map_err(|_| format!("invalid CORS origin {:?}", origin))?;
+        }
+        for method in &self.cors.allowed_methods {
+            Method::from_str(method).map_err(|_| format!("invalid CORS method {:?}", method))?;
+        }
+        for header in &self.cors.allowed_headers {
+            HeaderName::from_str(header).map_err(|_| format!("invalid CORS header {:?}", header))?;
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always representable as TOML")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo-backend.toml");
+        fs::write(
+            &path,
+            "port = 9000\nstorage = \"sqlite\"\n\n[cors]\nallowed_origins = [\"https://todo.example\"]\n",
+        )
+        .unwrap();
+
+        let args = Args::try_parse_from([
+            "todo-list-backend",
+            "--config",
+            path.to_str().unwrap(),
+            "--port",
+            "9001",
+            "--allowed-methods",
+            "GET,PATCH",
+            "--require-auth",
+        ])
+        .unwrap();
+        let config = Config::load(&args).unwrap();
+
+        assert_eq!(config.port, 9001);
+        assert_eq!(config.storage, StorageBackend::Sqlite);
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.cors.allowed_origins, vec!["https://todo.example"]);
+        assert_eq!(config.cors.allowed_methods, vec!["GET", "PATCH"]);
+        assert!(config.require_auth);
+
+        // What --print-config shows has to be loadable again
+        let printed: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(printed.port, 9001);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use todo_list_common::{ApiError, TodoItem};
+
+use crate::error::AppError;
+use crate::stamp;
+use crate::storage::{TodoStore, TodoView};
+
+/// Makes sure every blocker is one of the todos in `store`, and that following the blockers of
+/// the blockers never leads back to `id`, which would leave todos waiting on each other forever.
+/// `id` is `None` for a todo that's still being inserted, nothing can be waiting on that one yet.
+/// Like `hierarchy::check_parent`, the blockers are followed in `everyone`.
+pub fn check_blockers(store: &dyn TodoView, everyone: &dyn TodoView, id: Option<u32>, blocked_by: &[u32]) -> Result<(), AppError> {
+    for blocker in blocked_by {
+        if store.get(*blocker)?.is_none() {
+            return Err(ApiError::validation(format!("Blocking todo item {} doesn't exist", blocker)).into());
+        }
+    }
+    let Some(id) = id else { return Ok(()) };
+    if blocked_by.is_empty() {
+        return Ok(());
+    }
+
+    let todos = everyone.list()?;
+    let mut waiting: Vec<u32> = blocked_by.to_vec();
+    let mut seen = BTreeSet::new();
+    while let Some(blocker) = waiting.pop() {
+        if blocker == id {
+            return Err(ApiError::conflict(format!(
+                "Todo item {} can't be blocked by {}, it would end up waiting on itself",
+                id,
+                blocked_by.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")
+            ))
+            .into());
+        }
+        if seen.insert(blocker) {
+            waiting.extend(todos.get(&blocker).into_iter().flat_map(|item| item.blocked_by.iter().copied()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `item` still waits on a blocker that isn't completed.
+pub fn is_blocked(todos: &BTreeMap<u32, TodoItem>, item: &TodoItem) -> bool {
+    item.blocked_by
+        .iter()
+        .any(|blocker| todos.get(blocker).is_some_and(|blocker| !blocker.completed))
+}
+
+/// The open todos blocked by `id` that have nothing left to wait for, once `id` is completed.
+pub fn unblocked_by(todos: &BTreeMap<u32, TodoItem>, id: u32) -> Vec<u32> {
+    todos
+        .iter()
+        .filter(|(_, item)| !item.completed && item.blocked_by.contains(&id) && !is_blocked(todos, item))
+        .map(|(waiting_id, _)| *waiting_id)
+        .collect()
+}
+
+/// Takes the `deleted` todos out of the blockers of every todo left, so none waits on a todo
+/// that's gone.
+pub fn forget_blockers(store: &dyn TodoStore, todos: &BTreeMap<u32, TodoItem>, deleted: &[u32]) -> Result<(), AppError> {
+    for (id, current) in todos {
+        if deleted.contains(id) || !current.blocked_by.iter().any(|blocker| deleted.contains(blocker)) {
+            continue;
+        }
+        let mut item = current.clone();
+        item.blocked_by.retain(|blocker| !deleted.contains(blocker));
+        stamp(&mut item, Some(current))?;
+        store.update(*id, item)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::JsonFileStore;
+
+    use super::*;
+
+    fn blocked_by(blocked_by: &[u32]) -> TodoItem {
+        TodoItem { title: "task".to_string(), blocked_by: blocked_by.to_vec(), ..Default::default() }
+    }
+
+    #[test]
+    fn refuses_cycles_and_reports_unblocked() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonFileStore::open(dir.path().join("todo_list.json")).unwrap();
+        let first = store.insert(blocked_by(&[])).unwrap();
+        let second = store.insert(blocked_by(&[first])).unwrap();
+        let third = store.insert(blocked_by(&[first, second])).unwrap();
+
+        assert!(check_blockers(&store, &store, None, &[third]).is_ok());
+        assert!(check_blockers(&store, &store, Some(first), &[first]).is_err());
+        assert!(check_blockers(&store, &store, Some(first), &[third]).is_err());
+        assert!(check_blockers(&store, &store, Some(second), &[42]).is_err());
+
+        let mut todos = store.list().unwrap();
+        assert!(is_blocked(&todos, &todos[&third]));
+        todos.get_mut(&first).unwrap().completed = true;
+        // The third one still waits on the second
+        assert_eq!(unblocked_by(&todos, first), [second]);
+        todos.get_mut(&second).unwrap().completed = true;
+        assert_eq!(unblocked_by(&todos, second), [third]);
+    }
+}
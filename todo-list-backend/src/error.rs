@@ -0,0 +1,151 @@
+use std::fmt;
+
+use actix_web::{HttpRequest, HttpResponse, ResponseError, http::StatusCode};
+use todo_list_common::ApiError;
+
+use crate::storage::StoreError;
+
+/// What every handler returns on failure. It only wraps the shared `ApiError`, which lives in
+/// todo-list-common so the clients can decode it, but can't implement actix traits there.
+#[derive(Debug)]
+pub struct AppError(pub ApiError);
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.0.code.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(&self.0)
+    }
+}
+
+impl From<ApiError> for AppError {
+    fn from(err: ApiError) -> Self {
+        AppError(err)
+    }
+}
+
+impl From<StoreError> for AppError {
+    fn from(err: StoreError) -> Self {
+        // As far as the caller is concerned, someone else's todo isn't there
+        match err {
+            StoreError::NotOwned(id) => return AppError(ApiError::not_found(format!("Todo item with id {} not found", id))),
+            StoreError::ReadOnly(list_id) => return AppError(read_only(list_id)),
+            StoreError::OwnerOnly(id) => {
+                return AppError(ApiError::forbidden(format!(
+                    "Todo item {} isn't yours, only its owner can move it to a list of someone else",
+                    id
+                )));
+            }
+            _ => {}
+        }
+        // Paths and database messages are for whoever runs the server, not for the clients
+        eprintln!("Storage error: {}", err);
+        AppError(ApiError::storage("The todo list could not be read or written"))
+    }
+}
+
+/// Refuses a change to a todo on a list the caller may only look at.
+pub fn read_only(list_id: u32) -> ApiError {
+    ApiError::forbidden(format!("List {} is shared with you as a viewer, its todos can't be changed", list_id))
+}
+
+// Extractor failures (bad JSON, an id that isn't a number, ...) would otherwise answer in plain text
+pub fn json_error_handler(err: actix_web::error::JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError(ApiError::validation("Invalid JSON body").with_details(err.to_string())).into()
+}
+
+pub fn path_error_handler(err: actix_web::error::PathError, _req: &HttpRequest) -> actix_web::Error {
+    AppError(ApiError::validation("Invalid path parameter").with_details(err.to_string())).into()
+}
+
+pub fn query_error_handler(err: actix_web::error::QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError(ApiError::validation("Invalid query parameter").with_details(err.to_string())).into()
+}
+
+pub async fn route_not_found(req: HttpRequest) -> HttpResponse {
+    AppError(ApiError::not_found(format!("No endpoint at {} {}", req.method(), req.path()))).error_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::header::IF_MATCH;
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, init_service, read_body_json};
+    use todo_list_common::{ErrorCode, InsertResponse, ListRole, ListShare, TodoItem, TodoList, TodoListEntry};
+
+    use super::*;
+    use crate::testing::{app, state, user};
+
+    // Every error answers with the status its code maps to, and the code in the JSON body
+    async fn error<B: MessageBody>(res: ServiceResponse<B>, code: ErrorCode) -> ApiError {
+        assert_eq!(res.status().as_u16(), code.status_code());
+        let err: ApiError = read_body_json(res).await;
+        assert_eq!(err.code, code);
+        assert!(!err.message.is_empty());
+        err
+    }
+
+    #[actix_web::test]
+    async fn errors_answer_in_json_with_their_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+
+        // 400, from a handler and from each of the extractors
+        let req = TestRequest::post().uri("/insert").set_json(TodoItem { title: " ".to_string(), ..item.clone() }).to_request();
+        error(call_service(&app, req).await, ErrorCode::Validation).await;
+        let req = TestRequest::post().uri("/insert").insert_header(("content-type", "application/json")).set_payload("{not json").to_request();
+        let err = error(call_service(&app, req).await, ErrorCode::Validation).await;
+        assert!(err.details.is_some());
+        error(call_service(&app, TestRequest::get().uri("/view/first").to_request()).await, ErrorCode::Validation).await;
+        error(call_service(&app, TestRequest::get().uri("/list?completed=maybe").to_request()).await, ErrorCode::Validation).await;
+
+        // 401
+        error(call_service(&app, TestRequest::get().uri("/auth/me").to_request()).await, ErrorCode::Unauthorized).await;
+        let req = TestRequest::get().uri("/list").insert_header(("authorization", "Bearer nonsense")).to_request();
+        error(call_service(&app, req).await, ErrorCode::Unauthorized).await;
+
+        // 403, a viewer renaming the list
+        let (_, alice) = user(&state, "alice");
+        let (_, bob) = user(&state, "bob");
+        let req = TestRequest::post().uri("/lists").insert_header(alice.clone()).set_json(TodoList { name: "groceries".to_string() }).to_request();
+        let list: TodoListEntry = call_and_read_body_json(&app, req).await;
+        let share = ListShare { username: "bob".to_string(), role: ListRole::Viewer };
+        let req = TestRequest::put().uri(&format!("/lists/{}/shares", list.id)).insert_header(alice).set_json(share).to_request();
+        assert!(call_service(&app, req).await.status().is_success());
+        let req = TestRequest::put().uri(&format!("/lists/{}", list.id)).insert_header(bob).set_json(TodoList { name: "mine".to_string() }).to_request();
+        error(call_service(&app, req).await, ErrorCode::Forbidden).await;
+
+        // 404, for a todo and for a route
+        error(call_service(&app, TestRequest::get().uri("/view/7").to_request()).await, ErrorCode::NotFound).await;
+        error(call_service(&app, TestRequest::get().uri("/nowhere").to_request()).await, ErrorCode::NotFound).await;
+
+        // 409, deleting a todo that still has subtasks
+        let req = TestRequest::post().uri("/insert").set_json(&item).to_request();
+        let InsertResponse { id: parent } = call_and_read_body_json(&app, req).await;
+        let req = TestRequest::post().uri("/insert").set_json(TodoItem { parent_id: Some(parent), ..item.clone() }).to_request();
+        call_service(&app, req).await;
+        let req = TestRequest::delete().uri(&format!("/delete/{}", parent)).to_request();
+        error(call_service(&app, req).await, ErrorCode::Conflict).await;
+
+        // 412
+        let req = TestRequest::delete().uri(&format!("/delete/{}", parent)).insert_header((IF_MATCH, "\"7\"")).to_request();
+        error(call_service(&app, req).await, ErrorCode::PreconditionFailed).await;
+
+        // 500, without giving away where the data lives
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        let req = TestRequest::post().uri("/insert").set_json(&item).to_request();
+        let err = error(call_service(&app, req).await, ErrorCode::Storage).await;
+        assert!(!err.message.contains(&dir.path().display().to_string()));
+    }
+}
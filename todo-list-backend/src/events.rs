@@ -0,0 +1,123 @@
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::{HttpResponse, get, http::header, rt::time::timeout, web};
+use futures_util::stream;
+use todo_list_common::TodoEvent;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use crate::auth::Caller;
+use crate::lists;
+use crate::state::AppState;
+use crate::storage::{Change, OwnedStore};
+
+// Proxies tend to close connections that stay quiet for a minute, a comment now and then keeps it open
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// One client of `/events` or `/sync`, getting the changes to the todos its user can see.
+pub struct Subscriber {
+    state: web::Data<AppState>,
+    user_id: Option<u32>,
+    changes: Receiver<Change>,
+}
+
+impl Subscriber {
+    /// Only changes made from now on are heard of.
+    pub fn new(state: web::Data<AppState>, user_id: Option<u32>) -> Self {
+        Subscriber { changes: state.changes.subscribe(), state, user_id }
+    }
+
+    // Lists may be shared or unshared while the stream is open, so what the user can see is
+    // worked out again for every change
+    fn event_for(&self, change: Change) -> Option<TodoEvent> {
+        let store = OwnedStore::new(self.state.store.clone(), self.user_id, lists::shared_with(&self.state.lists, self.user_id));
+        let before = change.before.filter(|item| store.can_read(item));
+        let after = change.after.filter(|item| store.can_read(item));
+        let id = change.id;
+        match (before, after) {
+            (None, Some(item)) => Some(TodoEvent::Created { id, item }),
+            (Some(_), Some(item)) => Some(TodoEvent::Updated { id, item }),
+            (Some(_), None) => Some(TodoEvent::Deleted { id }),
+            (None, None) => None,
+        }
+    }
+
+    /// The next change the user can see, `None` once the backend shuts down.
+    pub async fn next_event(&mut self) -> Option<TodoEvent> {
+        loop {
+            match self.changes.recv().await {
+                Ok(change) => {
+                    if let Some(event) = self.event_for(change) {
+                        return Some(event);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => return Some(TodoEvent::Resync),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn next_message(&mut self) -> Option<String> {
+        let event = match timeout(KEEP_ALIVE, self.next_event()).await {
+            Err(_) => return Some(": keep-alive\n\n".to_string()),
+            Ok(event) => event?,
+        };
+        let data = serde_json::to_string(&event).expect("events serialize to JSON");
+        Some(format!("event: {}\ndata: {}\n\n", event.name(), data))
+    }
+}
+
+// Server-Sent Events, one for every todo the caller can see being created, updated or deleted,
+// whichever client or handler made the change. Only changes made after connecting are sent.
+#[get("/events")]
+async fn events(state: web::Data<AppState>, caller: Caller) -> HttpResponse {
+    let subscriber = Subscriber::new(state, caller.user_id);
+    let messages = stream::unfold(subscriber, |mut subscriber| async move {
+        let message = subscriber.next_message().await?;
+        Some((Ok::<_, Infallible>(web::Bytes::from(message)), subscriber))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use todo_list_common::TodoItem;
+
+    use super::*;
+    use crate::auth::Auth;
+    use crate::storage::{History, JsonFileStore};
+    use crate::webhooks::Webhooks;
+
+    #[test]
+    fn subscribers_only_hear_about_their_todos() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(JsonFileStore::open(dir.path().join("todo_list.json")).unwrap());
+        let history = History::open(dir.path().join("history.jsonl")).unwrap();
+        let lists = lists::open_lists(dir.path()).unwrap();
+        let auth = Auth::open(dir.path(), false).unwrap();
+        let state = web::Data::new(AppState::new(store, history, lists, auth, Webhooks::open(dir.path(), false).unwrap()));
+        let subscriber = |user_id| Subscriber::new(state.clone(), user_id);
+        let (mut alice, mut bob) = (subscriber(Some(0)), subscriber(Some(1)));
+
+        let todo = TodoItem { title: "task".to_string(), owner_id: Some(0), ..Default::default() };
+        let id = state.store.insert(todo.clone()).unwrap();
+        state.store.update(id, TodoItem { owner_id: Some(1), ..todo }).unwrap();
+        state.store.delete(id).unwrap();
+
+        let heard = |subscriber: &mut Subscriber| {
+            let mut names = Vec::new();
+            while let Ok(change) = subscriber.changes.try_recv() {
+                names.extend(subscriber.event_for(change).map(|event| event.name()));
+            }
+            names
+        };
+        // Handing the todo over to Bob is deleting it, as far as Alice can tell
+        assert_eq!(heard(&mut alice), vec!["created", "deleted"]);
+        assert_eq!(heard(&mut bob), vec!["created", "deleted"]);
+    }
+}
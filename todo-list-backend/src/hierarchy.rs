@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use actix_web::{HttpResponse, get, web};
+use todo_list_common::{ApiError, TodoEntry, TodoItem};
+
+use crate::auth::Caller;
+use crate::error::AppError;
+use crate::not_found;
+use crate::storage::TodoView;
+
+/// Makes sure `parent_id` is one of the todos in `store` and isn't `id` itself or one of its
+/// subtasks, which would turn the tree into a loop. `id` is `None` for a todo that's still being
+/// inserted. The loop may well go through todos the caller can't see, so the ancestors are
+/// followed in `everyone`, the store that holds all of them.
+pub fn check_parent(store: &dyn TodoView, everyone: &dyn TodoView, id: Option<u32>, parent_id: Option<u32>) -> Result<(), AppError> {
+    if let Some(parent_id) = parent_id
+        && store.get(parent_id)?.is_none()
+    {
+        return Err(ApiError::validation(format!("Parent todo item {} doesn't exist", parent_id)).into());
+    }
+
+    let mut ancestor = parent_id;
+    let mut seen = BTreeSet::new();
+    while let Some(ancestor_id) = ancestor {
+        if Some(ancestor_id) == id {
+            return Err(ApiError::conflict(format!(
+                "Todo item {} can't be moved under {}, it would become its own ancestor",
+                ancestor_id,
+                parent_id.unwrap_or(ancestor_id)
+            ))
+            .into());
+        }
+        // Only a loop that is already stored could get us here twice, stop rather than spin
+        if !seen.insert(ancestor_id) {
+            break;
+        }
+        ancestor = everyone.get(ancestor_id)?.and_then(|item| item.parent_id);
+    }
+    Ok(())
+}
+
+/// The direct subtasks of `id`, in id order.
+pub fn children_of(todos: &BTreeMap<u32, TodoItem>, id: u32) -> Vec<u32> {
+    todos
+        .iter()
+        .filter(|(_, item)| item.parent_id == Some(id))
+        .map(|(child_id, _)| *child_id)
+        .collect()
+}
+
+/// Every subtask below `id`, children before their own children.
+pub fn descendants_of(todos: &BTreeMap<u32, TodoItem>, id: u32) -> Vec<u32> {
+    let mut descendants = children_of(todos, id);
+    let mut next = 0;
+    while let Some(&child_id) = descendants.get(next) {
+        for grandchild_id in children_of(todos, child_id) {
+            if grandchild_id != id && !descendants.contains(&grandchild_id) {
+                descendants.push(grandchild_id);
+            }
+        }
+        next += 1;
+    }
+    descendants
+}
+
+#[get("/todos/{id}/children")]
+async fn list_children(
+    caller: Caller,
+    id: web::Path<u32>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let mut todos = caller.store.list()?;
+    if !todos.contains_key(&id) {
+        return Err(not_found(id));
+    }
+
+    let children: Vec<TodoEntry> = children_of(&todos, id)
+        .into_iter()
+        .filter_map(|child_id| todos.remove_entry(&child_id))
+        .map(|(id, item)| TodoEntry { id, item })
+        .collect();
+    Ok(HttpResponse::Ok().json(children))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::{JsonFileStore, OwnedStore, TodoStore};
+
+    use super::*;
+
+    fn child_of(parent_id: Option<u32>) -> TodoItem {
+        TodoItem { title: "task".to_string(), parent_id, ..Default::default() }
+    }
+
+    #[test]
+    fn refuses_loops_and_missing_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonFileStore::open(dir.path().join("todo_list.json")).unwrap();
+        let root = store.insert(child_of(None)).unwrap();
+        let child = store.insert(child_of(Some(root))).unwrap();
+        let grandchild = store.insert(child_of(Some(child))).unwrap();
+
+        assert!(check_parent(&store, &store, None, Some(grandchild)).is_ok());
+        assert!(check_parent(&store, &store, Some(grandchild), Some(root)).is_ok());
+        assert!(check_parent(&store, &store, Some(root), Some(root)).is_err());
+        assert!(check_parent(&store, &store, Some(root), Some(grandchild)).is_err());
+        assert!(check_parent(&store, &store, None, Some(42)).is_err());
+
+        let todos = store.list().unwrap();
+        assert_eq!(children_of(&todos, root), [child]);
+        assert_eq!(descendants_of(&todos, root), [child, grandchild]);
+
+        // A loop through someone else's todo is still a loop, though that todo can't be a parent
+        store.update(child, TodoItem { owner_id: Some(1), ..child_of(Some(root)) }).unwrap();
+        let store: Arc<dyn TodoStore> = Arc::new(store);
+        let visible = OwnedStore::new(store.clone(), None, BTreeMap::new());
+        assert!(check_parent(&visible, store.as_ref(), Some(root), Some(grandchild)).is_err());
+        assert!(check_parent(&visible, store.as_ref(), None, Some(child)).is_err());
+        assert!(check_parent(&visible, store.as_ref(), None, Some(grandchild)).is_ok());
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use actix_web::http::header::IfMatch;
+use actix_web::{HttpResponse, get, post, web};
+use serde_json::Value;
+use todo_list_common::{ApiError, FieldChange, HistoryAction, HistoryEntry, RevertQuery, TodoEntry, TodoItem, UndoQuery, UndoResponse};
+
+use crate::auth::{Auth, Caller};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::storage::{Record, StoreError, TodoStore, TodoView};
+use crate::{check_if_match, check_references, check_writable, dependencies, entry_response, hierarchy, not_found, stamp, validate_item};
+
+// Bumped by every change (or every new step) anyway, they'd only clutter the diff
+const BOOKKEEPING_FIELDS: [&str; 3] = ["revision", "updated_at", "next_step_id"];
+
+/// The fields that differ between two versions of a todo, by name.
+pub fn diff(before: &TodoItem, after: &TodoItem) -> Vec<FieldChange> {
+    let to_object = |item: &TodoItem| match serde_json::to_value(item) {
+        Ok(Value::Object(fields)) => fields,
+        _ => unreachable!("todos serialize to JSON objects"),
+    };
+    let (before, mut after) = (to_object(before), to_object(after));
+    let mut changes: Vec<FieldChange> = before
+        .into_iter()
+        .map(|(field, before)| {
+            let after = after.remove(&field).unwrap_or(Value::Null);
+            FieldChange { field, before, after }
+        })
+        .collect();
+    // Fields left out when empty may only be there after the change
+    changes.extend(after.into_iter().map(|(field, after)| FieldChange { field, before: Value::Null, after }));
+    changes.retain(|change| change.before != change.after && !BOOKKEEPING_FIELDS.contains(&change.field.as_str()));
+    changes
+}
+
+fn entry(auth: &Auth, record: Record) -> HistoryEntry {
+    let (action, changes, item) = match (record.before, record.after) {
+        (Some(before), Some(after)) => (HistoryAction::Updated, diff(&before, &after), after),
+        (None, Some(after)) => (HistoryAction::Created, Vec::new(), after),
+        (Some(before), None) => (HistoryAction::Deleted, Vec::new(), before),
+        (None, None) => unreachable!("a record is of a todo that exists on at least one side"),
+    };
+    HistoryEntry {
+        action,
+        revision: item.revision,
+        at: record.at,
+        actor_id: record.actor,
+        actor: record.actor.and_then(|user_id| auth.username(user_id)),
+        changes,
+        undo: record.undoes.is_some(),
+        item,
+    }
+}
+
+// The todo as last recorded, deleted or not, as long as the caller can see it
+fn latest<'a>(caller: &Caller, id: u32, records: &'a [Record]) -> Result<&'a TodoItem, AppError> {
+    records
+        .last()
+        .and_then(|record| record.after.as_ref().or(record.before.as_ref()))
+        .filter(|item| caller.store.can_read(item))
+        .ok_or_else(|| not_found(id))
+}
+
+// Every change made to the todo, by anyone, including its deletion. Whoever can see the todo,
+// or could see it when it was deleted, can see all of it.
+#[get("/todos/{id}/history")]
+async fn todo_history(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let records = state.history.of(id)?;
+    latest(&caller, id, &records)?;
+
+    let entries: Vec<HistoryEntry> = records.into_iter().map(|record| entry(&state.auth, record)).collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+// Puts the todo back the way it was at a revision, bringing it back if it was deleted. What it
+// pointed to then may be gone by now, so it's checked like any other update, but nothing is set
+// off: reverting to a completed revision doesn't add the next occurrence of a repeating todo.
+// If-Match is checked against the latest revision, the one it was deleted at if it was.
+#[post("/todos/{id}/revert")]
+async fn revert_todo(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    if_match: Option<web::Header<IfMatch>>,
+    query: web::Query<RevertQuery>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let _guard = state.write();
+    let records = state.history.of(id)?;
+    let latest = latest(&caller, id, &records)?;
+    check_writable(&caller, latest)?;
+    check_if_match(if_match.as_deref(), id, Some(latest))?;
+    // Revisions keep counting up when a deleted todo is brought back, but ones recorded before
+    // that may repeat, the latest is the one the client has most likely seen
+    let target = records
+        .iter()
+        .rev()
+        .filter_map(|record| record.after.as_ref())
+        .find(|item| item.revision == query.to)
+        .ok_or_else(|| ApiError::not_found(format!("Todo item {} has no revision {}", id, query.to)))?;
+
+    let mut item = target.clone();
+    validate_item(&item)?;
+    check_references(&state, &caller, &caller.store, state.store.as_ref(), Some(id), &item)?;
+    stamp(&mut item, Some(latest))?;
+    caller.store.update(id, item)?;
+
+    let item = caller.store.get(id)?.ok_or_else(|| not_found(id))?;
+    Ok(entry_response(id, item))
+}
+
+// What one operation did to one todo: how it was before its first change, and after its last
+struct Touched {
+    before: Option<TodoItem>,
+    after: Option<TodoItem>,
+}
+
+impl Touched {
+    fn latest(&self) -> &TodoItem {
+        self.after.as_ref().or(self.before.as_ref()).expect("a record is of a todo that exists on at least one side")
+    }
+}
+
+// Everyone's todos, or the caller's, as they'd be once the undo is done: the todos it puts
+// back are there again and the ones it deletes are gone. The undo is checked against this view
+// before any of it is stored, so it happens either completely or not at all.
+struct Undone<'a> {
+    store: &'a dyn TodoView,
+    changes: &'a BTreeMap<u32, Option<TodoItem>>,
+}
+
+impl TodoView for Undone<'_> {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        match self.changes.get(&id) {
+            Some(change) => Ok(change.clone()),
+            None => self.store.get(id),
+        }
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        let mut todos = self.store.list()?;
+        for (id, change) in self.changes {
+            match change {
+                Some(item) => todos.insert(*id, item.clone()),
+                None => todos.remove(id),
+            };
+        }
+        Ok(todos)
+    }
+}
+
+// Whether nothing happened to a todo since `operation` but `actor` undoing what they did after
+fn unchanged_since(records: &[Record], actor: Option<u32>, operation: u64) -> bool {
+    let mut undone = BTreeSet::new();
+    for record in records.iter().rev() {
+        match (record.operation, record.undoes) {
+            (Some(later), _) if later == operation => return true,
+            (_, Some(undoes)) if record.actor == actor => {
+                undone.insert(undoes);
+            }
+            (Some(later), None) if undone.contains(&later) => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+fn cant_undo(id: u32, err: AppError) -> AppError {
+    let message = format!("Todo item {} can't be put back the way it was: {}", id, err.0.message);
+    ApiError::conflict(message).into()
+}
+
+// Takes back the caller's last change, whatever endpoint made it, or the one named by
+// `operation`: every todo it changed or deleted is put back the way it was before, as its next
+// revision, and the ones it created are deleted. Undoing again goes further back. Nothing is
+// undone should someone have changed one of the todos since, or should the todos put back no
+// longer pass the checks of any other change, e.g. because their parent or list was deleted in
+// the meantime.
+#[post("/undo")]
+async fn undo(state: web::Data<AppState>, caller: Caller, query: web::Query<UndoQuery>) -> Result<HttpResponse, AppError> {
+    let _guard = state.write();
+    let Some(operation) = state.history.undoable(caller.user_id, query.operation) else {
+        return Err(match query.operation {
+            Some(operation) => ApiError::not_found(format!("Operation {} isn't one of yours that can still be undone", operation)),
+            None => ApiError::not_found("There is nothing to undo"),
+        }
+        .into());
+    };
+
+    let mut touched: BTreeMap<u32, Touched> = BTreeMap::new();
+    for record in state.history.operation(caller.user_id, operation)? {
+        match touched.get_mut(&record.todo_id) {
+            Some(touched) => touched.after = record.after,
+            None => {
+                touched.insert(record.todo_id, Touched { before: record.before, after: record.after });
+            }
+        }
+    }
+
+    let todos = caller.everyone.list()?;
+    let mut changes: BTreeMap<u32, Option<TodoItem>> = BTreeMap::new();
+    for (id, change) in &touched {
+        if !unchanged_since(&state.history.of(*id)?, caller.user_id, operation) {
+            let message = format!("Todo item {} was changed since, revert it to the revision you want instead", id);
+            return Err(ApiError::conflict(message).into());
+        }
+        for item in [change.before.as_ref(), Some(change.latest())].into_iter().flatten() {
+            if !caller.store.can_read(item) {
+                return Err(not_found(*id));
+            }
+            check_writable(&caller, item)?;
+        }
+        let item = match &change.before {
+            Some(before) => {
+                let mut item = before.clone();
+                stamp(&mut item, Some(todos.get(id).unwrap_or(change.latest())))?;
+                Some(item)
+            }
+            None => None,
+        };
+        changes.insert(*id, item);
+    }
+
+    let visible = Undone { store: &caller.store, changes: &changes };
+    let everyone = Undone { store: caller.everyone.as_ref(), changes: &changes };
+    let undone = everyone.list()?;
+    for (id, change) in &changes {
+        match change {
+            Some(item) => {
+                validate_item(item).map_err(|err| cant_undo(*id, err))?;
+                check_references(&state, &caller, &visible, &everyone, Some(*id), item).map_err(|err| cant_undo(*id, err))?;
+            }
+            // Subtasks added since would be left without their parent
+            None => {
+                if let Some(child_id) = hierarchy::children_of(&undone, *id).first() {
+                    let message = format!("Todo item {} has a subtask ({}) now, delete it yourself to choose what happens to it", id, child_id);
+                    return Err(ApiError::conflict(message).into());
+                }
+            }
+        }
+    }
+
+    caller.operation.undoing(operation);
+    let mut response = UndoResponse::default();
+    for (id, change) in changes {
+        match change {
+            Some(item) => {
+                caller.everyone.update(id, item.clone())?;
+                response.restored.push(TodoEntry { id, item });
+            }
+            None => {
+                caller.everyone.delete(id)?;
+                response.deleted.push(id);
+            }
+        }
+    }
+    // Like for a delete, the todos waiting on the ones deleted stop waiting
+    dependencies::forget_blockers(caller.everyone.as_ref(), &caller.everyone.list()?, &response.deleted)?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, init_service, read_body_json};
+    use todo_list_common::{InsertResponse, OPERATION_HEADER, TodoList, TodoListEntry, TodoPatch};
+
+    use super::*;
+    use crate::testing::{app, state};
+
+    fn operation<B>(res: &ServiceResponse<B>) -> u64 {
+        res.headers().get(OPERATION_HEADER).unwrap().to_str().unwrap().parse().unwrap()
+    }
+
+    // The id of the todo `POST /insert` answered with, and the operation it was recorded as
+    async fn created<B: MessageBody>(res: ServiceResponse<B>) -> (u32, u64) {
+        assert!(res.status().is_success());
+        let operation = operation(&res);
+        (read_body_json::<InsertResponse, _>(res).await.id, operation)
+    }
+
+    #[test]
+    fn diff_leaves_out_what_didnt_change() {
+        let before = TodoItem { title: "task".to_string(), revision: 1, ..Default::default() };
+        let after = TodoItem { completed: true, tags: vec!["home".to_string()], revision: 2, ..before.clone() };
+
+        let fields: Vec<_> = diff(&before, &after).into_iter().map(|change| change.field).collect();
+        assert_eq!(fields, vec!["completed", "tags"]);
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn undo_refuses_to_bring_back_what_lost_its_parent_or_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let create = async |item: &TodoItem| created(call_service(&app, TestRequest::post().uri("/insert").set_json(item).to_request()).await).await;
+        let undo_operation = |operation: u64| TestRequest::post().uri(&format!("/undo?operation={}", operation)).to_request();
+
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+        let (parent, _) = create(&item).await;
+        let (child, _) = create(&TodoItem { parent_id: Some(parent), ..item.clone() }).await;
+        let req = TestRequest::post().uri("/lists").set_json(TodoList { name: "groceries".to_string() }).to_request();
+        let list: TodoListEntry = call_and_read_body_json(&app, req).await;
+        let (listed, _) = create(&TodoItem { list_id: list.id, ..item.clone() }).await;
+
+        let res = call_service(&app, TestRequest::delete().uri(&format!("/delete/{}", child)).to_request()).await;
+        let child_deleted = operation(&res);
+        let res = call_service(&app, TestRequest::delete().uri(&format!("/delete/{}", listed)).to_request()).await;
+        let listed_deleted = operation(&res);
+        call_service(&app, TestRequest::delete().uri(&format!("/delete/{}", parent)).to_request()).await;
+        call_service(&app, TestRequest::delete().uri(&format!("/lists/{}", list.id)).to_request()).await;
+
+        for operation in [child_deleted, listed_deleted] {
+            let res = call_service(&app, undo_operation(operation)).await;
+            assert_eq!(res.status(), actix_web::http::StatusCode::CONFLICT);
+        }
+        assert!(state.store.get(child).unwrap().is_none());
+        assert!(state.store.get(listed).unwrap().is_none());
+
+        // With the parent back, the subtask can follow
+        let res = call_service(&app, TestRequest::post().uri("/undo").to_request()).await;
+        assert!(res.status().is_success());
+        let res = call_service(&app, undo_operation(child_deleted)).await;
+        assert!(res.status().is_success());
+        assert_eq!(state.store.get(child).unwrap().unwrap().parent_id, Some(parent));
+    }
+
+    #[actix_web::test]
+    async fn undoing_a_create_lets_the_todos_blocked_by_it_go() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let create = async |item: &TodoItem| created(call_service(&app, TestRequest::post().uri("/insert").set_json(item).to_request()).await).await;
+
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+        let (blocker, blocker_created) = create(&item).await;
+        let (waiting, _) = create(&TodoItem { blocked_by: vec![blocker], ..item.clone() }).await;
+
+        let req = TestRequest::post().uri(&format!("/undo?operation={}", blocker_created)).to_request();
+        let undone: UndoResponse = call_and_read_body_json(&app, req).await;
+        assert_eq!(undone.deleted, vec![blocker]);
+        let waiting = state.store.get(waiting).unwrap().unwrap();
+        assert!(waiting.blocked_by.is_empty());
+        assert_eq!(waiting.revision, 2);
+        // Undone already
+        let req = TestRequest::post().uri(&format!("/undo?operation={}", blocker_created)).to_request();
+        assert_eq!(call_service(&app, req).await.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn revert_tells_apart_the_revisions_of_a_recreated_todo() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let create = async |item: &TodoItem| created(call_service(&app, TestRequest::post().uri("/insert").set_json(item).to_request()).await).await;
+
+        let item = TodoItem { title: "first".to_string(), ..Default::default() };
+        let (id, _) = create(&item).await;
+        let patch = TodoPatch { title: Some("second".to_string()), ..Default::default() };
+        call_service(&app, TestRequest::patch().uri(&format!("/todos/{}", id)).set_json(patch).to_request()).await;
+        call_service(&app, TestRequest::delete().uri(&format!("/delete/{}", id)).to_request()).await;
+        let recreated = TodoItem { title: "third".to_string(), ..item.clone() };
+        call_service(&app, TestRequest::put().uri(&format!("/update/{}", id)).set_json(recreated).to_request()).await;
+        assert_eq!(state.store.get(id).unwrap().unwrap().revision, 3);
+
+        let revert = |to: u64, if_match: &str| {
+            TestRequest::post().uri(&format!("/todos/{}/revert?to={}", id, to)).insert_header(("If-Match", if_match)).to_request()
+        };
+        let res = call_service(&app, revert(2, "\"2\"")).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::PRECONDITION_FAILED);
+        let reverted: TodoEntry = call_and_read_body_json(&app, revert(2, "\"3\"")).await;
+        assert_eq!((reverted.item.title.as_str(), reverted.item.revision), ("second", 4));
+        let reverted: TodoEntry = call_and_read_body_json(&app, revert(3, "\"4\"")).await;
+        assert_eq!(reverted.item.title, "third");
+    }
+}
@@ -0,0 +1,215 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use chrono::{DateTime, Duration, Utc};
+use todo_list_common::{ApiError, SortField, SortOrder, TodoEntry, TodoItem, TodoPage, TodoQuery};
+
+/// What an item is ordered by, for the requested sort field. Dates are compared in whole
+/// milliseconds, since that's what ends up in the cursor.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Id,
+    Time(i64),
+    Text(String),
+    // Ascending is the order to work in, so the urgency is negated to put the highest first
+    Smart { completed: bool, urgency: i64, due: i64 },
+}
+
+fn sort_key(field: SortField, item: &TodoItem, now: DateTime<Utc>) -> SortKey {
+    match field {
+        SortField::Id => SortKey::Id,
+        SortField::DueDate => SortKey::Time(item.due_date.timestamp_millis()),
+        SortField::CreatedAt => SortKey::Time(item.created_at.timestamp_millis()),
+        SortField::Title => SortKey::Text(item.title.to_lowercase()),
+        SortField::Smart => SortKey::Smart {
+            completed: item.completed,
+            urgency: -urgency(item, now),
+            due: item.due_date.timestamp_millis(),
+        },
+    }
+}
+
+// Each priority level is worth 100, and being overdue 250 on top of that. An overdue todo
+// therefore comes before one two levels more important that isn't due yet, while one due
+// in a few weeks only gets ahead through its priority.
+fn urgency(item: &TodoItem, now: DateTime<Utc>) -> i64 {
+    let until_due = item.due_date - now;
+    let due = if until_due < Duration::zero() {
+        250
+    } else if until_due < Duration::days(1) {
+        150
+    } else if until_due < Duration::days(3) {
+        100
+    } else if until_due < Duration::days(7) {
+        50
+    } else {
+        0
+    };
+    item.priority as i64 * 100 + due
+}
+
+// A cursor is the position of the last item of a page, `<id>:<sort key>`. Paging by position
+// rather than by offset means inserts and deletes between two requests don't shift the pages.
+// Titles are hex encoded, so a cursor can be pasted on a command line without quoting.
+// The smart order moves as time passes, so a todo that became overdue between two pages can
+// turn up twice or not at all.
+fn encode_cursor(key: &SortKey, id: u32) -> String {
+    match key {
+        SortKey::Id => id.to_string(),
+        SortKey::Time(millis) => format!("{}:{}", id, millis),
+        SortKey::Text(text) => {
+            let hex: String = text.bytes().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{}:{}", id, hex)
+        }
+        SortKey::Smart { completed, urgency, due } => format!("{}:{}:{}:{}", id, *completed as u8, urgency, due),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn decode_cursor(cursor: &str, field: SortField) -> Option<(SortKey, u32)> {
+    let (id, key) = match field {
+        SortField::Id => (cursor, SortKey::Id),
+        SortField::DueDate | SortField::CreatedAt => {
+            let (id, millis) = cursor.split_once(':')?;
+            (id, SortKey::Time(millis.parse().ok()?))
+        }
+        SortField::Title => {
+            let (id, hex) = cursor.split_once(':')?;
+            (id, SortKey::Text(decode_hex(hex)?))
+        }
+        SortField::Smart => {
+            let mut parts = cursor.split(':');
+            let id = parts.next()?;
+            let completed = match parts.next()? {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            };
+            let urgency = parts.next()?.parse().ok()?;
+            let due = parts.next()?.parse().ok()?;
+            if parts.next().is_some() {
+                return None;
+            }
+            (id, SortKey::Smart { completed, urgency, due })
+        }
+    };
+    Some((key, id.parse().ok()?))
+}
+
+/// Sorts the (already filtered) todos and cuts out the page the query asks for.
+pub fn page(todos: BTreeMap<u32, TodoItem>, query: &TodoQuery) -> Result<TodoPage, ApiError> {
+    let field = query.sort.unwrap_or_default();
+    let now = Utc::now();
+    let order = query.order.unwrap_or_default();
+    let compare = |a: (&SortKey, u32), b: (&SortKey, u32)| match order {
+        SortOrder::Asc => a.cmp(&b),
+        SortOrder::Desc => b.cmp(&a),
+    };
+
+    let mut entries: Vec<(SortKey, u32, TodoItem)> = todos
+        .into_iter()
+        .map(|(id, item)| (sort_key(field, &item, now), id, item))
+        .collect();
+    entries.sort_by(|a, b| compare((&a.0, a.1), (&b.0, b.1)));
+
+    if let Some(cursor) = &query.cursor {
+        let (key, id) = decode_cursor(cursor, field)
+            .ok_or_else(|| ApiError::validation("Invalid cursor, it has to come from a page with the same sort"))?;
+        entries.retain(|entry| compare((&entry.0, entry.1), (&key, id)) == Ordering::Greater);
+    }
+
+    let limit = match query.limit {
+        Some(0) => return Err(ApiError::validation("limit must be at least 1")),
+        Some(limit) => limit as usize,
+        None => usize::MAX,
+    };
+    let next_cursor = (entries.len() > limit).then(|| {
+        let (key, id, _) = &entries[limit - 1];
+        encode_cursor(key, *id)
+    });
+    entries.truncate(limit);
+
+    Ok(TodoPage {
+        items: entries.into_iter().map(|(_, id, item)| TodoEntry { id, item }).collect(),
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use todo_list_common::Priority;
+
+    use super::*;
+
+    fn todos() -> BTreeMap<u32, TodoItem> {
+        ["b", "C", "a", "d", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, title)| (id as u32, TodoItem { title: title.to_string(), ..Default::default() }))
+            .collect()
+    }
+
+    fn titles(page: &TodoPage) -> Vec<&str> {
+        page.items.iter().map(|entry| entry.item.title.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_follow_the_sort_order() {
+        let query = TodoQuery::new().sort(SortField::Title, SortOrder::Desc).limit(2);
+
+        let first = page(todos(), &query).unwrap();
+        assert_eq!(titles(&first), ["d", "C"]);
+
+        // A todo inserted in between must not shift the next page
+        let mut changed = todos();
+        changed.insert(9, TodoItem { title: "e".to_string(), ..Default::default() });
+        let second = page(changed, &query.clone().cursor(first.next_cursor.unwrap())).unwrap();
+        assert_eq!(titles(&second), ["b", "b"]);
+        assert_eq!(second.items.iter().map(|entry| entry.id).collect::<Vec<_>>(), [4, 0]);
+
+        let last = page(todos(), &query.cursor(second.next_cursor.unwrap())).unwrap();
+        assert_eq!(titles(&last), ["a"]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn smart_order() {
+        let now = Utc::now();
+        let todo = |title: &str, priority, due_in_days| TodoItem {
+            title: title.to_string(),
+            priority,
+            due_date: now + Duration::days(due_in_days),
+            ..Default::default()
+        };
+        let todos = BTreeMap::from([
+            (0, todo("someday", Priority::Medium, 30)),
+            (1, todo("overdue", Priority::Low, -1)),
+            (2, todo("important", Priority::Urgent, 30)),
+            (3, TodoItem { completed: true, ..todo("done", Priority::Urgent, -1) }),
+            (4, todo("tomorrow", Priority::Medium, 2)),
+            (5, todo("later", Priority::Medium, 20)),
+        ]);
+
+        let query = TodoQuery::new().sort(SortField::Smart, SortOrder::Asc).limit(3);
+        let first = page(todos.clone(), &query).unwrap();
+        assert_eq!(titles(&first), ["important", "overdue", "tomorrow"]);
+        let rest = page(todos, &query.cursor(first.next_cursor.unwrap())).unwrap();
+        assert_eq!(titles(&rest), ["later", "someday", "done"]);
+    }
+
+    #[test]
+    fn rejects_bad_cursors() {
+        let by_date = TodoQuery::new().sort(SortField::DueDate, SortOrder::Asc);
+        assert!(page(todos(), &by_date.clone().cursor("3")).is_err());
+        assert!(page(todos(), &by_date.cursor("3:soon")).is_err());
+        let by_title = TodoQuery::new().sort(SortField::Title, SortOrder::Asc);
+        assert!(page(todos(), &by_title.cursor("3:6")).is_err());
+        assert!(page(todos(), &TodoQuery::new().limit(0)).is_err());
+    }
+}
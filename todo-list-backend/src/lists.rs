@@ -0,0 +1,318 @@
+use std::{collections::BTreeMap, path::Path};
+
+use actix_web::{HttpResponse, delete, get, http, post, put, web};
+use serde::{Deserialize, Serialize};
+use todo_list_common::{ApiError, INBOX_ID, ListDeleteQuery, ListRole, TodoList, TodoListEntry, TodoQuery};
+
+use crate::auth::Caller;
+use crate::error::{AppError, read_only};
+use crate::state::AppState;
+use crate::storage::{JsonCollection, SharedList, StoreError, TodoStore, TodoView};
+use crate::{list_response, stamp};
+
+/// A list as it's kept in `lists.json`, along with the user it belongs to and who it's shared with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoredList {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<u32>,
+    /// Users other than the owner, as editors or viewers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shared_with: BTreeMap<u32, ListRole>,
+}
+
+impl StoredList {
+    pub fn new(name: String, owner_id: Option<u32>) -> Self {
+        StoredList { name, owner_id, shared_with: BTreeMap::new() }
+    }
+
+    /// What `user_id` may do with this list, the one at `id`, if they may see it at all.
+    /// Everyone has the Inbox, each user gets their own todos on it.
+    pub fn role_of(&self, id: u32, user_id: Option<u32>) -> Option<ListRole> {
+        if id == INBOX_ID || self.owner_id == user_id {
+            return Some(ListRole::Owner);
+        }
+        self.shared_with.get(&user_id?).copied()
+    }
+}
+
+/// Opens the named lists kept in `data_dir`, whichever storage the todos are in.
+pub fn open_lists(data_dir: &Path) -> Result<JsonCollection<StoredList>, StoreError> {
+    let lists = JsonCollection::open(data_dir.join("lists.json"))?;
+    // Todos stored before there were lists all end up on the Inbox, so it has to be there
+    if lists.get(INBOX_ID).is_none() {
+        lists.update(INBOX_ID, StoredList::new("Inbox".to_string(), None))?;
+    }
+    Ok(lists)
+}
+
+// The lists `user_id` may see, along with what they may do with them
+fn visible(lists: &JsonCollection<StoredList>, user_id: Option<u32>) -> BTreeMap<u32, (StoredList, ListRole)> {
+    lists
+        .list()
+        .into_iter()
+        .filter_map(|(id, list)| Some((id, (list.clone(), list.role_of(id, user_id)?))))
+        .collect()
+}
+
+/// The list at `id` and the caller's role on it, or 404 for a list they can't see.
+pub fn get_visible(lists: &JsonCollection<StoredList>, user_id: Option<u32>, id: u32) -> Result<(StoredList, ListRole), AppError> {
+    lists
+        .get(id)
+        .and_then(|list| Some((list.clone(), list.role_of(id, user_id)?)))
+        .ok_or_else(|| list_not_found(id))
+}
+
+/// The lists of other users shared with `user_id`, for `OwnedStore`.
+pub fn shared_with(lists: &JsonCollection<StoredList>, user_id: Option<u32>) -> BTreeMap<u32, SharedList> {
+    let Some(user_id) = user_id else {
+        return BTreeMap::new();
+    };
+    lists
+        .list()
+        .into_iter()
+        .filter_map(|(id, list)| {
+            let role = list.shared_with.get(&user_id)?;
+            Some((id, SharedList { owner_id: list.owner_id, writable: *role == ListRole::Editor }))
+        })
+        .collect()
+}
+
+/// Makes sure a todo is put on a list that exists, and that the caller may add todos to.
+pub fn check_list(lists: &JsonCollection<StoredList>, user_id: Option<u32>, list_id: u32) -> Result<(), AppError> {
+    match lists.get(list_id).and_then(|list| list.role_of(list_id, user_id)) {
+        Some(ListRole::Owner | ListRole::Editor) => Ok(()),
+        Some(ListRole::Viewer) => Err(read_only(list_id).into()),
+        None => Err(ApiError::validation(format!("List {} doesn't exist", list_id)).into()),
+    }
+}
+
+/// Todos are assigned to someone who can see them: the owner of their list, or one of the
+/// users it's shared with. On the Inbox, that's only the caller.
+pub fn check_assignee(
+    lists: &JsonCollection<StoredList>,
+    user_id: Option<u32>,
+    list_id: u32,
+    assigned_to: Option<u32>,
+) -> Result<(), AppError> {
+    let Some(assignee) = assigned_to else {
+        return Ok(());
+    };
+    let member = match lists.get(list_id) {
+        _ if list_id == INBOX_ID => user_id == Some(assignee),
+        Some(list) => list.owner_id == Some(assignee) || list.shared_with.contains_key(&assignee),
+        None => false,
+    };
+    if !member {
+        return Err(ApiError::validation(format!("User {} isn't a member of list {}", assignee, list_id)).into());
+    }
+    Ok(())
+}
+
+/// Renaming, deleting and sharing a list is up to its owner.
+pub fn check_owner(role: ListRole, id: u32) -> Result<(), AppError> {
+    match role {
+        ListRole::Owner => Ok(()),
+        _ => Err(ApiError::forbidden(format!("Only the owner of list {} can do that", id)).into()),
+    }
+}
+
+fn list_not_found(id: u32) -> AppError {
+    ApiError::not_found(format!("List with id {} not found", id)).into()
+}
+
+// Names are trimmed, and have to be unique regardless of case so "work" and "Work" can't both exist.
+// Only among the caller's own lists though, other users may well have a "Work" of their own.
+fn check_name(
+    lists: &JsonCollection<StoredList>,
+    owner_id: Option<u32>,
+    id: Option<u32>,
+    list: &TodoList,
+) -> Result<String, AppError> {
+    let name = list.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiError::validation("List name must not be empty").into());
+    }
+    let taken = visible(lists, owner_id).into_iter().any(|(other_id, (other, role))| {
+        role == ListRole::Owner && Some(other_id) != id && other.name.to_lowercase() == name.to_lowercase()
+    });
+    if taken {
+        return Err(ApiError::conflict(format!("There already is a list called {}", name)).into());
+    }
+    Ok(name)
+}
+
+fn counts(store: &dyn TodoView) -> Result<BTreeMap<u32, u32>, AppError> {
+    let mut counts = BTreeMap::new();
+    for item in store.list()?.into_values() {
+        *counts.entry(item.list_id).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+fn entry(id: u32, list: StoredList, role: ListRole, count: u32) -> TodoListEntry {
+    TodoListEntry { id, name: list.name, count, role }
+}
+
+#[get("/lists")]
+async fn get_lists(state: web::Data<AppState>, caller: Caller) -> Result<HttpResponse, AppError> {
+    let counts = counts(&caller.store)?;
+    let lists: Vec<TodoListEntry> = visible(&state.lists, caller.user_id)
+        .into_iter()
+        .map(|(id, (list, role))| entry(id, list, role, counts.get(&id).copied().unwrap_or(0)))
+        .collect();
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[post("/lists")]
+async fn create_list(
+    state: web::Data<AppState>,
+    caller: Caller,
+    list: web::Json<TodoList>,
+) -> Result<HttpResponse, AppError> {
+    let _guard = state.write();
+    let list = StoredList::new(check_name(&state.lists, caller.user_id, None, &list)?, caller.user_id);
+    let id = state.lists.insert(list.clone())?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((http::header::LOCATION, format!("/lists/{}", id)))
+        .json(entry(id, list, ListRole::Owner, 0)))
+}
+
+#[get("/lists/{id}")]
+async fn get_list(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let (list, role) = get_visible(&state.lists, caller.user_id, id)?;
+    let count = counts(&caller.store)?.get(&id).copied().unwrap_or(0);
+    Ok(HttpResponse::Ok().json(entry(id, list, role, count)))
+}
+
+// Renames the list, its todos stay where they are
+#[put("/lists/{id}")]
+async fn rename_list(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    list: web::Json<TodoList>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let _guard = state.write();
+    let (current, role) = get_visible(&state.lists, caller.user_id, id)?;
+    // The Inbox is everyone's, so renaming it would rename it for everyone
+    if id == INBOX_ID && caller.user_id.is_some() {
+        return Err(ApiError::conflict("The Inbox can't be renamed").into());
+    }
+    check_owner(role, id)?;
+    let list = StoredList { name: check_name(&state.lists, caller.user_id, Some(id), &list)?, ..current };
+    state.lists.update(id, list.clone())?;
+
+    let count = counts(&caller.store)?.get(&id).copied().unwrap_or(0);
+    Ok(HttpResponse::Ok().json(entry(id, list, role, count)))
+}
+
+// Like deleting a todo with subtasks, deleting a list that still has todos has to say where
+// they go. Todos are never deleted along with their list.
+#[delete("/lists/{id}")]
+async fn delete_list(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    query: web::Query<ListDeleteQuery>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    if id == INBOX_ID {
+        return Err(ApiError::conflict("The Inbox can't be deleted").into());
+    }
+
+    let _guard = state.write();
+    let (_, role) = get_visible(&state.lists, caller.user_id, id)?;
+    check_owner(role, id)?;
+    let todos = caller.store.query(&TodoQuery::new().list(id))?;
+    match query.move_to {
+        None if !todos.is_empty() => {
+            return Err(ApiError::conflict(format!(
+                "List {} still has {} todos, pass move_to=<list id> to move them",
+                id,
+                todos.len()
+            ))
+            .into());
+        }
+        None => {}
+        Some(move_to) if move_to == id => {
+            return Err(ApiError::validation("Todos can't be moved to the list that's being deleted").into());
+        }
+        Some(move_to) => {
+            check_list(&state.lists, caller.user_id, move_to)?;
+            for (todo_id, current) in todos {
+                let mut item = current.clone();
+                item.list_id = move_to;
+                stamp(&mut item, Some(&current))?;
+                caller.store.update(todo_id, item)?;
+            }
+        }
+    }
+    state.lists.delete(id)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// The same as `GET /list?list={id}`, but answers 404 for a list that doesn't exist
+#[get("/lists/{id}/todos")]
+async fn list_todos(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    query: web::Query<TodoQuery>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    get_visible(&state.lists, caller.user_id, id)?;
+    list_response(&caller.store, &query.into_inner().list(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let lists = open_lists(dir.path()).unwrap();
+        let work = lists.insert(StoredList::new("Work".to_string(), None)).unwrap();
+        let named = |name: &str| TodoList { name: name.to_string() };
+
+        assert_eq!(lists.get(INBOX_ID).unwrap().name, "Inbox");
+        assert_eq!(check_name(&lists, None, None, &named("  Home ")).unwrap(), "Home");
+        assert!(check_name(&lists, None, None, &named("work")).is_err());
+        assert!(check_name(&lists, None, None, &named(" ")).is_err());
+        // Renaming a list to itself, in another case, is fine
+        assert!(check_name(&lists, None, Some(work), &named("WORK")).is_ok());
+        assert!(check_list(&lists, None, work).is_ok());
+        assert!(check_list(&lists, None, 42).is_err());
+
+        // Another user can have a "Work" too, and can't put todos on this one
+        assert!(check_name(&lists, Some(1), None, &named("work")).is_ok());
+        assert!(check_list(&lists, Some(1), work).is_err());
+        assert!(check_list(&lists, Some(1), INBOX_ID).is_ok());
+    }
+
+    #[test]
+    fn shared_lists_go_by_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let lists = open_lists(dir.path()).unwrap();
+        let mut work = StoredList::new("Work".to_string(), Some(1));
+        work.shared_with = BTreeMap::from([(2, ListRole::Editor), (3, ListRole::Viewer)]);
+        let id = lists.insert(work.clone()).unwrap();
+
+        assert_eq!(work.role_of(id, Some(1)), Some(ListRole::Owner));
+        assert_eq!(work.role_of(id, Some(4)), None);
+        assert!(check_list(&lists, Some(2), id).is_ok());
+        assert!(check_list(&lists, Some(3), id).is_err());
+        assert_eq!(shared_with(&lists, Some(3))[&id], SharedList { owner_id: Some(1), writable: false });
+        assert!(shared_with(&lists, Some(1)).is_empty());
+        // A list shared with someone doesn't keep them from having one of the same name
+        assert!(check_name(&lists, Some(2), None, &TodoList { name: "work".to_string() }).is_ok());
+        assert!(check_assignee(&lists, Some(2), id, Some(3)).is_ok());
+        assert!(check_assignee(&lists, Some(2), id, Some(4)).is_err());
+        assert!(check_assignee(&lists, Some(2), INBOX_ID, Some(1)).is_err());
+    }
+}
@@ -1,161 +1,831 @@
-use std::{collections::BTreeMap, fs::{self, File}, io::Write, path::Path, sync::Mutex};
+mod api_tokens;
+mod atomic_file;
+mod auth;
+mod checklist;
+mod config;
+mod dependencies;
+mod error;
+mod events;
+mod hierarchy;
+mod history;
+mod listing;
+mod lists;
+mod sharing;
+mod state;
+mod storage;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod webhooks;
+
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
 use actix_cors::Cors;
-use actix_web::{App, HttpResponse, HttpServer, Responder, delete, get, http, post, put, web};
-use todo_list_common::{TodoItem};
+use actix_web::http::header::{ETag, EntityTag, IfMatch};
+use actix_web::middleware::from_fn;
+use actix_web::{App, HttpResponse, HttpServer, delete, get, http, patch, post, put, web};
+use chrono::Utc;
+use clap::Parser;
+use todo_list_common::{
+    ApiError, ChildrenOnDelete, DeleteQuery, InsertResponse, Recurrence, TagCount, TagQuery, TagRename, TagRenameResponse, TodoEntry, TodoItem, TodoPatch,
+    TodoQuery, UpdateResponse, normalize_tag,
+};
+
+use crate::auth::Caller;
+use crate::config::{Args, Config, CorsConfig};
+use crate::error::{AppError, json_error_handler, read_only, path_error_handler, query_error_handler, route_not_found};
+use crate::state::AppState;
+use crate::storage::{History, TodoStore, TodoView, open_store};
+
+fn validate_item(item: &TodoItem) -> Result<(), AppError> {
+    if item.title.trim().is_empty() {
+        return Err(ApiError::validation("Title must not be empty").into());
+    }
+    if item.checklist.iter().any(|entry| entry.text.trim().is_empty()) {
+        return Err(ApiError::validation("Checklist items must not be empty").into());
+    }
+    if let Some(recurrence) = &item.recurrence {
+        recurrence.check().map_err(|err| ApiError::validation(format!("Invalid recurrence: {}", err)))?;
+    }
+    Ok(())
+}
 
-async fn initial_loading_from_disk() -> BTreeMap<u32, TodoItem> {
-    let storage_path = "./.storage/todo_list.json";
+fn not_found(id: u32) -> AppError {
+    ApiError::not_found(format!("Todo item with id {} not found", id)).into()
+}
 
-    // Ensure the directory exists
-    if let Some(parent) = Path::new(storage_path).parent() {
-        fs::create_dir_all(parent).unwrap();
+// A todo on a list shared with the caller as a viewer is there to look at only
+fn check_writable(caller: &Caller, item: &TodoItem) -> Result<(), AppError> {
+    match caller.store.can_write(item) {
+        true => Ok(()),
+        false => Err(read_only(item.list_id).into()),
     }
+}
 
-    // If file does not exist, create it with empty JSON object
-    if !Path::new(storage_path).exists() {
-        let mut file = File::create(storage_path).unwrap();
-        file.write_all(b"{}").unwrap();
-        println!("Created empty todo list at {}", storage_path);
+fn etag(item: &TodoItem) -> ETag {
+    ETag(EntityTag::new_strong(item.revision.to_string()))
+}
+
+// A request without If-Match goes through as before, last write wins. With one, the item must
+// still be at a revision the client names, otherwise it gets 412 along with the current item
+// so it can show the user what changed.
+fn check_if_match(if_match: Option<&IfMatch>, id: u32, current: Option<&TodoItem>) -> Result<(), AppError> {
+    let matches = match (if_match, current) {
+        // actix hands us an empty list when the header is missing altogether
+        (None, _) => true,
+        (Some(IfMatch::Items(tags)), _) if tags.is_empty() => true,
+        (Some(IfMatch::Any), current) => current.is_some(),
+        (Some(IfMatch::Items(tags)), Some(item)) => tags.iter().any(|tag| tag.strong_eq(&etag(item).0)),
+        (Some(IfMatch::Items(_)), None) => false,
+    };
+    if matches {
+        return Ok(());
     }
 
-    // Open file and deserialize into BTreeMap
-    let file = File::open(storage_path).unwrap();
-    let todo_map: BTreeMap<String, TodoItem> =
-        serde_json::from_reader(file).unwrap_or_default();
+    let err = ApiError::precondition_failed(format!("Todo item with id {} was changed by someone else", id));
+    Err(match current {
+        Some(item) => err.with_details(serde_json::json!(TodoEntry { id, item: item.clone() })),
+        None => err,
+    }
+    .into())
+}
 
-    // Convert string keys to u32
-    let mut result = BTreeMap::new();
-    for (key_str, value) in todo_map {
-        if let Ok(key) = key_str.parse::<u32>() {
-            result.insert(key, value);
+// Revisions and timestamps belong to the backend, so whatever the client sent for them is
+// replaced here, right before `item` takes the place of `current`. Tags are also brought into
+// the form they're stored in, as are the blockers.
+fn stamp(item: &mut TodoItem, current: Option<&TodoItem>) -> Result<(), AppError> {
+    let mut tags = Vec::new();
+    for tag in item.tags.iter().map(|tag| normalize_tag(tag)) {
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
         }
     }
+    item.tags = tags;
+    item.blocked_by.sort_unstable();
+    item.blocked_by.dedup();
+
+    // Step ids come from a counter kept with the todo. Only ids it already handed out are kept,
+    // new steps come with 0, but a client could also make one up or send the same one twice.
+    let handed_out = current.map_or(1, step_counter);
+    let mut next_step_id = handed_out;
+    let mut seen = Vec::new();
+    for entry in &mut item.checklist {
+        if entry.id == 0 || entry.id >= handed_out || seen.contains(&entry.id) {
+            entry.id = next_step_id;
+            next_step_id = next_step_id
+                .checked_add(1)
+                .ok_or_else(|| ApiError::conflict("The checklist has run out of ids for new steps"))?;
+        }
+        seen.push(entry.id);
+    }
+    item.next_step_id = next_step_id;
+
+    let now = Utc::now();
+    item.revision = current.map_or(1, |current| current.revision + 1);
+    item.created_at = current.map_or(now, |current| current.created_at);
+    item.updated_at = now;
+    item.completed_at = match current {
+        _ if !item.completed => None,
+        Some(current) if current.completed => current.completed_at.or(Some(now)),
+        _ => Some(now),
+    };
+    Ok(())
+}
 
-    result
+// Todos stored before the counter was kept carry on after their highest step id
+fn step_counter(item: &TodoItem) -> u32 {
+    let after_highest = item.checklist.iter().map(|entry| entry.id.saturating_add(1)).max().unwrap_or(1);
+    item.next_step_id.max(after_highest)
 }
 
-async fn update_on_disk(todo_map: &BTreeMap<u32, TodoItem>) -> Result<(), Box<dyn std::error::Error>> {
-    let storage_path = "./.storage/todo_list.json";
+/// What else storing a change set off, reported back along with the todo.
+#[derive(Default)]
+struct Effects {
+    unblocked: Vec<u32>,
+    next_occurrence: Option<u32>,
+}
 
-    // Convert BTreeMap<u32, TodoItem> into BTreeMap<String, TodoItem>
-    let todo_map_string_keys: BTreeMap<String, &TodoItem> = todo_map
-        .iter()
-        .map(|(k, v)| (k.to_string(), v))
-        .collect();
+// The next occurrence of a repeating todo, a fresh copy due at the next date of its recurrence
+fn next_occurrence(item: &TodoItem, recurrence: &Recurrence) -> Option<TodoItem> {
+    let (due_date, recurrence) = recurrence.next(item.due_date)?;
+    let mut next = item.clone();
+    next.due_date = due_date;
+    next.recurrence = Some(recurrence);
+    next.completed = false;
+    // Its blockers were done with for this occurrence already
+    next.blocked_by.clear();
+    for step in &mut next.checklist {
+        step.done = false;
+    }
+    Some(next)
+}
 
-    // Serialize map to pretty JSON
-    let json_data = serde_json::to_string_pretty(&todo_map_string_keys)?;
+// Stamps and stores a validated change to `id`. Only the change that completes a todo sets
+// anything else off: the todos waiting on it may be unblocked, and a repeating todo hands its
+// recurrence over to a new todo for the next occurrence, so completing it again (after
+// reopening it) doesn't add a second one. Must be called under the write lock.
+fn store_change(store: &dyn TodoStore, id: u32, current: Option<&TodoItem>, mut item: TodoItem) -> Result<(TodoItem, Effects), AppError> {
+    let completes = item.completed && !current.is_some_and(|current| current.completed);
+    let next = match item.recurrence.take_if(|_| completes) {
+        Some(recurrence) => next_occurrence(&item, &recurrence),
+        None => None,
+    };
+    stamp(&mut item, current)?;
+    store.update(id, item.clone())?;
+
+    let mut effects = Effects::default();
+    if let Some(mut next) = next {
+        stamp(&mut next, None)?;
+        effects.next_occurrence = Some(store.insert(next)?);
+    }
+    if completes {
+        effects.unblocked = dependencies::unblocked_by(&store.list()?, id);
+    }
+    Ok((item, effects))
+}
 
-    // Overwrite the file
-    let mut file = File::create(storage_path)?;
-    file.write_all(json_data.as_bytes())?;
+// What a todo points to has to be there and reachable by the caller: its parent and blockers
+// (without looping back to `id`), its list and its assignee. `store` is what the caller sees and
+// `everyone` all the todos, like for `hierarchy::check_parent`.
+fn check_references(
+    state: &AppState,
+    caller: &Caller,
+    store: &dyn TodoView,
+    everyone: &dyn TodoView,
+    id: Option<u32>,
+    item: &TodoItem,
+) -> Result<(), AppError> {
+    hierarchy::check_parent(store, everyone, id, item.parent_id)?;
+    dependencies::check_blockers(store, everyone, id, &item.blocked_by)?;
+    lists::check_list(&state.lists, caller.user_id, item.list_id)?;
+    lists::check_assignee(&state.lists, caller.user_id, item.list_id, item.assigned_to)
+}
 
-    Ok(())
+// Every change to an existing todo goes the same way: under the write lock, check If-Match,
+// apply the change to a copy, validate and stamp it, then store it as the next revision.
+fn modify_todo(
+    state: &AppState,
+    caller: &Caller,
+    id: u32,
+    if_match: Option<&IfMatch>,
+    change: impl FnOnce(&mut TodoItem) -> Result<(), AppError>,
+) -> Result<(TodoItem, Effects), AppError> {
+    let _guard = state.write();
+    let current = caller.store.get(id)?.ok_or_else(|| not_found(id))?;
+    check_writable(caller, &current)?;
+    check_if_match(if_match, id, Some(&current))?;
+    let mut item = current.clone();
+    change(&mut item)?;
+    validate_item(&item)?;
+    if item.parent_id != current.parent_id {
+        hierarchy::check_parent(&caller.store, state.store.as_ref(), Some(id), item.parent_id)?;
+    }
+    if item.blocked_by != current.blocked_by {
+        dependencies::check_blockers(&caller.store, state.store.as_ref(), Some(id), &item.blocked_by)?;
+    }
+    if item.list_id != current.list_id {
+        lists::check_list(&state.lists, caller.user_id, item.list_id)?;
+    }
+    if item.assigned_to != current.assigned_to || item.list_id != current.list_id {
+        lists::check_assignee(&state.lists, caller.user_id, item.list_id, item.assigned_to)?;
+    }
+    store_change(&caller.store, id, Some(&current), item)
 }
 
-// To minimize complexity, I opted not to include a new struct that would take in the field
-// to update, and the data itself. This is mainly to avoid the complexity of decoding
-// the type of data dynamically. Since I'm not concerned about performance in this case,
-// I am not concerned about resending the entire todo entry back with updated data.
-// In Rust, this function is technically redundant, as reinserting will override +
-// pop the old hashmap or btreemap entry out.
+fn entry_response(id: u32, item: TodoItem) -> HttpResponse {
+    HttpResponse::Ok().insert_header(etag(&item)).json(TodoEntry { id, item })
+}
+
+fn update_response(id: u32, item: TodoItem, effects: Effects) -> HttpResponse {
+    HttpResponse::Ok().insert_header(etag(&item)).json(UpdateResponse {
+        entry: TodoEntry { id, item },
+        unblocked: effects.unblocked,
+        next_occurrence: effects.next_occurrence,
+    })
+}
+
+// Replaces the whole todo with the body, or creates it at this id if there's none. It's checked
+// like any other change (If-Match, parent, blockers, list, assignee) and stored as the next
+// revision, see patch_todo for changing only some of the fields.
 #[put("/update/{id}")]
 async fn update_value(
-    todo_list: web::Data<Mutex<BTreeMap<u32, TodoItem>>>,
+    state: web::Data<AppState>,
+    caller: Caller,
     id: web::Path<u32>,
+    if_match: Option<web::Header<IfMatch>>,
     recieved_todo: web::Json<TodoItem>,
-) -> impl Responder {
-    let mut list = todo_list.lock().unwrap();
-
-    let _original_data = list.insert(*id,recieved_todo.0);
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    // Storing a todo moves the id counter past it, and there's nothing past the last id
+    if id == u32::MAX {
+        return Err(ApiError::validation(format!("Todo ids must be below {}", u32::MAX)).into());
+    }
+    validate_item(&recieved_todo)?;
+    let item = recieved_todo.0;
 
-    update_on_disk(&list).await.unwrap();
+    let _guard = state.write();
+    let current = caller.store.get(id)?;
+    if let Some(current) = &current {
+        check_writable(&caller, current)?;
+    }
+    check_if_match(if_match.as_deref(), id, current.as_ref())?;
+    check_references(&state, &caller, &caller.store, state.store.as_ref(), Some(id), &item)?;
+    // At the id of a deleted todo, it's that todo's next revision, so revisions don't repeat
+    // within the history of one id and revert can go by them
+    let previous = match current {
+        Some(current) => Some(current),
+        None => state.history.latest(id)?,
+    };
+    let (item, effects) = store_change(&caller.store, id, previous.as_ref(), item)?;
+
+    Ok(update_response(id, item, effects))
+}
 
-    HttpResponse::Ok()
+// Unlike update_value, only the fields present in the body change, and reading the current
+// item, applying the patch and storing it happens under the write lock, so it's atomic.
+#[patch("/todos/{id}")]
+async fn patch_todo(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    if_match: Option<web::Header<IfMatch>>,
+    patch: web::Json<TodoPatch>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let (item, effects) = modify_todo(&state, &caller, id, if_match.as_deref(), |item| {
+        patch.apply(item);
+        Ok(())
+    })?;
+
+    Ok(update_response(id, item, effects))
 }
 
 #[delete("/delete/{id}")]
 async fn delete(
-    todo_list: web::Data<Mutex<BTreeMap<u32, TodoItem>>>,
+    state: web::Data<AppState>,
+    caller: Caller,
     id: web::Path<u32>,
-) -> impl Responder {
-    let mut list = todo_list.lock().unwrap();
+    if_match: Option<web::Header<IfMatch>>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, AppError> {
+    delete_todo(&state, &caller, *id, if_match.as_deref(), query.children)?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    let _removed_data = list.remove(&id);
+// Subtasks are never left pointing at a todo that's gone, the request has to say whether they
+// go with it or move up a level. Todos it was blocking simply stop waiting on it.
+// Subtasks and blocked todos may be on lists other than this one, someone else's even, so
+// they're looked up in everyone's todos rather than just the caller's.
+fn delete_todo(
+    state: &AppState,
+    caller: &Caller,
+    id: u32,
+    if_match: Option<&IfMatch>,
+    children_on_delete: Option<ChildrenOnDelete>,
+) -> Result<(), AppError> {
+    let _guard = state.write();
+    let current = caller.store.get(id)?.ok_or_else(|| not_found(id))?;
+    check_writable(caller, &current)?;
+    check_if_match(if_match, id, Some(&current))?;
+
+    let todos = state.store.list()?;
+    let children = hierarchy::children_of(&todos, id);
+    let mut deleted = vec![id];
+    match (children_on_delete, children.is_empty()) {
+        (_, true) => {}
+        (None, false) => {
+            return Err(ApiError::conflict(format!(
+                "Todo item {} has {} subtasks, pass children=cascade to delete them too or children=reparent to keep them",
+                id,
+                children.len()
+            ))
+            .into());
+        }
+        (Some(ChildrenOnDelete::Cascade), false) => {
+            let descendants = hierarchy::descendants_of(&todos, id);
+            if let Some(locked) = descendants.iter().find(|descendant_id| !caller.store.can_write(&todos[descendant_id])) {
+                return Err(ApiError::forbidden(format!(
+                    "Subtask {} of todo item {} isn't yours to delete, pass children=reparent to keep it",
+                    locked, id
+                ))
+                .into());
+            }
+            for descendant_id in descendants {
+                caller.everyone.delete(descendant_id)?;
+                deleted.push(descendant_id);
+            }
+        }
+        (Some(ChildrenOnDelete::Reparent), false) => {
+            // Moving them up a level changes them, which has to be allowed for every one of them
+            if let Some(locked) = children.iter().find(|child_id| !caller.store.can_write(&todos[child_id])) {
+                return Err(ApiError::forbidden(format!(
+                    "Subtask {} of todo item {} isn't yours to move up a level, nor to delete",
+                    locked, id
+                ))
+                .into());
+            }
+            for child_id in children {
+                let child = &todos[&child_id];
+                let mut item = child.clone();
+                item.parent_id = current.parent_id;
+                stamp(&mut item, Some(child))?;
+                caller.everyone.update(child_id, item)?;
+            }
+        }
+    }
+    caller.store.delete(id)?;
+    // Re-read, so todos that were just reparented aren't overwritten with their old revision
+    dependencies::forget_blockers(caller.everyone.as_ref(), &caller.everyone.list()?, &deleted)?;
+    Ok(())
+}
 
-    update_on_disk(&list).await.unwrap();
+// Validates and stores a new todo, for `POST /insert` and a `create` over `/sync`
+fn insert_todo(state: &AppState, caller: &Caller, mut item: TodoItem) -> Result<(u32, TodoItem), AppError> {
+    validate_item(&item)?;
+    stamp(&mut item, None)?;
 
-    HttpResponse::Ok()
+    let _guard = state.write();
+    check_references(state, caller, &caller.store, state.store.as_ref(), None, &item)?;
+    let id = caller.store.insert(item.clone())?; // add the new item
+    Ok((id, item))
 }
 
-
 #[post("/insert")]
 async fn insert(
-    todo_list: web::Data<Mutex<BTreeMap<u32, TodoItem>>>,
+    state: web::Data<AppState>,
+    caller: Caller,
     recieved_todo: web::Json<TodoItem>,
-) -> impl Responder {
-    let mut list = todo_list.lock().unwrap();
-    let id = list.len() as u32; // assume essentially appending similar to a SQL db
-
-    list.insert(id,recieved_todo.0); // add the new item
-
-    update_on_disk(&list).await.unwrap();
+) -> Result<HttpResponse, AppError> {
+    let (id, item) = insert_todo(&state, &caller, recieved_todo.0)?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Created()
+        .insert_header((http::header::LOCATION, format!("/view/{}", id)))
+        .insert_header(etag(&item))
+        .json(InsertResponse { id }))
 }
 
 #[get("/view/{id}")]
 async fn view(
-    todo_list: web::Data<Mutex<BTreeMap<u32, TodoItem>>>,
+    caller: Caller,
     id_to_view: web::Path<u32>,
-) -> impl Responder {
-    let list = todo_list.lock().unwrap();
-
-    match list.get(&id_to_view) {
-        Some(item) => HttpResponse::Ok().json(item),
-        None => HttpResponse::NotFound().body(format!("Todo item with id {} not found", id_to_view)),
+) -> Result<HttpResponse, AppError> {
+    match caller.store.get(*id_to_view)? {
+        Some(item) => Ok(HttpResponse::Ok().insert_header(etag(&item)).json(item)),
+        None => Err(not_found(*id_to_view)),
     }
 }
 
 #[get("/list")]
 async fn list_all(
-    todo_list: web::Data<Mutex<BTreeMap<u32, TodoItem>>>
-) -> impl Responder {
-    let list = todo_list.lock().unwrap();
-    HttpResponse::Ok()
-        .json(&*list)
+    caller: Caller,
+    query: web::Query<TodoQuery>,
+) -> Result<HttpResponse, AppError> {
+    list_response(&caller.store, &query)
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+// One page of the todos that match `query`, shared with the list-scoped listing
+fn list_response(store: &dyn TodoView, query: &TodoQuery) -> Result<HttpResponse, AppError> {
+    let mut todos = store.query(query)?;
+    // Whether a todo is blocked depends on other todos, which the filters may have left out
+    if let Some(actionable) = query.actionable {
+        let all = store.list()?;
+        todos.retain(|_, item| !item.completed && dependencies::is_blocked(&all, item) != actionable);
+    }
+    let page = listing::page(todos, query)?;
 
-    // Initially, load from disk!
-    let todo_from_disk = initial_loading_from_disk().await;
-
-    // Mutex is being used to avoid concurrency issues, if this was a regular eg db refrence, with no writes to the object itself,
-    // the mutex lock would not be included, and would be more optimal for an application at scale.
-    // BTreeMap is used here, because at lower n-counts, it's faster than a hashmap.
-    let todo_data = web::Data::new(Mutex::new(todo_from_disk));
-
-    HttpServer::new(move || App::new().wrap(
-        Cors::default()
-        .allowed_origin("http://localhost:8080") // Replace with your desired origin, specifically if served in the browser with this URL
-        .allowed_methods(vec!["GET", "POST"]) // Specify the allowed HTTP methods
-        .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT]) // Specify the allowed headers
-        .max_age(usize::MAX) // Set the maximum age of the CORS options request
-    )
-        // Data
-        .app_data(todo_data.clone()) // This acts similarly to a global variable
+    // The page changes whenever one of its todos is added, removed or gets a new revision
+    let mut hasher = DefaultHasher::new();
+    for entry in &page.items {
+        (entry.id, entry.item.revision).hash(&mut hasher);
+    }
+    page.next_cursor.hash(&mut hasher);
+    let page_etag = ETag(EntityTag::new_weak(format!("{:x}", hasher.finish())));
+
+    Ok(HttpResponse::Ok()
+        .insert_header(page_etag)
+        .json(&page))
+}
+
+#[get("/tags")]
+async fn list_tags(
+    caller: Caller,
+    query: web::Query<TagQuery>,
+) -> Result<HttpResponse, AppError> {
+    let todos = match query.list {
+        Some(list_id) => caller.store.query(&TodoQuery::new().list(list_id))?,
+        None => caller.store.list()?,
+    };
+    let mut counts: BTreeMap<String, u32> = BTreeMap::new();
+    for item in todos.into_values() {
+        for tag in item.tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    let tags: Vec<TagCount> = counts.into_iter().map(|(tag, count)| TagCount { tag, count }).collect();
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Each todo is stored on its own, so should the storage fail halfway through, the todos
+// before it keep the new tag. Sending the same rename again finishes the job. Todos the
+// caller may only look at keep their tags.
+#[post("/tags/rename")]
+async fn rename_tag(
+    state: web::Data<AppState>,
+    caller: Caller,
+    rename: web::Json<TagRename>,
+) -> Result<HttpResponse, AppError> {
+    let from = normalize_tag(&rename.from);
+    let to = normalize_tag(&rename.to);
+    if from.is_empty() || to.is_empty() {
+        return Err(ApiError::validation("Tags must not be empty").into());
+    }
+
+    let _guard = state.write();
+    let mut renamed = 0;
+    if from != to {
+        let mut query = TodoQuery::new().tag(from.as_str());
+        query.list = rename.list;
+        for (id, current) in caller.store.query(&query)? {
+            if !caller.store.can_write(&current) {
+                continue;
+            }
+            let mut item = current.clone();
+            for tag in &mut item.tags {
+                if *tag == from {
+                    *tag = to.clone();
+                }
+            }
+            // Merging into a tag the todo already has leaves a duplicate, which stamp drops
+            stamp(&mut item, Some(&current))?;
+            caller.store.update(id, item)?;
+            renamed += 1;
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(TagRenameResponse { renamed }))
+}
+
+fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str)) // Specify the allowed HTTP methods
+        .allowed_headers(config.allowed_headers.iter().map(String::as_str)) // Specify the allowed headers
+        .max_age(usize::MAX); // Set the maximum age of the CORS options request
+
+    // An explicit origin would narrow "*" back down again in actix-cors, so "*" has to win outright
+    if config.allowed_origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    cors
+}
+
+// Everything the app serves, on top of the state and middleware main() (or a test) sets up
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Errors from extracting the request answer in the same JSON format as the handlers
+        .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(query_error_handler))
         // Services (API Endpoints)
         .service(list_all)
         .service(insert)
         .service(view)
         .service(update_value)
+        .service(patch_todo)
         .service(delete)
+        .service(list_tags)
+        .service(hierarchy::list_children)
+        .service(history::todo_history)
+        .service(history::revert_todo)
+        .service(history::undo)
+        .service(checklist::add_checklist_item)
+        .service(checklist::patch_checklist_item)
+        .service(checklist::delete_checklist_item)
+        .service(rename_tag)
+        .service(lists::get_lists)
+        .service(lists::create_list)
+        .service(lists::get_list)
+        .service(lists::rename_list)
+        .service(lists::delete_list)
+        .service(lists::list_todos)
+        .service(sharing::list_members)
+        .service(sharing::share_list)
+        .service(sharing::unshare_list)
+        .service(events::events)
+        .service(sync::sync)
+        .service(webhooks::create_webhook)
+        .service(webhooks::list_webhooks)
+        .service(webhooks::delete_webhook)
+        .service(webhooks::list_deliveries)
+        .service(auth::register)
+        .service(auth::login)
+        .service(auth::logout)
+        .service(auth::me)
+        .service(api_tokens::create_token)
+        .service(api_tokens::list_tokens)
+        .service(api_tokens::revoke_token)
+        .default_service(web::to(route_not_found));
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Configuration error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
+
+    // Initially, load from disk!
+    let store = match open_store(config.storage, &config.data_dir) {
+        Ok(store) => store,
+        Err(err) => {
+            eprintln!("Failed to load the todo list: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let history = match History::open(config.data_dir.join("history.jsonl")) {
+        Ok(history) => history,
+        Err(err) => {
+            eprintln!("Failed to open the change history: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let lists = match lists::open_lists(&config.data_dir) {
+        Ok(lists) => lists,
+        Err(err) => {
+            eprintln!("Failed to load the lists: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let auth = match auth::Auth::open(&config.data_dir, config.require_auth) {
+        Ok(auth) => auth,
+        Err(err) => {
+            eprintln!("Failed to load the user accounts: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let webhooks = match webhooks::Webhooks::open(&config.data_dir, config.allow_private_webhooks) {
+        Ok(webhooks) => webhooks,
+        Err(err) => {
+            eprintln!("Failed to load the webhooks: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // The store does its own locking, so all workers can share it behind the trait object
+    let todo_data = web::Data::new(AppState::new(store, history, lists, auth, webhooks));
+    webhooks::start(todo_data.clone());
+
+    let cors_config = config.cors.clone();
+    // Wrapped inside CORS, so preflight requests are answered without a token
+    HttpServer::new(move || App::new().wrap(from_fn(auth::authenticate)).wrap(cors(&cors_config))
+        // Data
+        .app_data(todo_data.clone()) // This acts similarly to a global variable
+        .configure(routes)
     )
-        .bind("127.0.0.1:8081")?
+        .workers(config.workers)
+        .bind((config.host.as_str(), config.port))?
         .run()
         .await
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{ETAG, IF_MATCH, LOCATION};
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use todo_list_common::{TodoList, TodoListEntry};
+
+    use super::*;
+    use crate::testing::{app, state};
+
+    #[actix_web::test]
+    async fn inserted_todos_get_ids_that_are_never_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = init_service(app(state(dir.path()))).await;
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let res = call_service(&app, TestRequest::post().uri("/insert").set_json(&item).to_request()).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let location = res.headers().get(LOCATION).unwrap().to_str().unwrap().to_string();
+            let InsertResponse { id } = read_body_json(res).await;
+            assert_eq!(location, format!("/view/{}", id));
+            let res = call_service(&app, TestRequest::get().uri(&location).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            ids.push(id);
+        }
+        assert_ne!(ids[0], ids[1]);
+
+        // Deleting the newest one doesn't hand its id out again
+        call_service(&app, TestRequest::delete().uri(&format!("/delete/{}", ids[1])).to_request()).await;
+        let res = call_service(&app, TestRequest::post().uri("/insert").set_json(&item).to_request()).await;
+        let InsertResponse { id } = read_body_json(res).await;
+        assert!(id > ids[1]);
+    }
+
+    #[actix_web::test]
+    async fn patch_changes_only_what_it_was_sent() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = init_service(app(state(dir.path()))).await;
+        let create = async |item: &TodoItem| {
+            let res = call_service(&app, TestRequest::post().uri("/insert").set_json(item).to_request()).await;
+            read_body_json::<InsertResponse, _>(res).await.id
+        };
+        let patch = async |id: u32, body: serde_json::Value| {
+            let res = call_service(&app, TestRequest::patch().uri(&format!("/todos/{}", id)).set_json(body).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            read_body_json::<UpdateResponse, _>(res).await.entry.item
+        };
+
+        let parent = create(&TodoItem { title: "parent".to_string(), ..Default::default() }).await;
+        let child = TodoItem {
+            title: "child".to_string(),
+            description: "details".to_string(),
+            tags: vec!["home".to_string()],
+            parent_id: Some(parent),
+            ..Default::default()
+        };
+        let id = create(&child).await;
+
+        // A missing key leaves the field as it is, parent_id included
+        let item = patch(id, serde_json::json!({"title": "renamed"})).await;
+        assert_eq!(item.title, "renamed");
+        assert_eq!((item.description.as_str(), item.tags.as_slice(), item.parent_id), ("details", &["home".to_string()][..], Some(parent)));
+        assert_eq!(item.revision, 2);
+
+        let item = patch(id, serde_json::json!({"completed": true, "tags": ["Work"]})).await;
+        assert!(item.completed && item.completed_at.is_some());
+        assert_eq!((item.title.as_str(), item.tags.as_slice(), item.parent_id), ("renamed", &["work".to_string()][..], Some(parent)));
+
+        // null takes it off its parent
+        let item = patch(id, serde_json::json!({"parent_id": null})).await;
+        assert_eq!(item.parent_id, None);
+        assert!(item.completed);
+        assert_eq!(item.revision, 4);
+    }
+
+    #[actix_web::test]
+    async fn changes_go_through_only_at_the_revision_if_match_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = init_service(app(state(dir.path()))).await;
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+        let res = call_service(&app, TestRequest::post().uri("/insert").set_json(&item).to_request()).await;
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"1\"");
+        let InsertResponse { id } = read_body_json(res).await;
+
+        let res = call_service(&app, TestRequest::get().uri(&format!("/view/{}", id)).to_request()).await;
+        let first = res.headers().get(ETAG).unwrap().clone();
+        assert_eq!(first, "\"1\"");
+        let rename = |title: &str| TodoPatch { title: Some(title.to_string()), ..Default::default() };
+        let patch = |patch: TodoPatch| TestRequest::patch().uri(&format!("/todos/{}", id)).set_json(patch);
+
+        // Matching, the change goes through and the response carries the new ETag
+        let res = call_service(&app, patch(rename("first")).insert_header((IF_MATCH, first.clone())).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"2\"");
+
+        // Stale, refused along with what the todo looks like now
+        let res = call_service(&app, patch(rename("second")).insert_header((IF_MATCH, first)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let err: ApiError = read_body_json(res).await;
+        let current: TodoEntry = serde_json::from_value(err.details.unwrap()).unwrap();
+        assert_eq!((current.id, current.item.title.as_str(), current.item.revision), (id, "first", 2));
+
+        // Without the header, last write wins
+        let res = call_service(&app, patch(rename("third")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = call_service(&app, TestRequest::get().uri(&format!("/view/{}", id)).to_request()).await;
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"3\"");
+        assert_eq!(read_body_json::<TodoItem, _>(res).await.title, "third");
+
+        // The same goes for replacing and deleting it
+        let req = TestRequest::put().uri(&format!("/update/{}", id)).insert_header((IF_MATCH, "\"2\"")).set_json(&item).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::PRECONDITION_FAILED);
+        let req = TestRequest::delete().uri(&format!("/delete/{}", id)).insert_header((IF_MATCH, "\"3\"")).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn tags_are_counted_and_renamed_in_their_normal_form() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = init_service(app(state(dir.path()))).await;
+        let req = TestRequest::post().uri("/lists").set_json(TodoList { name: "work".to_string() }).to_request();
+        let list: TodoListEntry = read_body_json(call_service(&app, req).await).await;
+        let create = async |tags: &[&str], list_id: u32| {
+            let item = TodoItem { title: "task".to_string(), tags: tags.iter().map(|tag| tag.to_string()).collect(), list_id, ..Default::default() };
+            let res = call_service(&app, TestRequest::post().uri("/insert").set_json(item).to_request()).await;
+            read_body_json::<InsertResponse, _>(res).await.id
+        };
+        let tags = async |uri: &str| -> Vec<(String, u32)> {
+            let counts: Vec<TagCount> = read_body_json(call_service(&app, TestRequest::get().uri(uri).to_request()).await).await;
+            counts.into_iter().map(|count| (count.tag, count.count)).collect()
+        };
+        let rename = async |rename: TagRename| {
+            let res = call_service(&app, TestRequest::post().uri("/tags/rename").set_json(rename).to_request()).await;
+            read_body_json::<TagRenameResponse, _>(res).await.renamed
+        };
+        let tags_of = async |id: u32| {
+            let res = call_service(&app, TestRequest::get().uri(&format!("/view/{}", id)).to_request()).await;
+            read_body_json::<TodoItem, _>(res).await.tags
+        };
+        let owned = |tags: &[(&str, u32)]| tags.iter().map(|(tag, count)| (tag.to_string(), *count)).collect::<Vec<_>>();
+
+        let both = create(&["Home", " URGENT "], 0).await;
+        let home = create(&["home", "chores"], 0).await;
+        let listed = create(&["Chores"], list.id).await;
+        assert_eq!(tags_of(both).await, vec!["home", "urgent"]);
+        assert_eq!(tags("/tags").await, owned(&[("chores", 2), ("home", 2), ("urgent", 1)]));
+        assert_eq!(tags(&format!("/tags?list={}", list.id)).await, owned(&[("chores", 1)]));
+
+        // Renaming onto a tag the todo already has leaves it there once
+        assert_eq!(rename(TagRename { from: "Urgent".to_string(), to: " HOME".to_string(), list: None }).await, 1);
+        assert_eq!(tags_of(both).await, vec!["home"]);
+        assert_eq!(tags("/tags").await, owned(&[("chores", 2), ("home", 2)]));
+
+        // Only on the given list
+        assert_eq!(rename(TagRename { from: "chores".to_string(), to: "errands".to_string(), list: Some(list.id) }).await, 1);
+        assert_eq!(tags_of(listed).await, vec!["errands"]);
+        assert_eq!(tags_of(home).await, vec!["home", "chores"]);
+        assert_eq!(tags("/tags").await, owned(&[("chores", 1), ("errands", 1), ("home", 2)]));
+    }
+
+    #[actix_web::test]
+    async fn the_last_id_is_turned_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+
+        let req = TestRequest::put().uri(&format!("/update/{}", u32::MAX)).set_json(&item).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(state.store.list().unwrap().is_empty());
+
+        // The one before it is fine, after that there's no id left for an insert
+        let req = TestRequest::put().uri(&format!("/update/{}", u32::MAX - 1)).set_json(&item).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+        let res = call_service(&app, TestRequest::post().uri("/insert").set_json(&item).to_request()).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(state.store.list().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+use actix_web::{HttpResponse, delete, get, put, web};
+use todo_list_common::{ApiError, INBOX_ID, ListMember, ListRole, ListShare, TodoQuery};
+
+use crate::auth::Caller;
+use crate::error::AppError;
+use crate::lists::{StoredList, check_owner, get_visible};
+use crate::state::AppState;
+use crate::stamp;
+
+// The owner first, then the others in the order of their ids. Users that have been removed
+// since are left out.
+fn members(state: &AppState, list: &StoredList) -> Vec<ListMember> {
+    let owner = list.owner_id.map(|id| (id, ListRole::Owner));
+    owner
+        .into_iter()
+        .chain(list.shared_with.iter().map(|(&id, &role)| (id, role)))
+        .filter_map(|(id, role)| Some(ListMember { id, username: state.auth.username(id)?, role }))
+        .collect()
+}
+
+#[get("/lists/{id}/members")]
+async fn list_members(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let (list, _) = get_visible(&state.lists, caller.user_id, id)?;
+    // Everyone has their own Inbox, nobody else is on it
+    let members = match (id, caller.user_id) {
+        (INBOX_ID, Some(user_id)) => members(state.get_ref(), &StoredList::new(list.name, Some(user_id))),
+        (INBOX_ID, None) => Vec::new(),
+        _ => members(state.get_ref(), &list),
+    };
+    Ok(HttpResponse::Ok().json(members))
+}
+
+// Sharing with someone who's already a member changes their role
+#[put("/lists/{id}/shares")]
+async fn share_list(
+    state: web::Data<AppState>,
+    caller: Caller,
+    id: web::Path<u32>,
+    share: web::Json<ListShare>,
+) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    let Some(owner_id) = caller.user_id else {
+        return Err(ApiError::unauthorized("Log in to share lists").into());
+    };
+    if id == INBOX_ID {
+        return Err(ApiError::conflict("The Inbox can't be shared").into());
+    }
+    if share.role == ListRole::Owner {
+        return Err(ApiError::validation("Lists are shared with an editor or a viewer role").into());
+    }
+
+    let _guard = state.write();
+    let (mut list, role) = get_visible(&state.lists, caller.user_id, id)?;
+    check_owner(role, id)?;
+    let user_id = state
+        .auth
+        .user_id(&share.username)
+        .ok_or_else(|| ApiError::not_found(format!("There's no user called {}", share.username.trim())))?;
+    if user_id == owner_id {
+        return Err(ApiError::validation("Lists can't be shared with their owner").into());
+    }
+    list.shared_with.insert(user_id, share.role);
+    state.lists.update(id, list.clone())?;
+
+    Ok(HttpResponse::Ok().json(members(state.get_ref(), &list)))
+}
+
+// The owner can take anyone off the list, and members can leave it themselves. Their
+// assignments on it go with them.
+#[delete("/lists/{id}/shares/{user_id}")]
+async fn unshare_list(
+    state: web::Data<AppState>,
+    caller: Caller,
+    path: web::Path<(u32, u32)>,
+) -> Result<HttpResponse, AppError> {
+    let (id, user_id) = path.into_inner();
+
+    let _guard = state.write();
+    let (mut list, role) = get_visible(&state.lists, caller.user_id, id)?;
+    if caller.user_id != Some(user_id) {
+        check_owner(role, id)?;
+    }
+    if list.shared_with.remove(&user_id).is_none() {
+        return Err(ApiError::not_found(format!("List {} isn't shared with user {}", id, user_id)).into());
+    }
+    state.lists.update(id, list)?;
+
+    // The todos on the list are its owner's, so they're only reachable through the shared store
+    for (todo_id, current) in caller.everyone.query(&TodoQuery::new().list(id).assigned_to(user_id))? {
+        let mut item = current.clone();
+        item.assigned_to = None;
+        stamp(&mut item, Some(&current))?;
+        caller.everyone.update(todo_id, item)?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, init_service, read_body_json};
+    use todo_list_common::{ErrorCode, InsertResponse, TodoItem, TodoList, TodoListEntry, TodoPatch};
+
+    use super::*;
+    use crate::testing::{app, state, user};
+
+    #[actix_web::test]
+    async fn editors_cant_take_todos_off_a_shared_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let app = init_service(app(state.clone())).await;
+        let (alice_id, alice) = user(&state, "alice");
+        let (_, bob) = user(&state, "bob");
+        let create_list = |auth: (_, String), name: &str| {
+            TestRequest::post().uri("/lists").insert_header(auth).set_json(TodoList { name: name.to_string() }).to_request()
+        };
+        let shared: TodoListEntry = call_and_read_body_json(&app, create_list(alice.clone(), "groceries")).await;
+        let own: TodoListEntry = call_and_read_body_json(&app, create_list(bob.clone(), "mine")).await;
+        let share = ListShare { username: "bob".to_string(), role: ListRole::Editor };
+        let req = TestRequest::put().uri(&format!("/lists/{}/shares", shared.id)).insert_header(alice.clone()).set_json(share).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        let item = TodoItem { title: "milk".to_string(), list_id: shared.id, ..Default::default() };
+        let req = TestRequest::post().uri("/insert").insert_header(alice.clone()).set_json(&item).to_request();
+        let InsertResponse { id } = call_and_read_body_json(&app, req).await;
+        let patch = |auth: (_, String), patch: TodoPatch| TestRequest::patch().uri(&format!("/todos/{}", id)).insert_header(auth).set_json(patch).to_request();
+
+        // Bob may change it where it is, but not move it to his own list or his Inbox
+        let res = call_service(&app, patch(bob.clone(), TodoPatch { title: Some("oat milk".to_string()), ..Default::default() })).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        for list_id in [own.id, INBOX_ID] {
+            let res = call_service(&app, patch(bob.clone(), TodoPatch { list_id: Some(list_id), ..Default::default() })).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert_eq!(read_body_json::<ApiError, _>(res).await.code, ErrorCode::Forbidden);
+        }
+        let moved = TodoItem { list_id: own.id, ..item.clone() };
+        let req = TestRequest::put().uri(&format!("/update/{}", id)).insert_header(bob.clone()).set_json(moved).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        let stored = state.store.get(id).unwrap().unwrap();
+        assert_eq!((stored.owner_id, stored.list_id, stored.title.as_str()), (Some(alice_id), shared.id, "oat milk"));
+
+        // Alice still can, after which Bob doesn't see it anymore
+        let res = call_service(&app, patch(alice, TodoPatch { list_id: Some(INBOX_ID), ..Default::default() })).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = TestRequest::get().uri(&format!("/view/{}", id)).insert_header(bob).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::sync::broadcast;
+
+use crate::auth::Auth;
+use crate::lists::StoredList;
+use crate::storage::{Change, History, HistoryStore, JsonCollection, NotifyingStore, Operation, TodoStore};
+use crate::webhooks::{WebhookStore, Webhooks};
+
+// How many changes a subscriber of `/events` may fall behind before it misses some
+const CHANGE_BUFFER: usize = 256;
+
+/// Shared by all workers, this acts similarly to a global variable.
+pub struct AppState {
+    /// Everyone's todos, changes to them aren't part of any operation. Handlers go through
+    /// `Caller::store`, which only has the caller's, or `Caller::everyone`.
+    pub store: Arc<dyn TodoStore>,
+    // The storage itself, under the history, webhooks and notifications
+    storage: Arc<dyn TodoStore>,
+    /// Every change to a todo, as it's stored
+    pub changes: broadcast::Sender<Change>,
+    /// Every change to a todo, ever
+    pub history: Arc<History>,
+    pub lists: Arc<JsonCollection<StoredList>>,
+    pub auth: Auth,
+    pub webhooks: Arc<Webhooks>,
+    // Each store call is atomic on its own, but a PATCH reads, changes and writes the item back.
+    // Every handler that writes holds this lock, so no other write can sneak in between.
+    write_lock: Mutex<()>,
+}
+
+impl AppState {
+    pub fn new(
+        store: Arc<dyn TodoStore>,
+        history: History,
+        lists: JsonCollection<StoredList>,
+        auth: Auth,
+        webhooks: Webhooks,
+    ) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_BUFFER);
+        let history = Arc::new(history);
+        let lists = Arc::new(lists);
+        let webhooks = Arc::new(webhooks);
+        let mut state = AppState {
+            store: store.clone(),
+            storage: store,
+            changes,
+            history,
+            lists,
+            auth,
+            webhooks,
+            write_lock: Mutex::new(()),
+        };
+        state.store = state.recording(None);
+        state
+    }
+
+    /// Everyone's todos, with every change recorded in the history as part of `operation`.
+    pub fn recording(&self, operation: Option<Arc<Operation>>) -> Arc<dyn TodoStore> {
+        let store = Arc::new(HistoryStore::new(self.storage.clone(), self.history.clone(), operation));
+        let store = Arc::new(WebhookStore::new(store, self.webhooks.clone(), self.lists.clone()));
+        Arc::new(NotifyingStore::new(store, self.changes.clone()))
+    }
+
+    /// Held for the whole of a change. Never keep it across an `.await`.
+    pub fn write(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
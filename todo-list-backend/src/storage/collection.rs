@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{StoreError, id_after, lock};
+use crate::atomic_file::write_atomically;
+
+const BACKUP_COUNT: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"), deny_unknown_fields)]
+struct Collection<T> {
+    #[serde(default)]
+    next_id: u32,
+    #[serde(default = "BTreeMap::new")]
+    items: BTreeMap<u32, T>,
+}
+
+/// A small set of records kept in its own JSON file, next to the todos whichever storage holds
+/// those. Meant for things there are only ever a handful of, like the named lists, so like
+/// `JsonFileStore` it keeps everything in memory and rewrites the file on every change.
+/// Ids are allocated the same way, and never reused.
+pub struct JsonCollection<T> {
+    path: PathBuf,
+    collection: Mutex<Collection<T>>,
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> JsonCollection<T> {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let collection = if path.exists() {
+            serde_json::from_reader(File::open(&path)?).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} could not be parsed ({}), fix the file or restore one of its backups", path.display(), err),
+                )
+            })?
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Collection { next_id: 0, items: BTreeMap::new() }
+        };
+
+        Ok(JsonCollection { path, collection: Mutex::new(collection) })
+    }
+
+    fn modify<R>(&self, change: impl FnOnce(&mut Collection<T>) -> Result<R, StoreError>) -> Result<R, StoreError> {
+        let mut collection = lock(&self.collection);
+        // Same as `JsonFileStore`, memory only changes once the file did
+        let mut changed = collection.clone();
+        let result = change(&mut changed)?;
+        write_atomically(&self.path, serde_json::to_string_pretty(&changed)?.as_bytes(), BACKUP_COUNT)?;
+        *collection = changed;
+        Ok(result)
+    }
+
+    pub fn get(&self, id: u32) -> Option<T> {
+        lock(&self.collection).items.get(&id).cloned()
+    }
+
+    pub fn list(&self) -> BTreeMap<u32, T> {
+        lock(&self.collection).items.clone()
+    }
+
+    pub fn insert(&self, item: T) -> Result<u32, StoreError> {
+        self.modify(|collection| {
+            let id = collection.next_id;
+            collection.next_id = id_after(id)?;
+            collection.items.insert(id, item);
+            Ok(id)
+        })
+    }
+
+    /// Replaces the record at `id`, or creates it there, and keeps `id` from being allocated again.
+    pub fn update(&self, id: u32, item: T) -> Result<Option<T>, StoreError> {
+        self.modify(|collection| {
+            collection.next_id = collection.next_id.max(id_after(id)?);
+            Ok(collection.items.insert(id, item))
+        })
+    }
+
+    pub fn delete(&self, id: u32) -> Result<Option<T>, StoreError> {
+        self.modify(|collection| Ok(collection.items.remove(&id)))
+    }
+
+    /// Deletes every record `keep` says no to, in one write.
+    pub fn retain(&self, mut keep: impl FnMut(u32, &T) -> bool) -> Result<(), StoreError> {
+        if lock(&self.collection).items.iter().all(|(id, item)| keep(*id, item)) {
+            return Ok(());
+        }
+        self.modify(|collection| {
+            collection.items.retain(|id, item| keep(*id, item));
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("names.json");
+
+        let names = JsonCollection::open(&path).unwrap();
+        names.update(0, "zero".to_string()).unwrap();
+        let first = names.insert("first".to_string()).unwrap();
+        assert_eq!(first, 1);
+        names.delete(first).unwrap();
+
+        let reopened = JsonCollection::<String>::open(&path).unwrap();
+        assert_eq!(reopened.list(), BTreeMap::from([(0, "zero".to_string())]));
+        assert_eq!(reopened.insert("second".to_string()).unwrap(), 2);
+        assert!(matches!(reopened.update(u32::MAX, "last".to_string()), Err(StoreError::OutOfIds)));
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use todo_list_common::{TodoItem, TodoQuery};
+
+use super::{StoreError, TodoStore, TodoView, lock};
+
+/// One change to one todo, a line of `history.jsonl`. `None` on either side is a todo that
+/// didn't exist before, or was deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub todo_id: u32,
+    pub at: DateTime<Utc>,
+    pub actor: Option<u32>,
+    /// Changes made by the same request share this, recorded before there were any have none
+    #[serde(default)]
+    pub operation: Option<u64>,
+    /// The operation this change was undoing, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+    pub before: Option<TodoItem>,
+    pub after: Option<TodoItem>,
+}
+
+/// The changes one request makes, and who makes them. The id is handed out by the first change
+/// that's recorded, so a request that changes nothing doesn't use one up.
+#[derive(Debug, Default)]
+pub struct Operation {
+    actor: Option<u32>,
+    id: OnceLock<u64>,
+    undoes: OnceLock<u64>,
+}
+
+impl Operation {
+    pub fn new(actor: Option<u32>) -> Self {
+        Operation { actor, ..Default::default() }
+    }
+
+    /// The id it was recorded as, `None` until something changed.
+    pub fn id(&self) -> Option<u64> {
+        self.id.get().copied()
+    }
+
+    /// Marks this operation as undoing `operation`, so that one isn't undone twice.
+    pub fn undoing(&self, operation: u64) {
+        let _ = self.undoes.set(operation);
+    }
+}
+
+// The fields of a record the index needs, read without the todos on either side
+#[derive(Deserialize)]
+struct RecordKey {
+    todo_id: u32,
+    actor: Option<u32>,
+    #[serde(default)]
+    operation: Option<u64>,
+    #[serde(default)]
+    undoes: Option<u64>,
+}
+
+// Where a record starts in the file, and what undo needs to know of it without reading it
+#[derive(Debug, Clone, Copy)]
+struct Indexed {
+    offset: u64,
+    operation: Option<u64>,
+    undoes: Option<u64>,
+}
+
+// Built when the file is opened and kept up to date by every append, so looking something up
+// only reads the records that are asked for
+#[derive(Debug, Default)]
+struct Index {
+    by_todo: BTreeMap<u32, Vec<u64>>,
+    by_actor: BTreeMap<Option<u32>, Vec<Indexed>>,
+    last_operation: u64,
+}
+
+impl Index {
+    fn add(&mut self, offset: u64, key: RecordKey) {
+        self.by_todo.entry(key.todo_id).or_default().push(offset);
+        let indexed = Indexed { offset, operation: key.operation, undoes: key.undoes };
+        self.by_actor.entry(key.actor).or_default().push(indexed);
+        self.last_operation = self.last_operation.max(key.operation.unwrap_or(0));
+    }
+}
+
+struct HistoryFile {
+    file: File,
+    // Length after the last complete record, to cut off a half-written one
+    len: u64,
+    index: Index,
+}
+
+/// Every change ever made to the todos, appended one JSON line at a time and never rewritten.
+/// Who made a change isn't something the storage knows, so each request hands its `Operation`
+/// to the `HistoryStore` its changes go through.
+pub struct History {
+    path: PathBuf,
+    file: Mutex<HistoryFile>,
+}
+
+impl History {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+
+        let mut index = Index::default();
+        let mut reader = BufReader::new(&mut file);
+        let (mut len, mut line) = (0, String::new());
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)? as u64;
+            // A crash mid-write can leave the last line cut off, the next record would be
+            // appended right after it
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            match serde_json::from_str::<RecordKey>(&line) {
+                Ok(key) => index.add(len, key),
+                Err(err) if !line.trim().is_empty() => eprintln!("Skipping unreadable line of {} ({})", path.display(), err),
+                Err(_) => {}
+            }
+            len += read;
+        }
+        if len < file.metadata()?.len() {
+            eprintln!("Cutting off the incomplete last line of {}, most likely from a crash mid-write", path.display());
+            file.set_len(len)?;
+        }
+
+        Ok(History { path, file: Mutex::new(HistoryFile { file, len, index }) })
+    }
+
+    fn append(&self, operation: Option<&Operation>, todo_id: u32, before: Option<TodoItem>, after: Option<TodoItem>) -> Result<(), StoreError> {
+        let mut history = lock(&self.file);
+        let id = operation.map(|operation| {
+            *operation.id.get_or_init(|| {
+                history.index.last_operation += 1;
+                history.index.last_operation
+            })
+        });
+        let record = Record {
+            todo_id,
+            at: Utc::now(),
+            actor: operation.and_then(|operation| operation.actor),
+            operation: id,
+            undoes: operation.and_then(|operation| operation.undoes.get().copied()),
+            before,
+            after,
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        let written = history.file.write_all(line.as_bytes()).and_then(|_| history.file.sync_data());
+        if let Err(err) = written {
+            // Don't leave a partial line behind, the next record would be appended right after it
+            let len = history.len;
+            let _ = history.file.set_len(len);
+            return Err(err.into());
+        }
+        let offset = history.len;
+        history.index.add(offset, RecordKey { todo_id, actor: record.actor, operation: id, undoes: record.undoes });
+        history.len += line.len() as u64;
+        Ok(())
+    }
+
+    // The records that start at `offsets`, in that order
+    fn read(&self, offsets: &[u64]) -> Result<Vec<Record>, StoreError> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut line = String::new();
+        let mut records = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            reader.seek(SeekFrom::Start(*offset))?;
+            line.clear();
+            reader.read_line(&mut line)?;
+            records.push(serde_json::from_str(&line)?);
+        }
+        Ok(records)
+    }
+
+    /// The records of one todo, oldest first.
+    pub fn of(&self, todo_id: u32) -> Result<Vec<Record>, StoreError> {
+        let offsets = lock(&self.file).index.by_todo.get(&todo_id).cloned().unwrap_or_default();
+        self.read(&offsets)
+    }
+
+    /// The todo as last recorded, as it was deleted if it was.
+    pub fn latest(&self, todo_id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let offset = lock(&self.file).index.by_todo.get(&todo_id).and_then(|offsets| offsets.last().copied());
+        let record = self.read(offset.as_slice())?.pop();
+        Ok(record.and_then(|record| record.after.or(record.before)))
+    }
+
+    /// `operation` if it's one of `actor` that is neither an undo nor undone already, or without
+    /// one, the last such operation of `actor`. Changes recorded before there were operations
+    /// can't be undone.
+    pub fn undoable(&self, actor: Option<u32>, operation: Option<u64>) -> Option<u64> {
+        let history = lock(&self.file);
+        let records = history.index.by_actor.get(&actor)?;
+        let undone: BTreeSet<u64> = records.iter().filter_map(|record| record.undoes).collect();
+        records
+            .iter()
+            .rev()
+            .filter(|record| record.undoes.is_none())
+            .filter_map(|record| record.operation)
+            .filter(|candidate| !undone.contains(candidate))
+            .find(|candidate| operation.is_none_or(|operation| operation == *candidate))
+    }
+
+    /// The records of one operation of `actor`, oldest first.
+    pub fn operation(&self, actor: Option<u32>, operation: u64) -> Result<Vec<Record>, StoreError> {
+        let offsets: Vec<u64> = lock(&self.file)
+            .index
+            .by_actor
+            .get(&actor)
+            .into_iter()
+            .flatten()
+            .filter(|record| record.operation == Some(operation))
+            .map(|record| record.offset)
+            .collect();
+        self.read(&offsets)
+    }
+}
+
+/// Records every change that goes through it in the `History`, as part of `operation`. Like
+/// `NotifyingStore` it sits right on top of the storage so nothing gets past it.
+pub struct HistoryStore {
+    inner: Arc<dyn TodoStore>,
+    history: Arc<History>,
+    // `None` for changes made outside of any request
+    operation: Option<Arc<Operation>>,
+}
+
+impl HistoryStore {
+    pub fn new(inner: Arc<dyn TodoStore>, history: Arc<History>, operation: Option<Arc<Operation>>) -> Self {
+        HistoryStore { inner, history, operation }
+    }
+
+    // A change that can't be recorded couldn't be undone either, so it's put back and the
+    // request fails. The id isn't known before an insert, so this can't append first like
+    // `JournalStore` does.
+    fn record(&self, id: u32, before: Option<TodoItem>, after: Option<TodoItem>) -> Result<(), StoreError> {
+        let Err(err) = self.history.append(self.operation.as_deref(), id, before.clone(), after) else {
+            return Ok(());
+        };
+        let put_back = match before {
+            Some(before) => self.inner.update(id, before).map(drop),
+            None => self.inner.delete(id).map(drop),
+        };
+        if let Err(put_back) = put_back {
+            eprintln!("Todo {} was changed but neither recorded in {} nor put back: {}", id, self.history.path.display(), put_back);
+        }
+        Err(err)
+    }
+}
+
+impl TodoView for HistoryStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        self.inner.get(id)
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.list()
+    }
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.query(query)
+    }
+}
+
+impl TodoStore for HistoryStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        let id = self.inner.insert(item.clone())?;
+        self.record(id, None, Some(item))?;
+        Ok(id)
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.update(id, item.clone())?;
+        self.record(id, before.clone(), Some(item))?;
+        Ok(before)
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.delete(id)?;
+        if before.is_some() {
+            self.record(id, before.clone(), None)?;
+        }
+        Ok(before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonFileStore;
+
+    #[test]
+    fn changes_are_recorded_with_their_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let history = Arc::new(History::open(&path).unwrap());
+        let inner: Arc<dyn TodoStore> = Arc::new(JsonFileStore::open(dir.path().join("todo_list.json")).unwrap());
+        let store = |operation: Option<Operation>| HistoryStore::new(inner.clone(), history.clone(), operation.map(Arc::new));
+
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+        let alice = store(Some(Operation::new(Some(3))));
+        let id = alice.insert(item.clone()).unwrap();
+        alice.insert(item.clone()).unwrap();
+        store(Some(Operation::new(Some(3)))).update(id, TodoItem { completed: true, ..item.clone() }).unwrap();
+        // Nothing changed, so no operation id is used up
+        store(Some(Operation::new(None))).delete(42).unwrap();
+        store(None).delete(id).unwrap();
+
+        let records = history.of(id).unwrap();
+        let operations: Vec<_> = records.iter().map(|record| (record.actor, record.operation)).collect();
+        assert_eq!(operations, vec![(Some(3), Some(1)), (Some(3), Some(2)), (None, None)]);
+        assert!(records[1].before.as_ref().is_some_and(|item| !item.completed));
+        assert!(records[2].after.is_none());
+        assert_eq!(history.operation(Some(3), 1).unwrap().len(), 2);
+
+        // Undoing walks back one operation at a time
+        assert_eq!(history.undoable(Some(3), None), Some(2));
+        let undo = Operation::new(Some(3));
+        undo.undoing(2);
+        HistoryStore::new(inner.clone(), history.clone(), Some(Arc::new(undo))).update(id, item.clone()).unwrap();
+        assert_eq!(history.undoable(Some(3), None), Some(1));
+        assert_eq!(history.undoable(Some(3), Some(2)), None);
+        assert_eq!(history.undoable(Some(4), Some(1)), None);
+        assert_eq!(history.undoable(None, None), None);
+
+        // Reopening picks up where it left off, operations included, and drops a torn last line
+        drop(history);
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(br#"{"todo_id":0,"at""#).unwrap();
+        let reopened = Arc::new(History::open(&path).unwrap());
+        assert_eq!(reopened.of(id).unwrap().len(), 4);
+        assert_eq!(reopened.undoable(Some(3), None), Some(1));
+        HistoryStore::new(inner.clone(), reopened.clone(), Some(Arc::new(Operation::new(None)))).delete(id).unwrap();
+        assert_eq!(reopened.of(id).unwrap()[4].operation, Some(4));
+
+        // A change that can't be recorded is put back
+        let kept = inner.insert(item.clone()).unwrap();
+        lock(&reopened.file).file = File::open(&path).unwrap();
+        let store = HistoryStore::new(inner.clone(), reopened.clone(), None);
+        assert!(store.insert(item.clone()).is_err());
+        assert!(store.update(kept, TodoItem { completed: true, ..item.clone() }).is_err());
+        assert!(store.delete(kept).is_err());
+        let todos = inner.list().unwrap();
+        assert_eq!(todos.len(), 2);
+        assert!(!todos[&kept].completed);
+    }
+}
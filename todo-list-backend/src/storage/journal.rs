@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use todo_list_common::TodoItem;
+
+use super::{
+    StoreError, TodoStore, TodoView, id_after, lock,
+    json::{TodoStorage, initial_loading_from_disk, update_on_disk},
+};
+use crate::atomic_file::write_atomically;
+
+// Once this many changes piled up in the journal, they get folded into the snapshot
+const COMPACT_AFTER: usize = 500;
+
+/// One line of the journal. Every entry carries the id it applies to, so replaying an entry
+/// that is already part of the snapshot changes nothing. That's what makes a crash between
+/// writing the snapshot and truncating the journal harmless.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalEntry {
+    Insert { id: u32, item: TodoItem },
+    Update { id: u32, item: TodoItem },
+    Delete { id: u32 },
+}
+
+impl JournalEntry {
+    fn apply(self, storage: &mut TodoStorage) -> Result<Option<TodoItem>, StoreError> {
+        match self {
+            JournalEntry::Insert { id, item } | JournalEntry::Update { id, item } => storage.put(id, item),
+            JournalEntry::Delete { id } => Ok(storage.todos.remove(&id)),
+        }
+    }
+}
+
+struct JournalState {
+    storage: TodoStorage,
+    journal: File,
+    // Length of the journal after the last complete entry, to cut off a half-written one
+    journal_len: u64,
+    entries_since_snapshot: usize,
+}
+
+/// Keeps the list in memory like the JSON store, but instead of rewriting the whole file on
+/// every change, it appends the change as one JSON line to `<snapshot>.journal`.
+/// On startup the snapshot is loaded and the journal replayed on top of it, and every
+/// `COMPACT_AFTER` changes the journal is folded back into a fresh snapshot.
+pub struct JournalStore {
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    state: Mutex<JournalState>,
+}
+
+impl JournalStore {
+    pub fn open(snapshot_path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let snapshot_path = snapshot_path.into();
+        let journal_path = journal_path_for(&snapshot_path);
+
+        let mut storage = initial_loading_from_disk(&snapshot_path)?;
+        let replayed = replay_journal(&journal_path, &mut storage)?;
+        if replayed > 0 {
+            println!("Replayed {} journal entries from {}", replayed, journal_path.display());
+        }
+
+        let journal = open_for_append(&journal_path)?;
+        let journal_len = journal.metadata()?.len();
+        let store = JournalStore {
+            snapshot_path,
+            journal_path,
+            state: Mutex::new(JournalState {
+                storage,
+                journal,
+                journal_len,
+                entries_since_snapshot: replayed,
+            }),
+        };
+
+        // Start out with a clean journal, which also gets rid of a cut off last line
+        if replayed > 0 || journal_len > 0 {
+            store.compact()?;
+        }
+
+        Ok(store)
+    }
+
+    /// Folds the journal into the snapshot and empties it.
+    pub fn compact(&self) -> Result<(), StoreError> {
+        let mut state = lock(&self.state);
+        self.compact_locked(&mut state)
+    }
+
+    fn compact_locked(&self, state: &mut JournalState) -> Result<(), StoreError> {
+        update_on_disk(&self.snapshot_path, &state.storage)?;
+
+        // Only now that the snapshot is safely on disk can the journal be emptied
+        write_atomically(&self.journal_path, b"", 0)?;
+        state.journal = open_for_append(&self.journal_path)?;
+        state.journal_len = 0;
+        state.entries_since_snapshot = 0;
+
+        Ok(())
+    }
+
+    fn append_locked(
+        &self,
+        state: &mut JournalState,
+        entry: JournalEntry,
+    ) -> Result<Option<TodoItem>, StoreError> {
+        // An entry that can't be applied mustn't end up in the journal either
+        if let JournalEntry::Insert { id, .. } | JournalEntry::Update { id, .. } = &entry {
+            id_after(*id)?;
+        }
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let written = state
+            .journal
+            .write_all(line.as_bytes())
+            .and_then(|_| state.journal.sync_data());
+        if let Err(err) = written {
+            // Don't leave a partial line behind, the next entry would be appended right after it
+            let _ = state.journal.set_len(state.journal_len);
+            return Err(err.into());
+        }
+        state.journal_len += line.len() as u64;
+
+        let previous = entry.apply(&mut state.storage)?;
+        state.entries_since_snapshot += 1;
+
+        if state.entries_since_snapshot >= COMPACT_AFTER {
+            // The change itself is already durable in the journal, so a failed compaction
+            // isn't worth failing the request over, we'll simply try again on the next one
+            if let Err(err) = self.compact_locked(state) {
+                eprintln!("Compacting {} failed: {}", self.journal_path.display(), err);
+            }
+        }
+
+        Ok(previous)
+    }
+}
+
+impl TodoView for JournalStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        Ok(lock(&self.state).storage.todos.get(&id).cloned())
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        Ok(lock(&self.state).storage.todos.clone())
+    }
+}
+
+impl TodoStore for JournalStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        let mut state = lock(&self.state);
+        // Applying the entry bumps the counter, so nothing is allocated if the append fails
+        let id = state.storage.next_id;
+        self.append_locked(&mut state, JournalEntry::Insert { id, item })?;
+        Ok(id)
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let mut state = lock(&self.state);
+        self.append_locked(&mut state, JournalEntry::Update { id, item })
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let mut state = lock(&self.state);
+        if !state.storage.todos.contains_key(&id) {
+            return Ok(None);
+        }
+        self.append_locked(&mut state, JournalEntry::Delete { id })
+    }
+}
+
+fn open_for_append(journal_path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(journal_path)
+}
+
+/// Where the journal for a given snapshot lives, e.g. `todo_list.json.journal`.
+pub fn journal_path_for(snapshot_path: &Path) -> PathBuf {
+    let mut file_name = snapshot_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".journal");
+    snapshot_path.with_file_name(file_name)
+}
+
+/// Applies every entry of the journal to `storage`, returning how many there were.
+///
+/// A crash in the middle of an append can leave the last line cut off, that one is skipped.
+/// Anything unreadable before it means the journal itself is damaged, and we refuse to guess.
+fn replay_journal(journal_path: &Path, storage: &mut TodoStorage) -> Result<usize, StoreError> {
+    let file = match File::open(journal_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+    let mut replayed = 0;
+    while let Some((index, line)) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<JournalEntry>(&line) {
+            Ok(entry) => {
+                entry.apply(storage)?;
+                replayed += 1;
+            }
+            Err(err) if lines.peek().is_none() => {
+                eprintln!(
+                    "Ignoring incomplete last entry of {} ({}), most likely from a crash mid-write",
+                    journal_path.display(),
+                    err
+                );
+            }
+            Err(err) => {
+                return Err(StoreError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "line {} of {} could not be parsed ({}). Refusing to start so no changes are lost.",
+                        index + 1,
+                        journal_path.display(),
+                        err
+                    ),
+                )));
+            }
+        }
+    }
+
+    Ok(replayed)
+}
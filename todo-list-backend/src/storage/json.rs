@@ -0,0 +1,168 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use todo_list_common::TodoItem;
+
+use super::{StoreError, TodoStore, TodoView, id_after, lock};
+use crate::atomic_file::{backup_path, write_atomically};
+
+// How many previous versions of the todo list we keep around (todo_list.json.1, .2, ...)
+const BACKUP_COUNT: usize = 3;
+
+/// Everything we persist: the todos themselves plus the id that the next insert will receive.
+/// Keeping the counter on disk means ids are never reused, even after deletes or restarts.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct TodoStorage {
+    pub(super) next_id: u32,
+    pub(super) todos: BTreeMap<u32, TodoItem>,
+}
+
+impl TodoStorage {
+    pub(super) fn allocate_id(&mut self) -> Result<u32, StoreError> {
+        let id = self.next_id;
+        self.next_id = id_after(id)?;
+        Ok(id)
+    }
+
+    /// Sets the item at `id`, making sure the id is never allocated again.
+    pub(super) fn put(&mut self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        if id >= self.next_id {
+            self.next_id = id_after(id)?;
+        }
+        Ok(self.todos.insert(id, item))
+    }
+}
+
+/// The original storage: the whole list lives in memory and is written out as one JSON file
+/// after every change. Simple and human readable, but every write rewrites everything.
+pub struct JsonFileStore {
+    storage_path: PathBuf,
+    // Mutex is being used to avoid concurrency issues, the lock is held for the whole
+    // modify + write to disk so two requests can't interleave their writes.
+    // BTreeMap is used here, because at lower n-counts, it's faster than a hashmap.
+    storage: Mutex<TodoStorage>,
+}
+
+impl JsonFileStore {
+    pub fn open(storage_path: impl Into<PathBuf>) -> io::Result<Self> {
+        let storage_path = storage_path.into();
+        let storage = initial_loading_from_disk(&storage_path)?;
+
+        Ok(JsonFileStore {
+            storage_path,
+            storage: Mutex::new(storage),
+        })
+    }
+
+    fn modify<T>(&self, change: impl FnOnce(&mut TodoStorage) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        let mut storage = lock(&self.storage);
+        // The change is made on a copy, so if it can't be written nothing has changed in memory either
+        let mut changed = storage.clone();
+        let result = change(&mut changed)?;
+        update_on_disk(&self.storage_path, &changed)?;
+        *storage = changed;
+        Ok(result)
+    }
+}
+
+impl TodoView for JsonFileStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        Ok(lock(&self.storage).todos.get(&id).cloned())
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        Ok(lock(&self.storage).todos.clone())
+    }
+}
+
+impl TodoStore for JsonFileStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        self.modify(|storage| {
+            let id = storage.allocate_id()?; // monotonic like an autoincrement column, so deletes never cause reuse
+            storage.todos.insert(id, item);
+            Ok(id)
+        })
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        // Updating can still create an entry at an arbitrary id, put() makes sure it's never handed out again
+        self.modify(|storage| storage.put(id, item))
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        self.modify(|storage| Ok(storage.todos.remove(&id)))
+    }
+}
+
+pub(super) fn initial_loading_from_disk(storage_path: &Path) -> io::Result<TodoStorage> {
+    // Ensure the directory exists
+    if let Some(parent) = storage_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // If file does not exist, create it with empty JSON object
+    if !storage_path.exists() {
+        write_atomically(storage_path, b"{}", 0)?;
+        println!("Created empty todo list at {}", storage_path.display());
+    }
+
+    // Open file and deserialize. If that fails we must not carry on with an empty list, the
+    // next write would replace whatever is still recoverable in the file.
+    let file = File::open(storage_path)?;
+    let storage = serde_json::from_reader(file).and_then(parse_storage).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} could not be parsed ({}). Refusing to start so the data isn't overwritten, \
+                 fix the file or restore one of the backups ({}).",
+                storage_path.display(),
+                err,
+                backup_path(storage_path, 1).display()
+            ),
+        )
+    })?;
+
+    Ok(storage)
+}
+
+// Older versions stored the bare map of todos, so we still accept that and derive the counter.
+// deny_unknown_fields on TodoStorage is what stops a legacy map from parsing as the current format.
+// This goes through a Value rather than an untagged enum, as those can't handle the u32 map keys.
+fn parse_storage(value: serde_json::Value) -> Result<TodoStorage, serde_json::Error> {
+    let current_err = match TodoStorage::deserialize(&value) {
+        Ok(storage) => return Ok(storage),
+        Err(err) => err,
+    };
+
+    let Ok(todo_map) = BTreeMap::<String, TodoItem>::deserialize(&value) else {
+        return Err(current_err);
+    };
+
+    // Convert string keys to u32
+    let mut todos = BTreeMap::new();
+    for (key_str, value) in todo_map {
+        if let Ok(key) = key_str.parse::<u32>() {
+            todos.insert(key, value);
+        }
+    }
+
+    let next_id = todos.keys().next_back().map_or(0, |last| last.saturating_add(1));
+    Ok(TodoStorage { next_id, todos })
+}
+
+pub(super) fn update_on_disk(storage_path: &Path, storage: &TodoStorage) -> Result<(), StoreError> {
+    // Serialize to pretty JSON, serde_json takes care of turning the u32 keys into strings
+    let json_data = serde_json::to_string_pretty(storage)?;
+
+    // Swap the file out atomically, the previous version is kept as a backup
+    write_atomically(storage_path, json_data.as_bytes(), BACKUP_COUNT)?;
+
+    Ok(())
+}
@@ -0,0 +1,281 @@
+mod collection;
+mod history;
+mod journal;
+mod json;
+mod notify;
+mod owned;
+mod sqlite;
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use todo_list_common::{TodoItem, TodoQuery};
+
+use crate::config::StorageBackend;
+
+pub use collection::JsonCollection;
+pub use history::{History, HistoryStore, Operation, Record};
+pub use journal::{JournalStore, journal_path_for};
+pub use json::JsonFileStore;
+pub use notify::{Change, NotifyingStore};
+pub use owned::{OwnedStore, SharedList};
+pub use sqlite::SqliteStore;
+
+/// Reading the todos, which is all the checks ahead of a change need. Every `TodoStore` can do
+/// this, as can views that are only there to be checked against, like the one of an undo.
+pub trait TodoView: Send + Sync {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError>;
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError>;
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        let mut todos = self.list()?;
+        todos.retain(|_, item| query.matches(item));
+        Ok(todos)
+    }
+}
+
+/// Where the todos live. The handlers only ever talk to this trait, so the backing storage
+/// can be swapped at startup without touching any of them.
+///
+/// Every method is atomic on its own, implementations take care of their own locking.
+pub trait TodoStore: TodoView {
+    /// Stores a new item under a freshly allocated id, which is never reused.
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError>;
+
+    /// Replaces the item at `id`, or creates it if there is none. Returns the previous value.
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError>;
+
+    /// Returns the removed value, if there was one.
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError>;
+}
+
+/// Opens the configured storage inside `data_dir`.
+pub fn open_store(backend: StorageBackend, data_dir: &Path) -> Result<Arc<dyn TodoStore>, StoreError> {
+    let json_path = data_dir.join("todo_list.json");
+    let sqlite_path = data_dir.join("todo_list.sqlite3");
+
+    // The journal mode shares its snapshot with the JSON storage. Should we be switching back from it,
+    // changes still sitting in the journal have to be folded into the snapshot first.
+    if backend != StorageBackend::Journal && journal_path_for(&json_path).exists() {
+        JournalStore::open(&json_path)?;
+        std::fs::remove_file(journal_path_for(&json_path))?;
+    }
+
+    let store: Arc<dyn TodoStore> = match backend {
+        StorageBackend::Json => Arc::new(JsonFileStore::open(json_path)?),
+        StorageBackend::Journal => Arc::new(JournalStore::open(json_path)?),
+        StorageBackend::Sqlite => {
+            let store = SqliteStore::open(&sqlite_path)?;
+
+            // First start on SQLite, bring over whatever the JSON storage had so switching is painless
+            if store.is_empty()? && json_path.exists() {
+                let todos = JsonFileStore::open(&json_path)?.list()?;
+                println!("Importing {} todos from {} into SQLite", todos.len(), json_path.display());
+                for (id, item) in todos {
+                    store.update(id, item)?;
+                }
+            }
+
+            Arc::new(store)
+        }
+    };
+
+    migrate_timestamps(store.as_ref())?;
+    Ok(store)
+}
+
+/// Todos stored before the backend kept `updated_at` and `completed_at` load with those missing.
+/// The best guess for them is the creation time, which is also the earliest they could have been.
+fn migrate_timestamps(store: &dyn TodoStore) -> Result<(), StoreError> {
+    for (id, mut item) in store.list()? {
+        let mut changed = false;
+        if item.updated_at < item.created_at {
+            item.updated_at = item.created_at;
+            changed = true;
+        }
+        if item.completed != item.completed_at.is_some() {
+            item.completed_at = item.completed.then_some(item.updated_at);
+            changed = true;
+        }
+        if changed {
+            store.update(id, item)?;
+        }
+    }
+    Ok(())
+}
+
+/// Locks one of the stores' mutexes. A panic while holding the lock can't have left a
+/// half-applied change behind (every change is applied in one step), so a poisoned lock is
+/// still safe to use, and refusing to would take the whole backend down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The id the counter moves on to once `id` is taken. There is none after `u32::MAX`, so
+/// nothing can be stored there, the handlers turn that id away before it gets this far.
+fn id_after(id: u32) -> Result<u32, StoreError> {
+    id.checked_add(1).ok_or(StoreError::OutOfIds)
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Serde(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    /// The todo at this id belongs to another user, see `OwnedStore`
+    NotOwned(u32),
+    /// The list with this id was only shared to look at, see `OwnedStore`
+    ReadOnly(u32),
+    /// The todo at this id would go to another owner, which only its own owner can do, see `OwnedStore`
+    OwnerOnly(u32),
+    /// Every id up to `u32::MAX` has been handed out, see `id_after`
+    OutOfIds,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "I/O error: {}", err),
+            StoreError::Serde(err) => write!(f, "serialization error: {}", err),
+            StoreError::Sqlite(err) => write!(f, "SQLite error: {}", err),
+            StoreError::NotOwned(id) => write!(f, "todo {} belongs to another user", id),
+            StoreError::ReadOnly(list_id) => write!(f, "list {} is shared read-only", list_id),
+            StoreError::OwnerOnly(id) => write!(f, "only the owner of todo {} can move it to another owner", id),
+            StoreError::OutOfIds => write!(f, "no ids are left to allocate"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError::Serde(err)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError::Sqlite(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn item(title: &str, completed: bool) -> TodoItem {
+        TodoItem {
+            title: title.to_string(),
+            description: String::new(),
+            due_date: Utc::now(),
+            created_at: Utc::now(),
+            completed,
+            ..Default::default()
+        }
+    }
+
+    // Both implementations have to behave the same, so they share one set of checks
+    fn check_store(store: &dyn TodoStore) {
+        let first = store.insert(item("first", false)).unwrap();
+        let second = store.insert(item("second", true)).unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(store.delete(second).unwrap().unwrap().title, "second");
+        let third = store.insert(item("third", false)).unwrap();
+        assert!(third > second, "ids must not be reused after a delete");
+
+        store.update(10, item("upserted", true)).unwrap();
+        assert!(store.insert(item("fourth", false)).unwrap() > 10);
+        assert!(matches!(store.update(u32::MAX, item("last", false)), Err(StoreError::OutOfIds)));
+
+        let completed = store.query(&TodoQuery::new().completed(true)).unwrap();
+        assert_eq!(completed.keys().copied().collect::<Vec<_>>(), vec![10]);
+        assert_eq!(store.get(first).unwrap().unwrap().title, "first");
+        assert!(store.get(second).unwrap().is_none());
+    }
+
+    #[test]
+    fn json_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo_list.json");
+        check_store(&JsonFileStore::open(&path).unwrap());
+
+        let reopened = JsonFileStore::open(&path).unwrap();
+        assert_eq!(reopened.list().unwrap().len(), 4);
+        assert!(reopened.insert(item("after restart", false)).unwrap() > 11);
+
+        // The format from before the id counter was persisted still loads
+        std::fs::write(&path, r#"{"3": {"title": "old", "description": "", "due_date": "2025-11-15T18:00:00Z",
+            "created_at": "2025-11-12T14:30:00Z", "completed": false}}"#).unwrap();
+        let legacy = JsonFileStore::open(&path).unwrap();
+        assert_eq!(legacy.insert(item("new", false)).unwrap(), 4);
+
+        // A write that doesn't make it to disk doesn't show up in memory either
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        assert!(legacy.insert(item("lost", false)).is_err());
+        assert_eq!(legacy.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn missing_timestamps_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("todo_list.json"), r#"{"next_id": 2, "todos": {
+            "0": {"title": "open", "description": "", "due_date": "2025-11-15T18:00:00Z",
+                "created_at": "2025-11-12T14:30:00Z", "completed": false},
+            "1": {"title": "done", "description": "", "due_date": "2025-11-15T18:00:00Z",
+                "created_at": "2025-11-12T14:30:00Z", "completed": true}}}"#).unwrap();
+
+        let store = open_store(StorageBackend::Json, dir.path()).unwrap();
+        let created_at = "2025-11-12T14:30:00Z".parse().unwrap();
+        let open = store.get(0).unwrap().unwrap();
+        assert_eq!((open.updated_at, open.completed_at), (created_at, None));
+        let done = store.get(1).unwrap().unwrap();
+        assert_eq!((done.updated_at, done.completed_at), (created_at, Some(created_at)));
+    }
+
+    #[test]
+    fn journal_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo_list.json");
+        check_store(&JournalStore::open(&path).unwrap());
+
+        // Nothing was compacted yet, so this is all replayed from the journal
+        let journal = std::fs::read_to_string(journal_path_for(&path)).unwrap();
+        assert_eq!(journal.lines().count(), 6);
+
+        // Simulate a crash in the middle of an append
+        let mut torn = journal.clone();
+        torn.push_str(r#"{"op":"insert","id":12,"item":{"tit"#);
+        std::fs::write(journal_path_for(&path), torn).unwrap();
+
+        let reopened = JournalStore::open(&path).unwrap();
+        assert_eq!(reopened.list().unwrap().len(), 4);
+        assert_eq!(std::fs::read_to_string(journal_path_for(&path)).unwrap(), "");
+        assert!(reopened.insert(item("after restart", false)).unwrap() > 11);
+
+        // After compaction the plain JSON store sees everything in the snapshot
+        drop(reopened);
+        JournalStore::open(&path).unwrap();
+        assert_eq!(JsonFileStore::open(&path).unwrap().list().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn sqlite_store() {
+        let dir = tempfile::tempdir().unwrap();
+        check_store(&SqliteStore::open(dir.path().join("todo_list.sqlite3")).unwrap());
+    }
+}
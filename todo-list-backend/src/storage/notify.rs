@@ -0,0 +1,70 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use todo_list_common::{TodoItem, TodoQuery};
+use tokio::sync::broadcast;
+
+use super::{StoreError, TodoStore, TodoView};
+
+/// A change to one todo, from what it was to what it is now. `None` on either side is a todo
+/// that didn't exist before, or was deleted.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub id: u32,
+    pub before: Option<TodoItem>,
+    pub after: Option<TodoItem>,
+}
+
+/// Announces every change that goes through it to whoever subscribed to `changes`. It sits
+/// right on top of the storage, below `OwnedStore`, so no handler can change a todo without
+/// it being announced.
+pub struct NotifyingStore {
+    inner: Arc<dyn TodoStore>,
+    changes: broadcast::Sender<Change>,
+}
+
+impl NotifyingStore {
+    pub fn new(inner: Arc<dyn TodoStore>, changes: broadcast::Sender<Change>) -> Self {
+        NotifyingStore { inner, changes }
+    }
+
+    fn announce(&self, id: u32, before: Option<TodoItem>, after: Option<TodoItem>) {
+        // Nobody listening isn't an error, the change is stored either way
+        let _ = self.changes.send(Change { id, before, after });
+    }
+}
+
+impl TodoView for NotifyingStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        self.inner.get(id)
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.list()
+    }
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.query(query)
+    }
+}
+
+impl TodoStore for NotifyingStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        let id = self.inner.insert(item.clone())?;
+        self.announce(id, None, Some(item));
+        Ok(id)
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.update(id, item.clone())?;
+        self.announce(id, before.clone(), Some(item));
+        Ok(before)
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.delete(id)?;
+        if before.is_some() {
+            self.announce(id, before.clone(), None);
+        }
+        Ok(before)
+    }
+}
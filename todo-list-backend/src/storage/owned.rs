@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use todo_list_common::{TodoItem, TodoQuery};
+
+use super::{StoreError, TodoStore, TodoView};
+
+/// A list of another user that was shared with the one an `OwnedStore` is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharedList {
+    pub owner_id: Option<u32>,
+    /// Shared as an editor rather than a viewer
+    pub writable: bool,
+}
+
+/// The todos one user gets to see, on top of the store that holds everyone's: their own, and
+/// those on the lists shared with them. Other todos don't exist as far as this store is
+/// concerned, so handlers (and the checks they run) can't reach past it by accident.
+///
+/// A todo belongs to the owner of the list it's on, whoever put it there, so it stays with the
+/// list when that is shared or unshared. On the Inbox, it belongs to whoever made it. Only the
+/// owner of a todo can move it to a list with another owner.
+/// `None` is nobody in particular: the todos stored without a token.
+pub struct OwnedStore {
+    inner: Arc<dyn TodoStore>,
+    owner_id: Option<u32>,
+    shared: BTreeMap<u32, SharedList>,
+}
+
+impl OwnedStore {
+    pub fn new(inner: Arc<dyn TodoStore>, owner_id: Option<u32>, shared: BTreeMap<u32, SharedList>) -> Self {
+        OwnedStore { inner, owner_id, shared }
+    }
+
+    /// Whether this user gets to see `item` at all.
+    pub fn can_read(&self, item: &TodoItem) -> bool {
+        item.owner_id == self.owner_id || self.shared.contains_key(&item.list_id)
+    }
+
+    /// Whether this user may change or delete `item`.
+    pub fn can_write(&self, item: &TodoItem) -> bool {
+        item.owner_id == self.owner_id || self.shared.get(&item.list_id).is_some_and(|list| list.writable)
+    }
+
+    fn owner_for(&self, list_id: u32) -> Result<Option<u32>, StoreError> {
+        match self.shared.get(&list_id) {
+            Some(list) if !list.writable => Err(StoreError::ReadOnly(list_id)),
+            Some(list) => Ok(list.owner_id),
+            None => Ok(self.owner_id),
+        }
+    }
+
+    // Writing to an id that another user's todo is at would replace it, so that's refused,
+    // unless the todo is on a list shared with this user as an editor. Returns the todo there.
+    fn check_writable(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        match self.inner.get(id)? {
+            Some(item) if !self.can_read(&item) => Err(StoreError::NotOwned(id)),
+            Some(item) if !self.can_write(&item) => Err(StoreError::ReadOnly(item.list_id)),
+            current => Ok(current),
+        }
+    }
+}
+
+impl TodoView for OwnedStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        Ok(self.inner.get(id)?.filter(|item| self.can_read(item)))
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        let mut todos = self.inner.list()?;
+        todos.retain(|_, item| self.can_read(item));
+        Ok(todos)
+    }
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        let mut todos = self.inner.query(query)?;
+        todos.retain(|_, item| self.can_read(item));
+        Ok(todos)
+    }
+}
+
+impl TodoStore for OwnedStore {
+    fn insert(&self, mut item: TodoItem) -> Result<u32, StoreError> {
+        item.owner_id = self.owner_for(item.list_id)?;
+        self.inner.insert(item)
+    }
+
+    fn update(&self, id: u32, mut item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let current = self.check_writable(id)?;
+        item.owner_id = self.owner_for(item.list_id)?;
+        // Moving a todo to a list of someone else hands it over to them, an editor can't do
+        // that with a todo that was never theirs
+        if let Some(current) = current
+            && current.owner_id != self.owner_id
+            && current.owner_id != item.owner_id
+        {
+            return Err(StoreError::OwnerOnly(id));
+        }
+        self.inner.update(id, item)
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        self.check_writable(id)?;
+        self.inner.delete(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonFileStore;
+
+    #[test]
+    fn shared_lists_are_seen_and_changed_by_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner: Arc<dyn TodoStore> = Arc::new(JsonFileStore::open(dir.path().join("todo_list.json")).unwrap());
+        let todo = |list_id| TodoItem { title: "task".to_string(), list_id, ..Default::default() };
+        // Alice (0) has lists 1 and 2, shares 1 with Bob (1) as an editor and 2 as a viewer
+        let alice = OwnedStore::new(inner.clone(), Some(0), BTreeMap::new());
+        let shared = BTreeMap::from([
+            (1, SharedList { owner_id: Some(0), writable: true }),
+            (2, SharedList { owner_id: Some(0), writable: false }),
+        ]);
+        let bob = OwnedStore::new(inner.clone(), Some(1), shared);
+
+        let private = alice.insert(todo(0)).unwrap();
+        let editable = alice.insert(todo(1)).unwrap();
+        let viewable = alice.insert(todo(2)).unwrap();
+        assert_eq!(bob.list().unwrap().keys().copied().collect::<Vec<_>>(), vec![editable, viewable]);
+
+        bob.update(editable, todo(1)).unwrap();
+        assert!(matches!(bob.update(viewable, todo(2)), Err(StoreError::ReadOnly(2))));
+        assert!(matches!(bob.delete(private), Err(StoreError::NotOwned(_))));
+        assert!(matches!(bob.insert(todo(2)), Err(StoreError::ReadOnly(2))));
+        // What Bob adds to Alice's list is hers, and he can't take it off to make it his
+        let added = bob.insert(todo(1)).unwrap();
+        assert_eq!(alice.get(added).unwrap().unwrap().owner_id, Some(0));
+        assert!(matches!(bob.update(added, todo(0)), Err(StoreError::OwnerOnly(_))));
+        alice.update(added, todo(0)).unwrap();
+        assert!(bob.get(added).unwrap().is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, path::Path, sync::Mutex};
+
+use rusqlite::{Connection, OptionalExtension, Transaction, params, params_from_iter, types::Value};
+use todo_list_common::{TodoItem, TodoQuery};
+
+use super::{StoreError, TodoStore, TodoView, id_after, lock};
+
+/// Embedded SQLite storage. Each todo is its own row, so a change only writes that row
+/// instead of the whole list.
+///
+/// The item itself is kept as JSON in `data`, that way new fields on `TodoItem` don't need a
+/// schema migration. Only what we filter on gets a column of its own.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS todos (
+                 id INTEGER PRIMARY KEY,
+                 completed INTEGER NOT NULL,
+                 due_date INTEGER NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS meta (
+                 key TEXT PRIMARY KEY,
+                 value INTEGER NOT NULL
+             );
+             INSERT OR IGNORE INTO meta (key, value) VALUES ('next_id', 0);",
+        )?;
+
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn is_empty(&self) -> Result<bool, StoreError> {
+        let connection = lock(&self.connection);
+        let count: u32 = connection.query_row("SELECT COUNT(*) FROM todos", [], |row| row.get(0))?;
+        Ok(count == 0)
+    }
+}
+
+fn upsert(tx: &Transaction, id: u32, item: &TodoItem) -> Result<(), StoreError> {
+    tx.execute(
+        "INSERT INTO todos (id, completed, due_date, data) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(id) DO UPDATE SET completed = ?2, due_date = ?3, data = ?4",
+        params![
+            id,
+            item.completed,
+            item.due_date.timestamp_millis(),
+            serde_json::to_string(item)?
+        ],
+    )?;
+    Ok(())
+}
+
+fn select(tx: &Connection, id: u32) -> Result<Option<TodoItem>, StoreError> {
+    let data: Option<String> = tx
+        .query_row("SELECT data FROM todos WHERE id = ?1", [id], |row| row.get(0))
+        .optional()?;
+    Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+}
+
+fn select_where(
+    connection: &Connection,
+    condition: &str,
+    values: Vec<Value>,
+) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+    let mut statement =
+        connection.prepare(&format!("SELECT id, data FROM todos WHERE {} ORDER BY id", condition))?;
+    let rows = statement.query_map(params_from_iter(values), |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut todos = BTreeMap::new();
+    for row in rows {
+        let (id, data) = row?;
+        todos.insert(id, serde_json::from_str(&data)?);
+    }
+    Ok(todos)
+}
+
+impl TodoView for SqliteStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        select(&lock(&self.connection), id)
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        select_where(&lock(&self.connection), "1", Vec::new())
+    }
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        let mut conditions = vec!["1".to_string()];
+        let mut values = Vec::new();
+
+        if let Some(completed) = query.completed {
+            conditions.push("completed = ?".to_string());
+            values.push(Value::Integer(completed as i64));
+        }
+        // The column only has whole milliseconds, so these are inclusive and `matches` has the final say
+        if let Some(due_before) = query.due_before {
+            conditions.push("due_date <= ?".to_string());
+            values.push(Value::Integer(due_before.timestamp_millis()));
+        }
+        if let Some(due_after) = query.due_after {
+            conditions.push("due_date >= ?".to_string());
+            values.push(Value::Integer(due_after.timestamp_millis()));
+        }
+
+        // The text search looks into the JSON, so that part is left to `matches` as well
+        let mut todos = select_where(&lock(&self.connection), &conditions.join(" AND "), values)?;
+        todos.retain(|_, item| query.matches(item));
+        Ok(todos)
+    }
+}
+
+impl TodoStore for SqliteStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        let mut connection = lock(&self.connection);
+        let tx = connection.transaction()?;
+
+        let id: u32 = tx.query_row("SELECT value FROM meta WHERE key = 'next_id'", [], |row| row.get(0))?;
+        upsert(&tx, id, &item)?;
+        tx.execute("UPDATE meta SET value = ?1 WHERE key = 'next_id'", [id_after(id)?])?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let mut connection = lock(&self.connection);
+        let tx = connection.transaction()?;
+
+        let previous = select(&tx, id)?;
+        upsert(&tx, id, &item)?;
+        // Same as the JSON store, an id created through update must never be allocated again
+        tx.execute(
+            "UPDATE meta SET value = MAX(value, ?1) WHERE key = 'next_id'",
+            [id_after(id)?],
+        )?;
+
+        tx.commit()?;
+        Ok(previous)
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let mut connection = lock(&self.connection);
+        let tx = connection.transaction()?;
+
+        let previous = select(&tx, id)?;
+        tx.execute("DELETE FROM todos WHERE id = ?1", [id])?;
+
+        tx.commit()?;
+        Ok(previous)
+    }
+}
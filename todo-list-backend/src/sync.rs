@@ -0,0 +1,230 @@
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{AUTHORIZATION, EntityTag, HeaderValue, IfMatch};
+use actix_web::{HttpRequest, HttpResponse, get, rt, web};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, Session};
+use todo_list_common::{
+    ApiError, ClientMessage, ErrorCode, Mutation, SYNC_PROTOCOL_VERSION, ServerMessage, TodoEntry, TokenScope,
+};
+
+use crate::auth::Caller;
+use crate::error::AppError;
+use crate::events::Subscriber;
+use crate::state::AppState;
+use crate::{delete_todo, insert_todo, modify_todo};
+
+// The client is pinged this often, and one that hasn't been heard from in a while is gone
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+// A message is a todo at most, big checklists and all
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// One `/sync` connection, after the upgrade.
+struct Connection {
+    state: web::Data<AppState>,
+    // Kept to check the token again for every mutation, it may be revoked while the connection is open
+    authorization: Option<HeaderValue>,
+    subscribed: bool,
+}
+
+impl Connection {
+    // Answers one message from the client, and says whether to close the connection after it
+    fn answer(&mut self, text: &str) -> (ServerMessage, bool) {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(err) => return (error(None, ApiError::validation(format!("Invalid message: {}", err))), false),
+        };
+        match message {
+            // A client that speaks another version can't make sense of what would follow
+            ClientMessage::Subscribe { version } if version != SYNC_PROTOCOL_VERSION => {
+                let message = format!("This backend speaks version {} of the sync protocol, not {}", SYNC_PROTOCOL_VERSION, version);
+                (error(None, ApiError::validation(message)), true)
+            }
+            ClientMessage::Subscribe { .. } => {
+                self.subscribed = true;
+                (ServerMessage::Subscribed { version: SYNC_PROTOCOL_VERSION }, false)
+            }
+            ClientMessage::Mutate { request_id, .. } if !self.subscribed => {
+                (error(Some(request_id), ApiError::validation("Send subscribe first")), false)
+            }
+            ClientMessage::Mutate { request_id, mutation } => (self.mutate(request_id, *mutation), false),
+        }
+    }
+
+    // The same as the HTTP endpoints for the change, down to the checks they run
+    fn mutate(&self, request_id: u64, mutation: Mutation) -> ServerMessage {
+        let applied = Caller::identify(&self.state, self.authorization.as_ref()).and_then(|caller| {
+            if !caller.credential.allows(TokenScope::Write) {
+                return Err(ApiError::forbidden("This API token doesn't have the write scope").into());
+            }
+            match mutation {
+                Mutation::Create { item } => {
+                    insert_todo(&self.state, &caller, item).map(|(id, item)| (id, Some(item.revision)))
+                }
+                Mutation::Update { id, revision, patch } => {
+                    modify_todo(&self.state, &caller, id, if_match(revision).as_ref(), |item| {
+                        patch.apply(item);
+                        Ok(())
+                    })
+                    .map(|(item, _)| (id, Some(item.revision)))
+                }
+                Mutation::Delete { id, revision, children } => {
+                    delete_todo(&self.state, &caller, id, if_match(revision).as_ref(), children).map(|()| (id, None))
+                }
+            }
+        });
+
+        match applied {
+            Ok((id, revision)) => ServerMessage::Ack { request_id, id, revision },
+            Err(AppError(err)) => match conflict(&err) {
+                Some(TodoEntry { id, item }) => ServerMessage::Conflict { request_id, id, current: item },
+                None => error(Some(request_id), err),
+            },
+        }
+    }
+
+    async fn run(mut self, mut session: Session, mut messages: AggregatedMessageStream, mut subscriber: Subscriber) {
+        let mut ping = rt::time::interval(PING_INTERVAL);
+        let mut last_heard = Instant::now();
+
+        let reason = loop {
+            tokio::select! {
+                message = messages.recv() => {
+                    last_heard = Instant::now();
+                    let sent = match message {
+                        Some(Ok(AggregatedMessage::Text(text))) => {
+                            let (answer, close) = self.answer(&text);
+                            let sent = send(&mut session, &answer).await;
+                            if close {
+                                break Some(CloseReason::from(CloseCode::Unsupported));
+                            }
+                            sent
+                        }
+                        Some(Ok(AggregatedMessage::Binary(_))) => {
+                            send(&mut session, &error(None, ApiError::validation("Messages are sent as JSON text"))).await
+                        }
+                        Some(Ok(AggregatedMessage::Ping(bytes))) => session.pong(&bytes).await.is_ok(),
+                        Some(Ok(AggregatedMessage::Pong(_))) => true,
+                        Some(Ok(AggregatedMessage::Close(reason))) => break reason,
+                        Some(Err(err)) => break Some(CloseReason { code: CloseCode::Protocol, description: Some(err.to_string()) }),
+                        None => break None,
+                    };
+                    if !sent {
+                        return;
+                    }
+                }
+                event = subscriber.next_event() => {
+                    let Some(event) = event else {
+                        break Some(CloseReason::from(CloseCode::Restart));
+                    };
+                    // Changes made before `subscribe` are of no interest yet
+                    if self.subscribed && !send(&mut session, &ServerMessage::Change { event }).await {
+                        return;
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > CLIENT_TIMEOUT {
+                        break Some(CloseReason::from(CloseCode::Away));
+                    }
+                    if session.ping(b"").await.is_err() {
+                        return;
+                    }
+                }
+            }
+        };
+        let _ = session.close(reason).await;
+    }
+}
+
+// Whether the message got out, the connection is closed if not
+async fn send(session: &mut Session, message: &ServerMessage) -> bool {
+    let text = serde_json::to_string(message).expect("messages serialize to JSON");
+    session.text(text).await.is_ok()
+}
+
+fn error(request_id: Option<u64>, error: ApiError) -> ServerMessage {
+    ServerMessage::Error { request_id, error }
+}
+
+fn if_match(revision: Option<u64>) -> Option<IfMatch> {
+    revision.map(|revision| IfMatch::Items(vec![EntityTag::new_strong(revision.to_string())]))
+}
+
+// A 412 over HTTP, which carries the todo as it is now
+fn conflict(err: &ApiError) -> Option<TodoEntry> {
+    match err.code {
+        ErrorCode::PreconditionFailed => serde_json::from_value(err.details.clone()?).ok(),
+        _ => None,
+    }
+}
+
+// Clients both make changes and hear about everyone else's over the one connection, see
+// `ClientMessage` and `ServerMessage` for the protocol
+#[get("/sync")]
+async fn sync(
+    req: HttpRequest,
+    body: web::Payload,
+    state: web::Data<AppState>,
+    caller: Caller,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    // Subscribed to right away, so nothing is missed between connecting and `subscribe`
+    let subscriber = Subscriber::new(state.clone(), caller.user_id);
+    let connection = Connection {
+        state,
+        authorization: req.headers().get(AUTHORIZATION).cloned(),
+        subscribed: false,
+    };
+    let messages = messages.max_frame_size(MAX_MESSAGE_SIZE).aggregate_continuations().max_continuation_size(MAX_MESSAGE_SIZE);
+    rt::spawn(connection.run(session, messages, subscriber));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use todo_list_common::{TodoItem, TodoPatch};
+
+    use super::*;
+    use crate::auth::Auth;
+    use crate::lists;
+    use crate::storage::{History, JsonFileStore};
+    use crate::webhooks::Webhooks;
+
+    fn connection(dir: &std::path::Path) -> Connection {
+        let store = Arc::new(JsonFileStore::open(dir.join("todo_list.json")).unwrap());
+        let history = History::open(dir.join("history.jsonl")).unwrap();
+        let state = AppState::new(store, history, lists::open_lists(dir).unwrap(), Auth::open(dir, false).unwrap(), Webhooks::open(dir, false).unwrap());
+        Connection { state: web::Data::new(state), authorization: None, subscribed: false }
+    }
+
+    fn mutate(connection: &mut Connection, request_id: u64, mutation: Mutation) -> ServerMessage {
+        let message = ClientMessage::Mutate { request_id, mutation: Box::new(mutation) };
+        connection.answer(&serde_json::to_string(&message).unwrap()).0
+    }
+
+    #[test]
+    fn mutations_are_acked_or_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut connection = connection(dir.path());
+        let item = TodoItem { title: "task".to_string(), ..Default::default() };
+
+        let create = Mutation::Create { item: item.clone() };
+        assert!(matches!(mutate(&mut connection, 1, create.clone()), ServerMessage::Error { request_id: Some(1), .. }));
+        let (_, close) = connection.answer(r#"{"type": "subscribe", "version": 0}"#);
+        assert!(close);
+        connection.answer(r#"{"type": "subscribe", "version": 1}"#);
+
+        let ServerMessage::Ack { id, revision: Some(1), .. } = mutate(&mut connection, 2, create) else {
+            panic!("create wasn't acked");
+        };
+        let update = |revision| Mutation::Update { id, revision: Some(revision), patch: TodoPatch { completed: Some(true), ..Default::default() } };
+        assert!(matches!(mutate(&mut connection, 3, update(1)), ServerMessage::Ack { revision: Some(2), .. }));
+        // Made against the revision before that, so it's refused along with the current todo
+        let conflict = mutate(&mut connection, 4, update(1));
+        assert!(matches!(conflict, ServerMessage::Conflict { request_id: 4, current, .. } if current.revision == 2));
+        let delete = Mutation::Delete { id, revision: Some(2), children: None };
+        assert!(matches!(mutate(&mut connection, 5, delete), ServerMessage::Ack { revision: None, .. }));
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::from_fn;
+use actix_web::http::header::{AUTHORIZATION, HeaderName};
+use actix_web::{App, web};
+
+use crate::auth::{self, Auth};
+use crate::state::AppState;
+use crate::storage::{History, JsonFileStore};
+use crate::webhooks::Webhooks;
+use crate::{lists, routes};
+
+/// Fresh state kept in `dir`, with the JSON storage and without login.
+pub fn state(dir: &Path) -> web::Data<AppState> {
+    let store = Arc::new(JsonFileStore::open(dir.join("todo_list.json")).unwrap());
+    let history = History::open(dir.join("history.jsonl")).unwrap();
+    let state = AppState::new(store, history, lists::open_lists(dir).unwrap(), Auth::open(dir, false).unwrap(), Webhooks::open(dir, false).unwrap());
+    web::Data::new(state)
+}
+
+/// The app as main() serves it, minus CORS, for `actix_web::test::init_service`.
+pub fn app(
+    state: web::Data<AppState>,
+) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error, InitError = ()>> {
+    App::new().app_data(state).wrap(from_fn(auth::authenticate)).configure(routes)
+}
+
+/// A new user, and the header that makes a request theirs.
+pub fn user(state: &AppState, username: &str) -> (u32, (HeaderName, String)) {
+    let (id, token) = state.auth.test_user(username);
+    (id, (AUTHORIZATION, format!("Bearer {}", token)))
+}
@@ -0,0 +1,690 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use actix_web::{HttpResponse, delete, get, http::header::CONTENT_TYPE, post, rt, web};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use todo_list_common::{
+    ApiError, CreatedWebhook, DeliveryAttempt, DeliveryStatus, NewWebhook, TodoItem, TodoQuery, WebhookDelivery, WebhookEvent,
+    WebhookInfo, WebhookPayload,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::auth::{Caller, new_token};
+use crate::error::AppError;
+use crate::lists::{self, StoredList};
+use crate::state::AppState;
+use crate::storage::{JsonCollection, OwnedStore, StoreError, TodoStore, TodoView};
+
+// After a failed attempt, the next one waits this long. One attempt more than there are delays.
+#[cfg(not(test))]
+const RETRY_DELAYS: [Duration; 4] =
+    [Duration::from_secs(10), Duration::from_secs(60), Duration::from_secs(5 * 60), Duration::from_secs(30 * 60)];
+#[cfg(test)]
+const RETRY_DELAYS: [Duration; 4] = [Duration::from_millis(10); 4];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// How often todos are checked for having become overdue
+const OVERDUE_CHECK: Duration = Duration::from_secs(60);
+// Finished deliveries kept in the log of each webhook, the oldest go first
+const LOGGED_DELIVERIES: usize = 100;
+
+/// A webhook, kept in `webhooks.json`. Unlike a token, the secret is stored as is, since
+/// every delivery is signed with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    user_id: Option<u32>,
+    url: String,
+    events: Vec<WebhookEvent>,
+    secret: String,
+    created_at: DateTime<Utc>,
+}
+
+/// One event on its way to a webhook, kept in `webhook_deliveries.json` so pending ones are
+/// picked up again after a restart. The body is kept as sent, every retry is the same request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    webhook_id: u32,
+    event: WebhookEvent,
+    todo_id: u32,
+    body: String,
+    status: DeliveryStatus,
+    created_at: DateTime<Utc>,
+    attempts: Vec<DeliveryAttempt>,
+}
+
+/// The webhooks of all users, and what was sent to them.
+pub struct Webhooks {
+    hooks: JsonCollection<Webhook>,
+    deliveries: JsonCollection<Delivery>,
+    client: reqwest::Client,
+    // Held while the deliveries are changed, so a delivery's attempt isn't logged after its
+    // webhook was deleted, or two changes to the log overwrite each other
+    write_lock: Mutex<()>,
+    // Whether posting to loopback and private network addresses is fine, see `check_target`
+    allow_private: bool,
+    // Deliveries queued along with a change, `start` records and sends them from there
+    queued: UnboundedSender<Delivery>,
+    queue: Mutex<Option<UnboundedReceiver<Delivery>>>,
+}
+
+impl Webhooks {
+    pub fn open(data_dir: &Path, allow_private: bool) -> Result<Self, StoreError> {
+        let mut client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            // A redirect would get the signed body sent somewhere nobody registered
+            .redirect(reqwest::redirect::Policy::none());
+        if !allow_private {
+            client = client.dns_resolver(Arc::new(PublicOnly));
+        }
+        let client = client.build().map_err(|err| StoreError::Io(std::io::Error::other(err)))?;
+        let (queued, queue) = mpsc::unbounded_channel();
+        Ok(Webhooks {
+            hooks: JsonCollection::open(data_dir.join("webhooks.json"))?,
+            deliveries: JsonCollection::open(data_dir.join("webhook_deliveries.json"))?,
+            client,
+            write_lock: Mutex::new(()),
+            allow_private,
+            queued,
+            queue: Mutex::new(Some(queue)),
+        })
+    }
+
+    fn write(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds an attempt to the log of a delivery, unless the delivery or its webhook were
+    /// deleted while it was being made. Returns where the delivery stands now, if it's still there.
+    fn log_attempt(&self, delivery_id: u32, attempt: DeliveryAttempt) -> Result<Option<DeliveryStatus>, StoreError> {
+        let _guard = self.write();
+        let Some(mut delivery) = self.deliveries.get(delivery_id) else {
+            return Ok(None);
+        };
+        if self.hooks.get(delivery.webhook_id).is_none() {
+            return Ok(None);
+        }
+
+        let delivered = attempt.error.is_none();
+        delivery.attempts.push(attempt);
+        delivery.status = match delivered {
+            true => DeliveryStatus::Delivered,
+            false if delivery.attempts.len() > RETRY_DELAYS.len() => DeliveryStatus::Failed,
+            false => DeliveryStatus::Pending,
+        };
+        let (webhook_id, status) = (delivery.webhook_id, delivery.status);
+        if status != DeliveryStatus::Pending {
+            // Nothing will be sent again, and the log doesn't show the body
+            delivery.body.clear();
+        }
+        self.deliveries.update(delivery_id, delivery)?;
+        if status != DeliveryStatus::Pending {
+            self.prune(webhook_id)?;
+        }
+        Ok(Some(status))
+    }
+
+    // Keeps the last `LOGGED_DELIVERIES` finished deliveries of a webhook, the ones still
+    // pending are kept anyway
+    fn prune(&self, webhook_id: u32) -> Result<(), StoreError> {
+        let finished: Vec<u32> = self
+            .deliveries
+            .list()
+            .into_iter()
+            .filter(|(_, delivery)| delivery.webhook_id == webhook_id && delivery.status != DeliveryStatus::Pending)
+            .map(|(id, _)| id)
+            .collect();
+        let Some(&oldest_kept) = finished.iter().rev().nth(LOGGED_DELIVERIES - 1) else {
+            return Ok(());
+        };
+        self.deliveries.retain(|id, delivery| {
+            delivery.webhook_id != webhook_id || delivery.status == DeliveryStatus::Pending || id >= oldest_kept
+        })
+    }
+
+    /// Deletes a webhook, and its deliveries along with it.
+    fn delete(&self, webhook_id: u32) -> Result<(), StoreError> {
+        let _guard = self.write();
+        self.hooks.delete(webhook_id)?;
+        self.deliveries.retain(|_, delivery| delivery.webhook_id != webhook_id)
+    }
+
+    // Queues a delivery to every webhook that wants to hear of `event`. A webhook hears about the
+    // todos its user can see, the same as they would over `/events`. This runs under the lock the
+    // change is stored under, so the deliveries are only written to disk later, by `send_queued`.
+    fn enqueue(&self, store: &Arc<dyn TodoStore>, lists: &JsonCollection<StoredList>, event: WebhookEvent, id: u32, item: &TodoItem) {
+        let payload = WebhookPayload { event, id, item: item.clone(), occurred_at: Utc::now() };
+        let body = serde_json::to_string(&payload).expect("payloads serialize to JSON");
+        for (webhook_id, hook) in self.hooks.list() {
+            let visible = OwnedStore::new(store.clone(), hook.user_id, lists::shared_with(lists, hook.user_id));
+            if !hook.events.contains(&event) || !visible.can_read(item) {
+                continue;
+            }
+            let delivery = Delivery {
+                webhook_id,
+                event,
+                todo_id: id,
+                body: body.clone(),
+                status: DeliveryStatus::Pending,
+                created_at: payload.occurred_at,
+                attempts: Vec::new(),
+            };
+            // Before `start` they simply wait in the channel, it's never closed while we're around
+            let _ = self.queued.send(delivery);
+        }
+    }
+
+    // Writes a queued delivery to disk, unless its webhook was deleted since it was queued
+    fn record(&self, delivery: Delivery) -> Result<Option<u32>, StoreError> {
+        let _guard = self.write();
+        if self.hooks.get(delivery.webhook_id).is_none() {
+            return Ok(None);
+        }
+        self.deliveries.insert(delivery).map(Some)
+    }
+}
+
+/// What a change to a todo tells webhooks, if anything. `None` on either side is a todo that
+/// didn't exist before, or was deleted.
+fn triggered<'a>(before: Option<&'a TodoItem>, after: Option<&'a TodoItem>) -> Option<(WebhookEvent, &'a TodoItem)> {
+    match (before, after) {
+        (None, Some(item)) => Some((WebhookEvent::Created, item)),
+        (Some(before), Some(item)) if !before.completed && item.completed => Some((WebhookEvent::Completed, item)),
+        (Some(item), None) => Some((WebhookEvent::Deleted, item)),
+        _ => None,
+    }
+}
+
+// Loopback, private networks, link-local and the like, which are no business of a webhook
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // 100.64.0.0/10 is the carrier-grade NAT range, private as well
+            let shared = first == 100 && second & 0xc0 == 64;
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || ip.is_unique_local() || ip.is_unicast_link_local()),
+        },
+    }
+}
+
+/// Makes sure `url` is an http(s) URL a webhook may post to. Host names are only checked when
+/// they're resolved, by `PublicOnly`, since what they point to can change.
+fn check_target(url: &str, allow_private: bool) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).ok().filter(|parsed| matches!(parsed.scheme(), "http" | "https"));
+    let Some(host) = parsed.as_ref().and_then(|parsed| parsed.host_str()) else {
+        return Err("url must be an http:// or https:// URL".to_string());
+    };
+    if allow_private {
+        return Ok(());
+    }
+    let private = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    match private {
+        true => Err(format!("{} is a local or private address, webhooks can only post to public ones", host)),
+        false => Ok(()),
+    }
+}
+
+// Resolves host names the way reqwest would, but leaves out the addresses that aren't public,
+// so a name can't be used to reach into the network the backend runs in
+struct PublicOnly;
+
+impl reqwest::dns::Resolve for PublicOnly {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.filter(|addr| is_public(addr.ip())).collect();
+            if addrs.is_empty() {
+                return Err(format!("{} doesn't resolve to a public address", name.as_str()).into());
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// `sha256=<hex>`, for the `X-Todo-Signature` header.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    format!("sha256={}", hex::encode(hmac::sign(&key, body)))
+}
+
+/// Queues the deliveries a change calls for as the change is stored. Like `NotifyingStore`, it
+/// sits right on top of the storage so nothing gets past it.
+pub struct WebhookStore {
+    inner: Arc<dyn TodoStore>,
+    webhooks: Arc<Webhooks>,
+    lists: Arc<JsonCollection<StoredList>>,
+}
+
+impl WebhookStore {
+    pub fn new(inner: Arc<dyn TodoStore>, webhooks: Arc<Webhooks>, lists: Arc<JsonCollection<StoredList>>) -> Self {
+        WebhookStore { inner, webhooks, lists }
+    }
+
+    fn queue(&self, id: u32, before: Option<&TodoItem>, after: Option<&TodoItem>) {
+        if let Some((event, item)) = triggered(before, after) {
+            self.webhooks.enqueue(&self.inner, &self.lists, event, id, item);
+        }
+    }
+}
+
+impl TodoView for WebhookStore {
+    fn get(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        self.inner.get(id)
+    }
+
+    fn list(&self) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.list()
+    }
+
+    fn query(&self, query: &TodoQuery) -> Result<BTreeMap<u32, TodoItem>, StoreError> {
+        self.inner.query(query)
+    }
+}
+
+impl TodoStore for WebhookStore {
+    fn insert(&self, item: TodoItem) -> Result<u32, StoreError> {
+        let id = self.inner.insert(item.clone())?;
+        self.queue(id, None, Some(&item));
+        Ok(id)
+    }
+
+    fn update(&self, id: u32, item: TodoItem) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.update(id, item.clone())?;
+        self.queue(id, before.as_ref(), Some(&item));
+        Ok(before)
+    }
+
+    fn delete(&self, id: u32) -> Result<Option<TodoItem>, StoreError> {
+        let before = self.inner.delete(id)?;
+        self.queue(id, before.as_ref(), None);
+        Ok(before)
+    }
+}
+
+async fn attempt(webhooks: &Webhooks, hook: &Webhook, delivery_id: u32, delivery: &Delivery) -> DeliveryAttempt {
+    // Webhooks added before private addresses were refused may still point at one
+    if let Err(err) = check_target(&hook.url, webhooks.allow_private) {
+        return DeliveryAttempt { at: Utc::now(), status: None, error: Some(err) };
+    }
+    let response = webhooks
+        .client
+        .post(&hook.url)
+        .header(CONTENT_TYPE.as_str(), "application/json")
+        .header("X-Todo-Event", delivery.event.as_str())
+        .header("X-Todo-Delivery", delivery_id.to_string())
+        .header("X-Todo-Signature", sign(&hook.secret, delivery.body.as_bytes()))
+        .body(delivery.body.clone())
+        .send()
+        .await;
+    let at = Utc::now();
+    match response {
+        Ok(response) if response.status().is_success() => DeliveryAttempt { at, status: Some(response.status().as_u16()), error: None },
+        Ok(response) => {
+            let error = Some(format!("The webhook answered {}", response.status()));
+            DeliveryAttempt { at, status: Some(response.status().as_u16()), error }
+        }
+        Err(err) => DeliveryAttempt { at, status: None, error: Some(err.to_string()) },
+    }
+}
+
+// Tries until the webhook takes the delivery or there are no attempts left, waiting longer
+// after every failure. A delivery picked up after a restart waits out what's left of its delay.
+async fn deliver(state: web::Data<AppState>, delivery_id: u32) {
+    loop {
+        let Some(delivery) = state.webhooks.deliveries.get(delivery_id) else {
+            return;
+        };
+        if let Some(last) = delivery.attempts.last() {
+            let delay = RETRY_DELAYS[delivery.attempts.len() - 1];
+            let wait = (last.at + delay - Utc::now()).to_std().unwrap_or_default();
+            rt::time::sleep(wait).await;
+        }
+        // The webhook may have been deleted in the meantime, its log along with it
+        let Some(hook) = state.webhooks.hooks.get(delivery.webhook_id) else {
+            return;
+        };
+
+        let attempt = attempt(&state.webhooks, &hook, delivery_id, &delivery).await;
+        match state.webhooks.log_attempt(delivery_id, attempt) {
+            Ok(Some(DeliveryStatus::Pending)) => {}
+            Ok(_) => return,
+            Err(err) => {
+                eprintln!("Failed to log delivery {}: {}", delivery_id, err);
+                return;
+            }
+        }
+    }
+}
+
+// Records what was queued, away from the lock the changes were stored under, and sends it.
+// Should the backend stop before a delivery is recorded, that one is lost.
+async fn send_queued(state: web::Data<AppState>, mut queue: UnboundedReceiver<Delivery>) {
+    while let Some(delivery) = queue.recv().await {
+        let webhook_id = delivery.webhook_id;
+        match state.webhooks.record(delivery) {
+            Ok(Some(delivery_id)) => {
+                rt::spawn(deliver(state.clone(), delivery_id));
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("Failed to queue a delivery to webhook {}: {}", webhook_id, err),
+        }
+    }
+}
+
+// Nothing changes when a todo becomes overdue, so the todos are looked at every so often for
+// ones whose due date passed since the last look. Those that came due while the backend was
+// down are not reported.
+async fn watch_overdue(state: web::Data<AppState>) {
+    let mut check = rt::time::interval(OVERDUE_CHECK);
+    let mut since = Utc::now();
+    loop {
+        check.tick().await;
+        let now = Utc::now();
+        match state.store.list() {
+            Ok(todos) => {
+                for (id, item) in todos {
+                    if !item.completed && item.due_date > since && item.due_date <= now {
+                        state.webhooks.enqueue(&state.store, &state.lists, WebhookEvent::Overdue, id, &item);
+                    }
+                }
+            }
+            Err(err) => eprintln!("Failed to check for overdue todos: {}", err),
+        }
+        since = now;
+    }
+}
+
+/// Starts sending events to the webhooks, and picks up the deliveries still pending from
+/// before a restart. Called once, before the server starts taking requests.
+pub fn start(state: web::Data<AppState>) {
+    let Some(queue) = state.webhooks.queue.lock().unwrap_or_else(PoisonError::into_inner).take() else {
+        return;
+    };
+    for (id, delivery) in state.webhooks.deliveries.list() {
+        if delivery.status == DeliveryStatus::Pending {
+            rt::spawn(deliver(state.clone(), id));
+        }
+    }
+    rt::spawn(send_queued(state.clone(), queue));
+    rt::spawn(watch_overdue(state));
+}
+
+fn info(id: u32, hook: Webhook) -> WebhookInfo {
+    WebhookInfo { id, url: hook.url, events: hook.events, created_at: hook.created_at }
+}
+
+fn webhook_not_found(id: u32) -> AppError {
+    ApiError::not_found(format!("Webhook with id {} not found", id)).into()
+}
+
+// Everyone only sees their own webhooks
+fn get_own(state: &AppState, caller: &Caller, id: u32) -> Result<Webhook, AppError> {
+    match state.webhooks.hooks.get(id) {
+        Some(hook) if hook.user_id == caller.user_id => Ok(hook),
+        _ => Err(webhook_not_found(id)),
+    }
+}
+
+#[post("/webhooks")]
+async fn create_webhook(
+    state: web::Data<AppState>,
+    caller: Caller,
+    new_webhook: web::Json<NewWebhook>,
+) -> Result<HttpResponse, AppError> {
+    // Someone has to answer for where the backend posts to
+    if caller.user_id.is_none() {
+        return Err(ApiError::unauthorized("Log in to add webhooks").into());
+    }
+    let NewWebhook { url, mut events } = new_webhook.into_inner();
+    let url = url.trim().to_string();
+    check_target(&url, state.webhooks.allow_private).map_err(ApiError::validation)?;
+    events.sort_unstable();
+    events.dedup();
+    if events.is_empty() {
+        return Err(ApiError::validation("A webhook needs at least one event").into());
+    }
+
+    let secret = format!("whsec_{}", new_token()?);
+    let hook = Webhook { user_id: caller.user_id, url, events, secret: secret.clone(), created_at: Utc::now() };
+    let id = state.webhooks.hooks.insert(hook.clone())?;
+
+    Ok(HttpResponse::Created().json(CreatedWebhook { info: info(id, hook), secret }))
+}
+
+#[get("/webhooks")]
+async fn list_webhooks(state: web::Data<AppState>, caller: Caller) -> Result<HttpResponse, AppError> {
+    let hooks: Vec<WebhookInfo> = state
+        .webhooks
+        .hooks
+        .list()
+        .into_iter()
+        .filter(|(_, hook)| hook.user_id == caller.user_id)
+        .map(|(id, hook)| info(id, hook))
+        .collect();
+    Ok(HttpResponse::Ok().json(hooks))
+}
+
+// Deliveries still being retried are dropped along with the log
+#[delete("/webhooks/{id}")]
+async fn delete_webhook(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    get_own(&state, &caller, id)?;
+    state.webhooks.delete(id)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/webhooks/{id}/deliveries")]
+async fn list_deliveries(state: web::Data<AppState>, caller: Caller, id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let id = *id;
+    get_own(&state, &caller, id)?;
+    let deliveries: Vec<WebhookDelivery> = state
+        .webhooks
+        .deliveries
+        .list()
+        .into_iter()
+        .rev()
+        .filter(|(_, delivery)| delivery.webhook_id == id)
+        .map(|(id, delivery)| WebhookDelivery {
+            id,
+            event: delivery.event,
+            todo_id: delivery.todo_id,
+            status: delivery.status,
+            created_at: delivery.created_at,
+            attempts: delivery.attempts,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpRequest, HttpServer};
+
+    use super::*;
+    use crate::auth::Auth;
+    use crate::storage::{History, JsonFileStore};
+
+    #[test]
+    fn changes_trigger_events() {
+        let open = TodoItem { title: "task".to_string(), ..Default::default() };
+        let done = TodoItem { completed: true, ..open.clone() };
+        let event = |before, after| triggered(before, after).map(|(event, _)| event);
+
+        assert_eq!(event(None, Some(&open)), Some(WebhookEvent::Created));
+        assert_eq!(event(Some(&open), Some(&done)), Some(WebhookEvent::Completed));
+        // Reopening it, or changing it in any other way, isn't worth a message
+        assert_eq!(event(Some(&done), Some(&open)), None);
+        assert_eq!(event(Some(&done), Some(&done)), None);
+        assert_eq!(event(Some(&done), None), Some(WebhookEvent::Deleted));
+    }
+
+    #[test]
+    fn deliveries_are_queued_as_changes_are_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let webhooks = Arc::new(Webhooks::open(dir.path(), false).unwrap());
+        let hook = |user_id| Webhook {
+            user_id,
+            url: "https://hooks.example/todo".to_string(),
+            events: vec![WebhookEvent::Created],
+            secret: "whsec_test".to_string(),
+            created_at: Utc::now(),
+        };
+        let mine = webhooks.hooks.insert(hook(None)).unwrap();
+        webhooks.hooks.insert(hook(Some(1))).unwrap();
+        let inner = Arc::new(crate::storage::JsonFileStore::open(dir.path().join("todo_list.json")).unwrap());
+        let store = WebhookStore::new(inner, webhooks.clone(), Arc::new(lists::open_lists(dir.path()).unwrap()));
+
+        let id = store.insert(TodoItem { title: "task".to_string(), ..Default::default() }).unwrap();
+        store.delete(id).unwrap();
+
+        // Only the created event was asked for, and only by the webhook whose user sees the todo.
+        // It's written to disk once it's taken off the queue.
+        let mut queue = webhooks.queue.lock().unwrap().take().unwrap();
+        let delivery = queue.try_recv().unwrap();
+        assert!(queue.try_recv().is_err());
+        assert_eq!((delivery.webhook_id, delivery.status), (mine, DeliveryStatus::Pending));
+        assert!(webhooks.deliveries.list().is_empty());
+        let delivery_id = webhooks.record(delivery.clone()).unwrap().unwrap();
+        assert_eq!(webhooks.deliveries.get(delivery_id).unwrap().body, delivery.body);
+
+        // Not when the webhook was deleted in the meantime
+        webhooks.delete(mine).unwrap();
+        assert_eq!(webhooks.record(delivery).unwrap(), None);
+        assert!(webhooks.deliveries.list().is_empty());
+    }
+
+    #[test]
+    fn finished_deliveries_are_pruned_and_deleted_ones_stay_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let webhooks = Webhooks::open(dir.path(), false).unwrap();
+        let hook = Webhook {
+            user_id: None,
+            url: "https://hooks.example/todo".to_string(),
+            events: vec![WebhookEvent::Created],
+            secret: "whsec_test".to_string(),
+            created_at: Utc::now(),
+        };
+        let webhook_id = webhooks.hooks.insert(hook).unwrap();
+        let pending = || Delivery {
+            webhook_id,
+            event: WebhookEvent::Created,
+            todo_id: 0,
+            body: "{}".to_string(),
+            status: DeliveryStatus::Pending,
+            created_at: Utc::now(),
+            attempts: Vec::new(),
+        };
+        let delivered = || DeliveryAttempt { at: Utc::now(), status: Some(200), error: None };
+
+        for _ in 0..=LOGGED_DELIVERIES {
+            let id = webhooks.deliveries.insert(pending()).unwrap();
+            assert_eq!(webhooks.log_attempt(id, delivered()).unwrap(), Some(DeliveryStatus::Delivered));
+        }
+        let logged = webhooks.deliveries.list();
+        assert_eq!(logged.len(), LOGGED_DELIVERIES);
+        assert!(!logged.contains_key(&0) && logged.values().all(|delivery| delivery.body.is_empty()));
+
+        // An attempt that was under way when the webhook was deleted doesn't bring its log back
+        let id = webhooks.deliveries.insert(pending()).unwrap();
+        webhooks.delete(webhook_id).unwrap();
+        assert_eq!(webhooks.log_attempt(id, delivered()).unwrap(), None);
+        assert!(webhooks.deliveries.list().is_empty());
+    }
+
+    #[test]
+    fn private_targets_are_refused() {
+        assert!(check_target("https://hooks.example.com/todo", false).is_ok());
+        assert!(check_target("http://93.184.216.34:8080/", false).is_ok());
+        for url in ["http://localhost:9000/hook", "http://127.0.0.1/", "http://10.1.2.3/", "http://169.254.169.254/latest",
+            "http://[::1]:9000/", "http://[::ffff:192.168.0.1]/", "http://100.64.0.1/", "ftp://hooks.example.com/"]
+        {
+            assert!(check_target(url, false).is_err(), "{} was let through", url);
+        }
+        assert!(check_target("http://localhost:9000/hook", true).is_ok());
+        assert!(check_target("not a url", true).is_err());
+    }
+
+    #[test]
+    fn signatures_check_out_with_the_secret() {
+        let signature = sign("whsec_test", b"{}");
+        let tag = hex::decode(signature.strip_prefix("sha256=").unwrap()).unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"whsec_test");
+        assert!(hmac::verify(&key, b"{}", &tag).is_ok());
+        assert_ne!(sign("whsec_other", b"{}"), signature);
+    }
+
+    // What the listener was sent: the signature header and the body
+    type Received = Mutex<Vec<(String, String)>>;
+
+    // Turns the first delivery away, as a webhook that's briefly down would
+    async fn receive(req: HttpRequest, body: String, received: web::Data<Received>) -> HttpResponse {
+        let signature = req.headers().get("X-Todo-Signature").map_or("", |value| value.to_str().unwrap()).to_string();
+        let mut received = received.lock().unwrap();
+        received.push((signature, body));
+        match received.len() {
+            1 => HttpResponse::ServiceUnavailable().finish(),
+            _ => HttpResponse::Ok().finish(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn deliveries_are_signed_and_retried() {
+        let received = web::Data::new(Received::default());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server_data = received.clone();
+        let server = HttpServer::new(move || App::new().app_data(server_data.clone()).route("/hook", web::post().to(receive)))
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+        rt::spawn(server);
+
+        let dir = tempfile::tempdir().unwrap();
+        let webhooks = Webhooks::open(dir.path(), true).unwrap();
+        let hook = Webhook { user_id: None, url, events: vec![WebhookEvent::Created], secret: "whsec_test".to_string(), created_at: Utc::now() };
+        let webhook_id = webhooks.hooks.insert(hook).unwrap();
+        let store = Arc::new(JsonFileStore::open(dir.path().join("todo_list.json")).unwrap());
+        let history = History::open(dir.path().join("history.jsonl")).unwrap();
+        let auth = Auth::open(dir.path(), false).unwrap();
+        let state = web::Data::new(AppState::new(store, history, lists::open_lists(dir.path()).unwrap(), auth, webhooks));
+        start(state.clone());
+
+        let id = state.store.insert(TodoItem { title: "task".to_string(), ..Default::default() }).unwrap();
+        let mut delivered = None;
+        for _ in 0..500 {
+            rt::time::sleep(Duration::from_millis(10)).await;
+            delivered = state.webhooks.deliveries.list().into_values().find(|delivery| delivery.status != DeliveryStatus::Pending);
+            if delivered.is_some() {
+                break;
+            }
+        }
+        let delivered = delivered.expect("the delivery went through in time");
+        assert_eq!((delivered.webhook_id, delivered.status), (webhook_id, DeliveryStatus::Delivered));
+        let statuses: Vec<_> = delivered.attempts.iter().map(|attempt| attempt.status).collect();
+        assert_eq!(statuses, vec![Some(503), Some(200)]);
+
+        // The retry is the same request, signed over exactly the body that was sent
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], received[1]);
+        let (signature, body) = &received[0];
+        let tag = hex::decode(signature.strip_prefix("sha256=").unwrap()).unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"whsec_test");
+        assert!(hmac::verify(&key, body.as_bytes(), &tag).is_ok());
+        let payload: WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!((payload.event, payload.id, payload.item.title.as_str()), (WebhookEvent::Created, id, "task"));
+    }
+}
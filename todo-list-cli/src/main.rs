@@ -1,57 +1,324 @@
 mod todo_client;
 
+use std::io::{self, IsTerminal, Write};
+
 use chrono::{DateTime, Utc};
-use todo_list_common::TodoItem;
-use std::env;
+use clap::{ArgAction, Parser, Subcommand};
+use todo_list_common::{
+    credentials, ChecklistPatch, ChildrenOnDelete, Credentials, DeleteQuery, HistoryAction, HistoryEntry, INBOX_ID, ListDeleteQuery, ListMember, ListRole, ListShare, NewApiToken, NewChecklistItem, Priority, Recurrence, SortField, SortOrder, TagCount,
+    TagQuery, TagRename, TodoEntry, TokenScope, TodoItem, TodoList, TodoListEntry, TodoPatch, TodoQuery, UpdateResponse,
+};
 use todo_client::*;
 
+/// CLI app to test Todo backend
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Only work with the todos on this list, by name or id
+    #[arg(long, global = true)]
+    list: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List todos, optionally filtered, sorted and a page at a time
+    List {
+        /// Only completed (true) or incomplete (false) todos
+        #[arg(long)]
+        completed: Option<bool>,
+        /// Only todos due before this ISO 8601 datetime
+        #[arg(long)]
+        due_before: Option<DateTime<Utc>>,
+        /// Only todos due after this ISO 8601 datetime
+        #[arg(long)]
+        due_after: Option<DateTime<Utc>>,
+        /// Text to look for in the title and description
+        #[arg(short = 'q', long)]
+        search: Option<String>,
+        /// Only todos with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only open todos that can be started (true) or are still waiting on a blocker (false)
+        #[arg(long)]
+        actionable: Option<bool>,
+        /// id, due_date, created_at, title or smart (what to work on next)
+        #[arg(long)]
+        sort: Option<SortField>,
+        /// Sort in descending order
+        #[arg(long)]
+        desc: bool,
+        /// Show at most this many todos
+        #[arg(long)]
+        limit: Option<u32>,
+        /// Continue from where the previous page ended
+        #[arg(long)]
+        cursor: Option<String>,
+        /// Show subtasks indented below their parent
+        #[arg(long)]
+        tree: bool,
+        /// Only the todos assigned to you
+        #[arg(long)]
+        assigned_to_me: bool,
+    },
+    /// Show all fields of a todo
+    View { id: u32 },
+    /// Add a todo, due in three days unless a due date is given
+    Insert {
+        title: String,
+        description: String,
+        due_date: Option<DateTime<Utc>>,
+        /// Tag the todo, can be given more than once
+        #[arg(long)]
+        tag: Vec<String>,
+        /// none, low, medium, high or urgent
+        #[arg(long)]
+        priority: Option<Priority>,
+        /// Make it a subtask of this todo
+        #[arg(long)]
+        parent: Option<u32>,
+        /// A todo that has to be completed first, can be given more than once
+        #[arg(long)]
+        blocked_by: Vec<u32>,
+        /// Repeat it, e.g. FREQ=WEEKLY;BYDAY=MO,TH or FREQ=MONTHLY;BYMONTHDAY=31;COUNT=12
+        #[arg(long)]
+        repeat: Option<Recurrence>,
+    },
+    /// Change a todo's title, description, due date and completion
+    Update {
+        id: u32,
+        title: String,
+        description: String,
+        due_date: DateTime<Utc>,
+        #[arg(action = ArgAction::Set)]
+        completed: bool,
+        /// none, low, medium, high or urgent, left as it is when not given
+        #[arg(long)]
+        priority: Option<Priority>,
+    },
+    /// Mark a todo as completed
+    Complete { id: u32 },
+    /// Mark a todo as not completed
+    Incomplete { id: u32 },
+    /// Delete a todo
+    Delete {
+        id: u32,
+        /// What happens to its subtasks: cascade deletes them too, reparent moves them up a level
+        #[arg(long)]
+        children: Option<ChildrenOnDelete>,
+    },
+    /// List the direct subtasks of a todo
+    Children { id: u32 },
+    /// Make a todo a subtask of <PARENT>, or a top level todo again without one
+    Reparent { id: u32, parent: Option<u32> },
+    /// Set the todos that have to be completed before <ID>, none clears them
+    Block { id: u32, blockers: Vec<u32> },
+    /// Make a todo repeat by an RRULE like FREQ=DAILY;INTERVAL=3, without one it stops repeating
+    Repeat { id: u32, rule: Option<Recurrence> },
+    /// Move a todo to another list, by name or id
+    Move { id: u32, to: String },
+    /// Assign a todo to a member of its list, without a username it's unassigned again
+    Assign { id: u32, username: Option<String> },
+    /// Show every change made to a todo, when and by whom
+    History { id: u32 },
+    /// Put a todo back the way it was at a revision from its history, even after deleting it
+    Revert { id: u32, revision: u64 },
+    /// Take back your last change, running it again goes further back
+    Undo,
+    /// Show or change the named lists
+    Lists {
+        #[command(subcommand)]
+        command: ListsCommand,
+    },
+    /// List every tag and how many todos have it
+    Tags,
+    /// Rename a tag on every todo, merging it into <TO> if that tag exists already
+    RenameTag { from: String, to: String },
+    /// Show or change the checklist of a todo
+    Checklist {
+        #[command(subcommand)]
+        command: ChecklistCommand,
+    },
+    /// Create an account on the backend, the password is asked for
+    Register { username: String },
+    /// Log in, the token is kept in the credentials file and sent by every command after it
+    Login { username: String },
+    /// Log out, ending the session and removing the credentials file
+    Logout,
+    /// Show who is logged in
+    Whoami,
+    /// Show, create or revoke personal API tokens, which scripts send in $TODO_TOKEN
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokensCommand {
+    /// Show your API tokens, without the tokens themselves
+    Show,
+    /// Create a token, it's only shown this once
+    Create {
+        name: String,
+        /// read or write, can be given more than once
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<TokenScope>,
+        /// Expire after this many days, without it the token is valid until it's revoked
+        #[arg(long)]
+        expires_in_days: Option<u32>,
+    },
+    /// Revoke a token, by id
+    Revoke { id: u32 },
+}
+
+#[derive(Subcommand)]
+enum ListsCommand {
+    /// Show every list and how many todos are on it
+    Show,
+    /// Add a list
+    Add { name: String },
+    /// Rename a list, given by name or id
+    Rename { list: String, name: String },
+    /// Delete a list, its todos have to be moved to another one first
+    Delete {
+        list: String,
+        /// Move its todos to this list
+        #[arg(long)]
+        move_to: Option<String>,
+    },
+    /// Show who a list is shared with
+    Members { list: String },
+    /// Share a list with another user, or change their role on it
+    Share {
+        list: String,
+        username: String,
+        /// editor, who may change its todos, or viewer
+        #[arg(long, default_value = "editor")]
+        role: ListRole,
+    },
+    /// Stop sharing a list with a user, or leave a list shared with you
+    Unshare { list: String, username: String },
+}
+
+#[derive(Subcommand)]
+enum ChecklistCommand {
+    /// Show the checklist with the ids of its items
+    Show { id: u32 },
+    /// Add an item, at the end unless a position is given
+    Add {
+        id: u32,
+        text: String,
+        /// 0 is the top
+        #[arg(long)]
+        position: Option<usize>,
+    },
+    /// Mark an item as done
+    Check { id: u32, item_id: u32 },
+    /// Mark an item as not done
+    Uncheck { id: u32, item_id: u32 },
+    /// Change the text of an item
+    Edit { id: u32, item_id: u32, text: String },
+    /// Move an item to another position, 0 is the top
+    Move { id: u32, item_id: u32, position: usize },
+    /// Remove an item
+    Remove { id: u32, item_id: u32 },
+}
+
 /// CLI app to test Todo backend
 ///
 /// Example usage:
 /// ```bash
 /// cargo run -- list
+/// cargo run -- list --completed false --search milk --sort due_date --limit 10
 /// cargo run -- view 1
-/// cargo run -- insert "Buy groceries" "Milk, eggs, bread" "2025-11-20T23:59:59Z"
+/// cargo run -- insert "Buy groceries" "Milk, eggs, bread" "2025-11-20T23:59:59Z" --tag errands
+/// cargo run -- list --tag errands
+/// cargo run -- list --sort smart
+/// cargo run -- checklist add 1 "Buy stamps"
+/// cargo run -- checklist check 1 1
 /// cargo run -- update 1 "Do laundry" "Fold clothes" "2025-11-21T23:59:59Z" true
 /// cargo run -- complete 1
 /// cargo run -- incomplete 1
 /// cargo run -- delete 1
+/// cargo run -- list --tree
+/// cargo run -- block 2 1
+/// cargo run -- list --actionable true
+/// cargo run -- repeat 1 "FREQ=WEEKLY;BYDAY=SA"
+/// cargo run -- lists add Work
+/// cargo run -- --list work insert "Write report" "Quarterly numbers"
+/// cargo run -- move 1 work
+/// cargo run -- register alice
+/// cargo run -- login alice
+/// cargo run -- lists share work bob --role viewer
+/// cargo run -- assign 1 bob
+/// cargo run -- list --assigned-to-me
+/// cargo run -- tokens create nightly-report --scope read
+/// TODO_TOKEN=todo_... cargo run -- list
+/// cargo run -- logout
 /// ```
-
 fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        print_usage();
+    let cli = Cli::parse();
+    let list_id = match cli.list.as_deref().map(resolve_list).transpose() {
+        Ok(list_id) => list_id,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+    // With --list, a todo on another list is left alone
+    if let (Some(list_id), Some(id)) = (list_id, cli.command.todo_id())
+        && !on_list(id, list_id)
+    {
         return;
     }
 
-    match args[1].as_str() {
-        "list" => {
-            match list_todos() {
-                Ok(map) => {
+    match cli.command {
+        Command::List { completed, due_before, due_after, search, tag, actionable, sort, desc, limit, cursor, tree, assigned_to_me } => {
+            let mut query = TodoQuery::new();
+            query.completed = completed;
+            query.due_before = due_before;
+            query.due_after = due_after;
+            query.q = search;
+            query.tag = tag;
+            query.list = list_id;
+            query.actionable = actionable;
+            query.limit = limit;
+            query.cursor = cursor;
+            if sort.is_some() || desc {
+                let order = if desc { SortOrder::Desc } else { SortOrder::Asc };
+                query = query.sort(sort.unwrap_or_default(), order);
+            }
+            if assigned_to_me {
+                match whoami() {
+                    Ok(user) => query = query.assigned_to(user.id),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return;
+                    }
+                }
+            }
+
+            match list_todos(&query) {
+                Ok(page) => {
                     println!("=== All Todos ===");
-                    for (id, item) in map {
-                        println!(
-                            "[{}] {} - {} | Due: {} | Completed: {}",
-                            id,
-                            item.title,
-                            item.description,
-                            item.due_date.to_rfc3339(),
-                            item.completed
-                        );
+                    if tree {
+                        print_tree(&page.items);
+                    } else {
+                        for entry in &page.items {
+                            print_entry(entry, 0);
+                        }
+                    }
+                    if let Some(next_cursor) = page.next_cursor {
+                        println!("More todos follow, add --cursor {} for the next page", next_cursor);
                     }
                 }
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
 
-        "view" => {
-            if args.len() < 3 {
-                eprintln!("Usage: view <id>");
-                return;
-            }
-            let id = &args[2];
+        Command::View { id } => {
             match view_todo(id) {
                 Ok(Some(todo)) => println!("{:#?}", todo),
                 Ok(None) => println!("No todo found with id {}", id),
@@ -59,124 +326,608 @@ fn main() {
             }
         }
 
-        "insert" => {
-            if args.len() < 4 {
-                eprintln!("Usage: insert <title> <description> [due_date]");
-                eprintln!("Example: insert \"Buy groceries\" \"Milk, eggs\" \"2025-11-20T23:59:59Z\"");
-                return;
+        Command::Insert { title, description, due_date, tag, priority, parent, blocked_by, repeat } => {
+            let new_todo = TodoItem {
+                title,
+                description,
+                // Optional due date
+                due_date: due_date.unwrap_or_else(|| Utc::now() + chrono::Duration::days(3)),
+                completed: false,
+                tags: tag,
+                priority: priority.unwrap_or_default(),
+                parent_id: parent,
+                blocked_by,
+                recurrence: repeat,
+                list_id: list_id.unwrap_or(INBOX_ID),
+                ..Default::default()
+            };
+
+            match insert_todo(&new_todo) {
+                Ok(inserted) => println!("Inserted successfully with id {}", inserted.id),
+                Err(e) => eprintln!("Error inserting: {}", e),
             }
-            let title = &args[2];
-            let description = &args[3];
-            let now = Utc::now();
+        }
 
-            // Optional due date
-            let due_date: DateTime<Utc> = if args.len() > 4 {
-                match args[4].parse() {
-                    Ok(dt) => dt,
-                    Err(_) => {
-                        eprintln!("Invalid due_date format. Example: 2025-11-20T23:59:59Z");
-                        return;
+        Command::Update { id, title, description, due_date, completed, priority } => {
+            // Patch rather than replace, so the fields we don't know about stay as they are
+            let patch = TodoPatch {
+                title: Some(title),
+                description: Some(description),
+                due_date: Some(due_date),
+                completed: Some(completed),
+                priority,
+                ..Default::default()
+            };
+
+            match patch_todo(id, &patch) {
+                Ok(resp) => {
+                    println!("Updated todo {} successfully", resp.entry.id);
+                    print_effects(&resp);
+                }
+                Err(e) => eprintln!("Error updating: {}", e),
+            }
+        }
+
+        Command::Complete { id } => set_completed(id, true),
+        Command::Incomplete { id } => set_completed(id, false),
+
+        Command::Delete { id, children } => {
+            match delete_todo(id, &DeleteQuery { children }) {
+                Ok(resp) => println!("Deleted successfully: {:?}", resp.status()),
+                Err(e) => eprintln!("Error deleting: {}", e),
+            }
+        }
+
+        Command::Children { id } => {
+            match list_children(id) {
+                Ok(children) => {
+                    println!("=== Subtasks of {} ===", id);
+                    for entry in children.iter().filter(|entry| list_id.is_none_or(|list_id| entry.item.list_id == list_id)) {
+                        print_entry(entry, 0);
                     }
                 }
-            } else {
-                now + chrono::Duration::days(3)
-            };
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
 
-            let new_todo = TodoItem {
-                title: title.to_string(),
-                description: description.to_string(),
-                due_date,
-                created_at: now,
-                completed: false,
+        Command::Reparent { id, parent } => {
+            let patch = TodoPatch {
+                parent_id: Some(parent),
+                ..Default::default()
             };
+            match patch_todo(id, &patch) {
+                Ok(resp) => match resp.entry.item.parent_id {
+                    Some(parent_id) => println!("Todo {} is now a subtask of {}", id, parent_id),
+                    None => println!("Todo {} is now a top level todo", id),
+                },
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
 
-            match insert_todo(&new_todo) {
-                Ok(resp) => println!("Inserted successfully: {:?}", resp.status()),
-                Err(e) => eprintln!("Error inserting: {}", e),
+        Command::Block { id, blockers } => {
+            let patch = TodoPatch {
+                blocked_by: Some(blockers),
+                ..Default::default()
+            };
+            match patch_todo(id, &patch) {
+                Ok(resp) if resp.entry.item.blocked_by.is_empty() => println!("Todo {} isn't waiting on anything", id),
+                Ok(resp) => println!("Todo {} is now blocked by {}", id, join_ids(&resp.entry.item.blocked_by)),
+                Err(e) => eprintln!("Error: {}", e),
             }
         }
 
-        "update" => {
-            if args.len() < 6 {
-                eprintln!("Usage: update <id> <title> <description> <due_date> <completed>");
-                eprintln!("Example: update 1 \"Do laundry\" \"Fold clothes\" \"2025-11-21T23:59:59Z\" false");
-                return;
+        Command::Repeat { id, rule } => {
+            let patch = TodoPatch {
+                recurrence: Some(rule),
+                ..Default::default()
+            };
+            match patch_todo(id, &patch) {
+                Ok(resp) => match resp.entry.item.recurrence {
+                    Some(recurrence) => println!("Todo {} now repeats: {}", id, recurrence),
+                    None => println!("Todo {} doesn't repeat anymore", id),
+                },
+                Err(e) => eprintln!("Error: {}", e),
             }
-            let id = &args[2];
-            let title = &args[3];
-            let description = &args[4];
-            let due_date = match args[5].parse::<DateTime<Utc>>() {
-                Ok(dt) => dt,
-                Err(_) => {
-                    eprintln!("Invalid due_date format. Example: 2025-11-21T23:59:59Z");
+        }
+
+        Command::Move { id, to } => {
+            let patch = match resolve_list(&to) {
+                Ok(to) => TodoPatch { list_id: Some(to), ..Default::default() },
+                Err(e) => {
+                    eprintln!("Error: {}", e);
                     return;
                 }
             };
-            let completed: bool = args[6].parse().unwrap_or(false);
-
-            let now = Utc::now();
-            let updated = TodoItem {
-                title: title.to_string(),
-                description: description.to_string(),
-                due_date,
-                created_at: now,
-                completed,
+            match patch_todo(id, &patch) {
+                Ok(_) => println!("Moved todo {} to {}", id, to),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+
+        Command::Assign { id, username } => {
+            let patch = match username.as_deref().map(|username| resolve_member(id, username)).transpose() {
+                Ok(assigned_to) => TodoPatch { assigned_to: Some(assigned_to), ..Default::default() },
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
             };
+            match patch_todo(id, &patch) {
+                Ok(_) => match username {
+                    Some(username) => println!("Assigned todo {} to {}", id, username),
+                    None => println!("Todo {} isn't assigned to anyone now", id),
+                },
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
 
-            match update_todo(id, &updated) {
-                Ok(resp) => println!("Updated successfully: {:?}", resp.status()),
-                Err(e) => eprintln!("Error updating: {}", e),
+        Command::History { id } => {
+            match todo_history(id) {
+                Ok(entries) => {
+                    println!("=== History of {} ===", id);
+                    for entry in entries {
+                        print_history_entry(&entry);
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", e),
             }
         }
 
-        "complete" | "incomplete" => {
-            if args.len() < 3 {
-                eprintln!("Usage: {} <id>", args[1]);
-                return;
+        Command::Revert { id, revision } => {
+            match revert_todo(id, revision) {
+                Ok(entry) => println!("Todo {} is back to how it was at revision {}, as revision {}", id, revision, entry.item.revision),
+                Err(e) => eprintln!("Error: {}", e),
             }
-            let id = &args[2];
-            match view_todo(id) {
-                Ok(Some(mut todo)) => {
-                    todo.completed = args[1] == "complete";
-                    match update_todo(id, &todo) {
-                        Ok(resp) => println!(
-                            "{} successfully: {:?}",
-                            args[1].to_uppercase(),
-                            resp.status()
-                        ),
-                        Err(e) => eprintln!("Error: {}", e),
+        }
+
+        Command::Undo => {
+            match undo() {
+                Ok(undone) => {
+                    for entry in &undone.restored {
+                        println!("Restored todo {}", entry.id);
+                    }
+                    for id in &undone.deleted {
+                        println!("Deleted todo {} again", id);
                     }
                 }
-                Ok(None) => println!("No todo found with id {}", id),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
 
-        "delete" => {
-            if args.len() < 3 {
-                eprintln!("Usage: delete <id>");
+        Command::Lists { command } => run_lists(command),
+
+        Command::Tags => {
+            match list_tags(&TagQuery { list: list_id }) {
+                Ok(tags) => {
+                    println!("=== All Tags ===");
+                    for TagCount { tag, count } in tags {
+                        println!("{} ({})", tag, count);
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+
+        Command::RenameTag { from, to } => {
+            match rename_tag(&TagRename { from, to, list: list_id }) {
+                Ok(resp) => println!("Renamed the tag on {} todos", resp.renamed),
+                Err(e) => eprintln!("Error renaming tag: {}", e),
+            }
+        }
+
+        Command::Checklist { command } => run_checklist(command),
+
+        Command::Register { username } => {
+            let result = read_password().map_err(|e| e.to_string()).and_then(|password| {
+                register(&Credentials { username, password }).map_err(|e| e.to_string())
+            });
+            match result {
+                Ok(user) => println!("Registered {} with id {}, log in to use it", user.username, user.id),
+                Err(e) => eprintln!("Error registering: {}", e),
+            }
+        }
+
+        Command::Login { username } => {
+            let result = read_password().map_err(|e| e.to_string()).and_then(|password| {
+                let resp = login(&Credentials { username, password }).map_err(|e| e.to_string())?;
+                let stored = credentials::StoredCredentials {
+                    username: resp.user.username,
+                    token: resp.token,
+                    expires_at: resp.expires_at,
+                };
+                credentials::save(&stored).map(|path| (stored, path)).map_err(|e| e.to_string())
+            });
+            match result {
+                Ok((stored, path)) => println!(
+                    "Logged in as {} until {}, the token is in {}",
+                    stored.username,
+                    stored.expires_at.format("%Y-%m-%d"),
+                    path.display()
+                ),
+                Err(e) => eprintln!("Error logging in: {}", e),
+            }
+        }
+
+        Command::Logout => {
+            // The token is forgotten even if the backend can't be told, it may well have expired already
+            if credentials::load().is_some()
+                && let Err(e) = logout()
+            {
+                eprintln!("Warning: the backend didn't end the session: {}", e);
+            }
+            match credentials::remove() {
+                Ok(true) => println!("Logged out"),
+                Ok(false) => println!("Not logged in"),
+                Err(e) => eprintln!("Error removing the credentials file: {}", e),
+            }
+        }
+
+        Command::Whoami => match whoami() {
+            Ok(user) => println!("Logged in as {} (id {})", user.username, user.id),
+            Err(e) => eprintln!("Error: {}", e),
+        },
+
+        Command::Tokens { command } => run_tokens(command),
+    }
+}
+
+impl Command {
+    // The todo the command works on, for the ones that work on a single todo
+    fn todo_id(&self) -> Option<u32> {
+        match self {
+            Command::View { id }
+            | Command::Update { id, .. }
+            | Command::Complete { id }
+            | Command::Incomplete { id }
+            | Command::Delete { id, .. }
+            | Command::Children { id }
+            | Command::Reparent { id, .. }
+            | Command::Block { id, .. }
+            | Command::Repeat { id, .. }
+            | Command::Move { id, .. }
+            | Command::Assign { id, .. }
+            | Command::History { id } => Some(*id),
+            Command::Checklist { command } => match command {
+                ChecklistCommand::Show { id }
+                | ChecklistCommand::Add { id, .. }
+                | ChecklistCommand::Check { id, .. }
+                | ChecklistCommand::Uncheck { id, .. }
+                | ChecklistCommand::Edit { id, .. }
+                | ChecklistCommand::Move { id, .. }
+                | ChecklistCommand::Remove { id, .. } => Some(*id),
+            },
+            Command::List { .. }
+            | Command::Insert { .. }
+            | Command::Lists { .. }
+            | Command::Tags
+            | Command::RenameTag { .. }
+            | Command::Register { .. }
+            | Command::Login { .. }
+            | Command::Logout
+            | Command::Whoami
+            | Command::Tokens { .. }
+            // A deleted todo isn't on any list to check
+            | Command::Revert { .. }
+            | Command::Undo => None,
+        }
+    }
+}
+
+fn run_tokens(command: TokensCommand) {
+    let result = match command {
+        TokensCommand::Show => list_api_tokens().map(|tokens| {
+            println!("=== API Tokens ===");
+            for token in tokens {
+                let expires = token.expires_at.map_or("never".to_string(), |expires_at| expires_at.format("%Y-%m-%d").to_string());
+                println!("[{}] {} ({}) | Expires: {}", token.id, token.name, join_scopes(&token.scopes), expires);
+            }
+        }),
+        TokensCommand::Create { name, scopes, expires_in_days } => {
+            create_api_token(&NewApiToken { name, scopes, expires_in_days }).map(|created| {
+                println!("Created token {} ({}), it won't be shown again:", created.info.id, join_scopes(&created.info.scopes));
+                println!("{}", created.token);
+            })
+        }
+        TokensCommand::Revoke { id } => revoke_api_token(id).map(|_| println!("Revoked token {}", id)),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+    }
+}
+
+fn join_scopes(scopes: &[TokenScope]) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+// Read from stdin, so it can be piped in. At a terminal it isn't echoed, stty is the one way
+// to turn that off without pulling in a crate for it.
+fn read_password() -> io::Result<String> {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        eprint!("Password: ");
+        io::stderr().flush()?;
+        set_echo(false);
+    }
+    let mut password = String::new();
+    let read = io::stdin().read_line(&mut password);
+    if interactive {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn set_echo(on: bool) {
+    let _ = std::process::Command::new("stty").arg(if on { "echo" } else { "-echo" }).status();
+}
+
+// Lists can be given by id or by name, in any case
+fn resolve_list(list: &str) -> Result<u32, String> {
+    let lists = list_lists().map_err(|e| e.to_string())?;
+    lists
+        .iter()
+        .find(|entry| entry.id.to_string() == list || entry.name.to_lowercase() == list.to_lowercase())
+        .map(|entry| entry.id)
+        .ok_or_else(|| format!("there is no list called {}, see `lists show`", list))
+}
+
+// Members are given by username, the backend only knows them by id
+fn find_member(list_id: u32, username: &str) -> Result<u32, String> {
+    let members = list_members(list_id).map_err(|e| e.to_string())?;
+    members
+        .iter()
+        .find(|member| member.username == username.to_lowercase())
+        .map(|member| member.id)
+        .ok_or_else(|| format!("{} isn't a member of list {}, see `lists members`", username, list_id))
+}
+
+// Someone a todo can be assigned to, going by the list it's on
+fn resolve_member(id: u32, username: &str) -> Result<u32, String> {
+    match view_todo(id).map_err(|e| e.to_string())? {
+        Some(item) => find_member(item.list_id, username),
+        None => Err(format!("no todo found with id {}", id)),
+    }
+}
+
+// A todo that doesn't exist is let through, so the command itself can say so
+fn on_list(id: u32, list_id: u32) -> bool {
+    match view_todo(id) {
+        Ok(Some(item)) if item.list_id != list_id => {
+            eprintln!("Error: todo {} isn't on list {}", id, list_id);
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            false
+        }
+    }
+}
+
+fn run_lists(command: ListsCommand) {
+    let result = match command {
+        ListsCommand::Show => list_lists().map(|lists| {
+            println!("=== All Lists ===");
+            for TodoListEntry { id, name, count, role } in lists {
+                match role {
+                    ListRole::Owner => println!("[{}] {} ({})", id, name, count),
+                    role => println!("[{}] {} ({}) | Shared with you as {}", id, name, count, role),
+                }
+            }
+        }),
+        ListsCommand::Add { name } => {
+            create_list(&TodoList { name }).map(|entry| println!("Added list {} with id {}", entry.name, entry.id))
+        }
+        ListsCommand::Rename { list, name } => match resolve_list(&list) {
+            Ok(id) => rename_list(id, &TodoList { name }).map(|entry| println!("List {} is now called {}", id, entry.name)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
                 return;
             }
-            let id = &args[2];
-            match delete_todo(id) {
-                Ok(resp) => println!("Deleted successfully: {:?}", resp.status()),
-                Err(e) => eprintln!("Error deleting: {}", e),
+        },
+        ListsCommand::Delete { list, move_to } => {
+            let ids = resolve_list(&list).and_then(|id| Ok((id, move_to.as_deref().map(resolve_list).transpose()?)));
+            match ids {
+                Ok((id, move_to)) => delete_list(id, &ListDeleteQuery { move_to }).map(|_| println!("Deleted list {}", list)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            }
+        }
+        ListsCommand::Members { list } => match resolve_list(&list) {
+            Ok(id) => list_members(id).map(|members| print_members(&list, &members)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        },
+        ListsCommand::Share { list, username, role } => match resolve_list(&list) {
+            Ok(id) => share_list(id, &ListShare { username, role }).map(|members| print_members(&list, &members)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        },
+        ListsCommand::Unshare { list, username } => {
+            match resolve_list(&list).and_then(|id| Ok((id, find_member(id, &username)?))) {
+                Ok((id, user_id)) => unshare_list(id, user_id).map(|_| println!("List {} isn't shared with {} anymore", list, username)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
             }
         }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+    }
+}
+
+fn print_members(list: &str, members: &[ListMember]) {
+    println!("=== Members of {} ===", list);
+    for member in members {
+        println!("[{}] {} ({})", member.id, member.username, member.role);
+    }
+}
+
+fn run_checklist(command: ChecklistCommand) {
+    let result = match command {
+        ChecklistCommand::Show { id } => match view_todo(id) {
+            Ok(Some(item)) => Ok(TodoEntry { id, item }),
+            Ok(None) => {
+                println!("No todo found with id {}", id);
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        ChecklistCommand::Add { id, text, position } => add_checklist_item(id, &NewChecklistItem { text, position }),
+        ChecklistCommand::Check { id, item_id } => {
+            patch_checklist_item(id, item_id, &ChecklistPatch { done: Some(true), ..Default::default() })
+        }
+        ChecklistCommand::Uncheck { id, item_id } => {
+            patch_checklist_item(id, item_id, &ChecklistPatch { done: Some(false), ..Default::default() })
+        }
+        ChecklistCommand::Edit { id, item_id, text } => {
+            patch_checklist_item(id, item_id, &ChecklistPatch { text: Some(text), ..Default::default() })
+        }
+        ChecklistCommand::Move { id, item_id, position } => {
+            patch_checklist_item(id, item_id, &ChecklistPatch { position: Some(position), ..Default::default() })
+        }
+        ChecklistCommand::Remove { id, item_id } => delete_checklist_item(id, item_id),
+    };
+
+    // Every change answers with the todo, so show how the checklist looks now
+    match result {
+        Ok(entry) => {
+            let (done, total) = entry.item.checklist_progress();
+            println!("=== Checklist of todo {} ({}/{}) ===", entry.id, done, total);
+            for step in &entry.item.checklist {
+                println!("[{}] {}: {}", if step.done { "x" } else { " " }, step.id, step.text);
+            }
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+// One change per line, and for an update one more line for every field it changed
+fn print_history_entry(entry: &HistoryEntry) {
+    let actor = match (&entry.actor, entry.actor_id) {
+        (Some(username), _) => username.clone(),
+        // An account that's gone since
+        (None, Some(user_id)) => format!("user {}", user_id),
+        (None, None) => "anonymous".to_string(),
+    };
+    let undo = if entry.undo { " (undo)" } else { "" };
+    match entry.action {
+        HistoryAction::Created => {
+            println!("[rev {}] {} {} created \"{}\"{}", entry.revision, entry.at.to_rfc3339(), actor, entry.item.title, undo)
+        }
+        action => println!("[rev {}] {} {} {}{}", entry.revision, entry.at.to_rfc3339(), actor, action, undo),
+    }
+    for change in &entry.changes {
+        println!("    {}: {} -> {}", change.field, change.before, change.after);
+    }
+}
+
+fn print_entry(entry: &TodoEntry, depth: usize) {
+    let mut line = format!(
+        "{}[{}] {} - {} | Due: {} | Completed: {}",
+        "    ".repeat(depth),
+        entry.id,
+        entry.item.title,
+        entry.item.description,
+        entry.item.due_date.to_rfc3339(),
+        entry.item.completed
+    );
+    if entry.item.priority != Priority::None {
+        line.push_str(&format!(" | Priority: {}", entry.item.priority));
+    }
+    if !entry.item.tags.is_empty() {
+        line.push_str(&format!(" | Tags: {}", entry.item.tags.join(", ")));
+    }
+    if !entry.item.checklist.is_empty() {
+        let (done, total) = entry.item.checklist_progress();
+        line.push_str(&format!(" | Checklist: {}/{}", done, total));
+    }
+    if !entry.item.blocked_by.is_empty() {
+        line.push_str(&format!(" | Blocked by: {}", join_ids(&entry.item.blocked_by)));
+    }
+    if let Some(recurrence) = &entry.item.recurrence {
+        line.push_str(&format!(" | Repeats: {}", recurrence));
+    }
+    if let Some(assigned_to) = entry.item.assigned_to {
+        line.push_str(&format!(" | Assigned to: user {}", assigned_to));
+    }
+    match priority_color(entry.item.priority) {
+        Some(code) if use_color() => println!("\x1b[{}m{}\x1b[0m", code, line),
+        _ => println!("{}", line),
+    }
+}
+
+// Keeps the order the backend sorted them in among siblings. A subtask whose parent was
+// filtered out (or is on another page) is shown at the top level.
+fn print_tree(entries: &[TodoEntry]) {
+    fn print_children(entries: &[TodoEntry], parent_id: u32, depth: usize) {
+        for entry in entries.iter().filter(|entry| entry.item.parent_id == Some(parent_id)) {
+            print_entry(entry, depth);
+            print_children(entries, entry.id, depth + 1);
+        }
+    }
 
-        _ => print_usage(),
+    let is_listed = |id: u32| entries.iter().any(|entry| entry.id == id);
+    for entry in entries.iter().filter(|entry| !entry.item.parent_id.is_some_and(is_listed)) {
+        print_entry(entry, 0);
+        print_children(entries, entry.id, 1);
+    }
+}
+
+// Only color when writing to a terminal, and respect https://no-color.org
+fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+// ANSI SGR codes, getting louder with the priority
+fn priority_color(priority: Priority) -> Option<&'static str> {
+    match priority {
+        Priority::None => None,
+        Priority::Low => Some("36"),
+        Priority::Medium => Some("33"),
+        Priority::High => Some("31"),
+        Priority::Urgent => Some("1;31"),
+    }
+}
+
+fn set_completed(id: u32, completed: bool) {
+    let patch = TodoPatch {
+        completed: Some(completed),
+        ..Default::default()
+    };
+    match patch_todo(id, &patch) {
+        Ok(resp) => {
+            println!(
+                "{} todo {} successfully",
+                if completed { "COMPLETE" } else { "INCOMPLETE" },
+                resp.entry.id
+            );
+            print_effects(&resp);
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+// What completing a todo set off on the backend
+fn print_effects(resp: &UpdateResponse) {
+    if !resp.unblocked.is_empty() {
+        println!("Unblocked: {}", join_ids(&resp.unblocked));
+    }
+    if let Some(next_id) = resp.next_occurrence {
+        println!("Next occurrence added as todo {}", next_id);
     }
 }
 
-fn print_usage() {
-    eprintln!("Usage:");
-    eprintln!("  list");
-    eprintln!("  view <id>");
-    eprintln!("  insert <title> <description> [due_date]");
-    eprintln!("  update <id> <title> <description> <due_date> <completed>");
-    eprintln!("  complete <id>");
-    eprintln!("  incomplete <id>");
-    eprintln!("  delete <id>");
-    eprintln!();
-    eprintln!("Example ISO 8601 datetime: 2025-11-20T23:59:59Z");
+fn join_ids(ids: &[u32]) -> String {
+    ids.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")
 }
@@ -1,49 +1,263 @@
-use std::collections::BTreeMap;
+use std::fmt;
 use reqwest::blocking::{Client, Response};
-use reqwest::header::CONTENT_TYPE;
-use todo_list_common::TodoItem;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue};
+use todo_list_common::{
+    credentials, ApiError, ApiTokenInfo, ChecklistPatch, CreatedApiToken, Credentials, DeleteQuery, NewChecklistItem, ErrorCode, HistoryEntry, InsertResponse, ListDeleteQuery, ListMember, ListShare, LoginResponse, NewApiToken, RevertQuery, TagCount, TagQuery,
+    TagRename, TagRenameResponse, TodoEntry, TodoItem, TodoList, TodoListEntry, TodoPage, TodoPatch, TodoQuery, UndoResponse, UpdateResponse, UserInfo,
+};
 
 const BASE_URL: &str = "http://127.0.0.1:8081";
 
+/// Either the backend couldn't be reached at all, or it answered with an error.
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Api(ApiError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "could not talk to the backend at {}: {}", BASE_URL, err),
+            ClientError::Api(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+// Every request carries a token: the one in $TODO_TOKEN, so scripts can use an API token
+// without logging in, or else the one from `login`, for as long as there is one
 fn get_client() -> Client {
-    Client::new()
+    let mut headers = HeaderMap::new();
+    if let Some(token) = credentials::token()
+        && let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", token))
+    {
+        headers.insert(AUTHORIZATION, value);
+    }
+    Client::builder().default_headers(headers).build().unwrap_or_default()
 }
 
-pub fn insert_todo(item: &TodoItem) -> Result<Response, reqwest::Error> {
-    get_client()
-        .post(&format!("{}/insert", BASE_URL))
+// Turns an error status into the ApiError the backend sent along with it
+fn check(resp: Response) -> Result<Response, ClientError> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status().as_u16();
+    let body = resp.text().unwrap_or_default();
+    Err(ClientError::Api(ApiError::from_response(status, &body)))
+}
+
+pub fn insert_todo(item: &TodoItem) -> Result<InsertResponse, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/insert", BASE_URL))
         .header(CONTENT_TYPE, "application/json")
         .json(item)
-        .send()
+        .send()?;
+    Ok(check(resp)?.json::<InsertResponse>()?)
 }
 
-pub fn update_todo(id: &str, item: &TodoItem) -> Result<Response, reqwest::Error> {
-    get_client()
-        .put(&format!("{}/update/{}", BASE_URL, id))
+pub fn patch_todo(id: u32, patch: &TodoPatch) -> Result<UpdateResponse, ClientError> {
+    let resp = get_client()
+        .patch(format!("{}/todos/{}", BASE_URL, id))
         .header(CONTENT_TYPE, "application/json")
-        .json(item)
-        .send()
+        .json(patch)
+        .send()?;
+    Ok(check(resp)?.json::<UpdateResponse>()?)
 }
 
-pub fn delete_todo(id: &str) -> Result<Response, reqwest::Error> {
-    get_client()
-        .delete(&format!("{}/delete/{}", BASE_URL, id))
-        .send()
+pub fn delete_todo(id: u32, query: &DeleteQuery) -> Result<Response, ClientError> {
+    let resp = get_client()
+        .delete(format!("{}/delete/{}", BASE_URL, id))
+        .query(query)
+        .send()?;
+    check(resp)
 }
 
-pub fn view_todo(id: &str) -> Result<Option<TodoItem>, reqwest::Error> {
+pub fn view_todo(id: u32) -> Result<Option<TodoItem>, ClientError> {
     let resp = get_client()
-        .get(&format!("{}/view/{}", BASE_URL, id))
+        .get(format!("{}/view/{}", BASE_URL, id))
         .send()?;
 
-    if resp.status().is_success() {
-        Ok(Some(resp.json::<TodoItem>()?))
-    } else {
-        Ok(None)
+    match check(resp) {
+        Ok(resp) => Ok(Some(resp.json::<TodoItem>()?)),
+        Err(ClientError::Api(err)) if err.code == ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
     }
 }
 
-pub fn list_todos() -> Result<BTreeMap<String, TodoItem>, reqwest::Error> {
-    let resp = get_client().get(&format!("{}/list", BASE_URL)).send()?;
-    resp.json::<BTreeMap<String, TodoItem>>()
+pub fn todo_history(id: u32) -> Result<Vec<HistoryEntry>, ClientError> {
+    let resp = get_client().get(format!("{}/todos/{}/history", BASE_URL, id)).send()?;
+    Ok(check(resp)?.json::<Vec<HistoryEntry>>()?)
+}
+
+pub fn revert_todo(id: u32, revision: u64) -> Result<TodoEntry, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/todos/{}/revert", BASE_URL, id))
+        .query(&RevertQuery { to: revision })
+        .send()?;
+    Ok(check(resp)?.json::<TodoEntry>()?)
+}
+
+pub fn undo() -> Result<UndoResponse, ClientError> {
+    let resp = get_client().post(format!("{}/undo", BASE_URL)).send()?;
+    Ok(check(resp)?.json::<UndoResponse>()?)
+}
+
+pub fn list_todos(query: &TodoQuery) -> Result<TodoPage, ClientError> {
+    let resp = get_client()
+        .get(format!("{}/list", BASE_URL))
+        .query(query)
+        .send()?;
+    Ok(check(resp)?.json::<TodoPage>()?)
+}
+
+pub fn list_tags(query: &TagQuery) -> Result<Vec<TagCount>, ClientError> {
+    let resp = get_client()
+        .get(format!("{}/tags", BASE_URL))
+        .query(query)
+        .send()?;
+    Ok(check(resp)?.json::<Vec<TagCount>>()?)
+}
+
+pub fn rename_tag(rename: &TagRename) -> Result<TagRenameResponse, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/tags/rename", BASE_URL))
+        .header(CONTENT_TYPE, "application/json")
+        .json(rename)
+        .send()?;
+    Ok(check(resp)?.json::<TagRenameResponse>()?)
+}
+
+pub fn add_checklist_item(id: u32, new_item: &NewChecklistItem) -> Result<TodoEntry, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/todos/{}/checklist", BASE_URL, id))
+        .header(CONTENT_TYPE, "application/json")
+        .json(new_item)
+        .send()?;
+    Ok(check(resp)?.json::<TodoEntry>()?)
+}
+
+pub fn patch_checklist_item(id: u32, item_id: u32, patch: &ChecklistPatch) -> Result<TodoEntry, ClientError> {
+    let resp = get_client()
+        .patch(format!("{}/todos/{}/checklist/{}", BASE_URL, id, item_id))
+        .header(CONTENT_TYPE, "application/json")
+        .json(patch)
+        .send()?;
+    Ok(check(resp)?.json::<TodoEntry>()?)
+}
+
+pub fn delete_checklist_item(id: u32, item_id: u32) -> Result<TodoEntry, ClientError> {
+    let resp = get_client()
+        .delete(format!("{}/todos/{}/checklist/{}", BASE_URL, id, item_id))
+        .send()?;
+    Ok(check(resp)?.json::<TodoEntry>()?)
+}
+
+pub fn list_children(id: u32) -> Result<Vec<TodoEntry>, ClientError> {
+    let resp = get_client()
+        .get(format!("{}/todos/{}/children", BASE_URL, id))
+        .send()?;
+    Ok(check(resp)?.json::<Vec<TodoEntry>>()?)
+}
+
+pub fn list_lists() -> Result<Vec<TodoListEntry>, ClientError> {
+    let resp = get_client().get(format!("{}/lists", BASE_URL)).send()?;
+    Ok(check(resp)?.json::<Vec<TodoListEntry>>()?)
+}
+
+pub fn create_list(list: &TodoList) -> Result<TodoListEntry, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/lists", BASE_URL))
+        .header(CONTENT_TYPE, "application/json")
+        .json(list)
+        .send()?;
+    Ok(check(resp)?.json::<TodoListEntry>()?)
+}
+
+pub fn rename_list(id: u32, list: &TodoList) -> Result<TodoListEntry, ClientError> {
+    let resp = get_client()
+        .put(format!("{}/lists/{}", BASE_URL, id))
+        .header(CONTENT_TYPE, "application/json")
+        .json(list)
+        .send()?;
+    Ok(check(resp)?.json::<TodoListEntry>()?)
+}
+
+pub fn delete_list(id: u32, query: &ListDeleteQuery) -> Result<Response, ClientError> {
+    let resp = get_client()
+        .delete(format!("{}/lists/{}", BASE_URL, id))
+        .query(query)
+        .send()?;
+    check(resp)
+}
+
+pub fn list_members(id: u32) -> Result<Vec<ListMember>, ClientError> {
+    let resp = get_client().get(format!("{}/lists/{}/members", BASE_URL, id)).send()?;
+    Ok(check(resp)?.json::<Vec<ListMember>>()?)
+}
+
+pub fn share_list(id: u32, share: &ListShare) -> Result<Vec<ListMember>, ClientError> {
+    let resp = get_client()
+        .put(format!("{}/lists/{}/shares", BASE_URL, id))
+        .header(CONTENT_TYPE, "application/json")
+        .json(share)
+        .send()?;
+    Ok(check(resp)?.json::<Vec<ListMember>>()?)
+}
+
+pub fn unshare_list(id: u32, user_id: u32) -> Result<Response, ClientError> {
+    let resp = get_client().delete(format!("{}/lists/{}/shares/{}", BASE_URL, id, user_id)).send()?;
+    check(resp)
+}
+
+pub fn register(credentials: &Credentials) -> Result<UserInfo, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/auth/register", BASE_URL))
+        .header(CONTENT_TYPE, "application/json")
+        .json(credentials)
+        .send()?;
+    Ok(check(resp)?.json::<UserInfo>()?)
+}
+
+pub fn login(credentials: &Credentials) -> Result<LoginResponse, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/auth/login", BASE_URL))
+        .header(CONTENT_TYPE, "application/json")
+        .json(credentials)
+        .send()?;
+    Ok(check(resp)?.json::<LoginResponse>()?)
+}
+
+pub fn logout() -> Result<Response, ClientError> {
+    let resp = get_client().post(format!("{}/auth/logout", BASE_URL)).send()?;
+    check(resp)
+}
+
+pub fn whoami() -> Result<UserInfo, ClientError> {
+    let resp = get_client().get(format!("{}/auth/me", BASE_URL)).send()?;
+    Ok(check(resp)?.json::<UserInfo>()?)
+}
+
+pub fn create_api_token(new_token: &NewApiToken) -> Result<CreatedApiToken, ClientError> {
+    let resp = get_client()
+        .post(format!("{}/auth/tokens", BASE_URL))
+        .header(CONTENT_TYPE, "application/json")
+        .json(new_token)
+        .send()?;
+    Ok(check(resp)?.json::<CreatedApiToken>()?)
+}
+
+pub fn list_api_tokens() -> Result<Vec<ApiTokenInfo>, ClientError> {
+    let resp = get_client().get(format!("{}/auth/tokens", BASE_URL)).send()?;
+    Ok(check(resp)?.json::<Vec<ApiTokenInfo>>()?)
+}
+
+pub fn revoke_api_token(id: u32) -> Result<Response, ClientError> {
+    let resp = get_client().delete(format!("{}/auth/tokens/{}", BASE_URL, id)).send()?;
+    check(resp)
 }
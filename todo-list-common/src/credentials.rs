@@ -0,0 +1,71 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What logging in leaves behind for the requests after it, and logging out removes again.
+/// The CLI and the frontend share the file, so logging in with one logs in the other as well.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredCredentials {
+    pub username: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// `$TODO_CREDENTIALS` if set, otherwise `credentials.json` in the user's config directory.
+pub fn credentials_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("TODO_CREDENTIALS") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::home_dir()?.join(".config"),
+    };
+    Some(config_dir.join("todo-list").join("credentials.json"))
+}
+
+/// The token to send: `$TODO_TOKEN` if it's set, otherwise the one stored by logging in.
+pub fn token() -> Option<String> {
+    match env::var("TODO_TOKEN") {
+        Ok(token) if !token.trim().is_empty() => Some(token.trim().to_string()),
+        _ => load().map(|credentials| credentials.token),
+    }
+}
+
+// A file that can't be read or parsed is treated as not being logged in, logging in overwrites it
+pub fn load() -> Option<StoredCredentials> {
+    let contents = fs::read_to_string(credentials_path()?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+pub fn save(credentials: &StoredCredentials) -> io::Result<PathBuf> {
+    let path = credentials_path().ok_or_else(|| io::Error::other("no home directory, set TODO_CREDENTIALS"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // The token is as good as the password until it expires, so only the user gets to read it
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    file.write_all(serde_json::to_string_pretty(credentials)?.as_bytes())?;
+    Ok(path)
+}
+
+/// Whether there were credentials to remove.
+pub fn remove() -> io::Result<bool> {
+    let Some(path) = credentials_path() else {
+        return Ok(false);
+    };
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
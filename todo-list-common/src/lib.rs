@@ -1,16 +1,1055 @@
+pub mod credentials;
+mod recurrence;
+
+use std::fmt;
+
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
+pub use recurrence::{Recurrence, RecurrenceRule};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct TodoItem {
     pub title: String,
     pub description: String,
     pub due_date: DateTime<Utc>,
+    // The backend sets these three itself, values sent by a client are ignored
+    #[serde(default)]
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
     pub completed: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Free-form labels. The backend trims and lowercases them and drops duplicates.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The steps of the todo, in order
+    #[serde(default)]
+    pub checklist: Vec<ChecklistItem>,
+    /// The id the next new step of the checklist gets. Kept by the backend, so the id of a
+    /// removed step isn't handed out again, whatever a client puts here is ignored.
+    #[serde(default)]
+    pub next_step_id: u32,
+    /// The todo this one is a subtask of. The backend refuses parents that don't exist or
+    /// would make a todo its own ancestor.
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+    /// Todos that have to be completed before this one can be started. The backend refuses
+    /// ids that don't exist and chains of blockers that lead back to this todo.
+    #[serde(default)]
+    pub blocked_by: Vec<u32>,
+    /// Completing a todo that repeats adds its next occurrence, which takes the recurrence over
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// The named list the todo is on, the Inbox unless another one is picked
+    #[serde(default)]
+    pub list_id: u32,
+    /// The user who's doing it. Has to be the owner of the todo's list or someone it's shared
+    /// with, and on the Inbox the owner of the todo.
+    #[serde(default)]
+    pub assigned_to: Option<u32>,
+    /// The user the todo belongs to, set by the backend from the token it was created with.
+    /// Todos created without one have no owner, and are only seen by requests without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<u32>,
+    /// Bumped by the backend on every change, and sent as the item's ETag. Whatever a client
+    /// puts here is ignored, except as the revision it expects when it sends `If-Match`.
+    #[serde(default)]
+    pub revision: u64,
+}
+
+/// How important a todo is, from `None` (the default) up to `Urgent`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    None,
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 5] = [Priority::None, Priority::Low, Priority::Medium, Priority::High, Priority::Urgent];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.as_str() == s)
+            .ok_or_else(|| format!("unknown priority '{}', expected none, low, medium, high or urgent", s))
+    }
+}
+
+impl TodoItem {
+    /// The strong ETag for this revision of the item, e.g. `"3"`.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.revision)
+    }
+
+    /// How many checklist items are done, out of how many.
+    pub fn checklist_progress(&self) -> (usize, usize) {
+        let done = self.checklist.iter().filter(|entry| entry.done).count();
+        (done, self.checklist.len())
+    }
+}
+
+/// One step of a todo's checklist. The id stays the same when steps are moved around, it's
+/// unique within the todo and never reused. A step sent with id 0, or with an id the backend
+/// didn't hand out, is new and gets the next id on save.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChecklistItem {
+    #[serde(default)]
+    pub id: u32,
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+}
+
+/// Body for `POST /todos/{id}/checklist`. Without a position the step goes at the end.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NewChecklistItem {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+/// Body for `PATCH /todos/{id}/checklist/{item_id}`. `position` moves the step, 0 being the top.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChecklistPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+/// A todo together with its id, as returned by the endpoints that hand back a single item.
+/// The item's fields are flattened, so it reads as `{"id": 3, "title": ..., ...}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TodoEntry {
+    pub id: u32,
+    #[serde(flatten)]
+    pub item: TodoItem,
+}
+
+/// One message of the `GET /events` stream. Its SSE event name is the `type`, and the data is
+/// the whole event as JSON, e.g. `{"type": "updated", "id": 3, "item": {...}}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TodoEvent {
+    Created { id: u32, item: TodoItem },
+    Updated { id: u32, item: TodoItem },
+    /// Also sent when a todo moves somewhere the subscriber can't see it anymore
+    Deleted { id: u32 },
+    /// The subscriber fell behind and missed events, it should load the todos again
+    Resync,
+}
+
+impl TodoEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TodoEvent::Created { .. } => "created",
+            TodoEvent::Updated { .. } => "updated",
+            TodoEvent::Deleted { .. } => "deleted",
+            TodoEvent::Resync => "resync",
+        }
+    }
+}
+
+/// Version of the `/sync` WebSocket protocol, sent in `subscribe`. Bumped whenever its
+/// messages change in a way that older clients wouldn't understand.
+pub const SYNC_PROTOCOL_VERSION: u32 = 1;
+
+/// What a client sends over the `/sync` WebSocket, one JSON text message each, e.g.
+/// `{"type": "subscribe", "version": 1}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Has to come first. From then on, every change to the todos the client can see is sent.
+    Subscribe { version: u32 },
+    /// A change the client makes, `request_id` is its own and comes back in the answer.
+    Mutate { request_id: u64, mutation: Box<Mutation> },
+}
+
+/// The change a `mutate` message makes. `revision` is the one the client last saw, like
+/// `If-Match` over HTTP, and without it the last write wins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Mutation {
+    Create {
+        item: TodoItem,
+    },
+    Update {
+        id: u32,
+        #[serde(default)]
+        revision: Option<u64>,
+        patch: TodoPatch,
+    },
+    Delete {
+        id: u32,
+        #[serde(default)]
+        revision: Option<u64>,
+        #[serde(default)]
+        children: Option<ChildrenOnDelete>,
+    },
+}
+
+/// What the backend sends over the `/sync` WebSocket.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Answers `subscribe`, with the protocol version the backend speaks
+    Subscribed { version: u32 },
+    /// A todo was changed, by this client or another one. A client's own change comes after its `ack`.
+    Change { event: TodoEvent },
+    /// The mutation went through. `revision` is the todo's new one, there's none after a delete.
+    Ack { request_id: u64, id: u32, revision: Option<u64> },
+    /// The todo was changed since the revision the mutation names, so nothing was done.
+    /// `current` is how it looks now.
+    Conflict { request_id: u64, id: u32, current: TodoItem },
+    /// Anything else that went wrong. `request_id` is missing when the message couldn't be read.
+    Error { request_id: Option<u64>, error: ApiError },
+}
+
+/// Returned by `PUT /update/{id}` and `PATCH /todos/{id}`. Reads as a `TodoEntry` with what
+/// completing the todo set off on top.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateResponse {
+    #[serde(flatten)]
+    pub entry: TodoEntry,
+    /// The todos that completing this one left with no open blockers
+    #[serde(default)]
+    pub unblocked: Vec<u32>,
+    /// The todo added as the next occurrence, when this one repeats and was just completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_occurrence: Option<u32>,
+}
+
+/// Partial update for `PATCH /todos/{id}`. Only the fields that are set get changed, so
+/// flipping `completed` doesn't require sending (and possibly clobbering) everything else.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TodoPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// Replaces all of the todo's tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// `Some(None)`, sent as `"parent_id": null`, makes the todo a top level one again
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Option<u32>>,
+    /// Replaces all of the todo's blockers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked_by: Option<Vec<u32>>,
+    /// `null` stops the todo from repeating
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Option<Recurrence>>,
+    /// Moves the todo to another list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_id: Option<u32>,
+    /// `null` unassigns the todo
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub assigned_to: Option<Option<u32>>,
+}
+
+// Tells a field that was sent as null (Some(None)) apart from one that wasn't sent at all (None)
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl TodoPatch {
+    pub fn apply(&self, item: &mut TodoItem) {
+        if let Some(title) = &self.title {
+            item.title = title.clone();
+        }
+        if let Some(description) = &self.description {
+            item.description = description.clone();
+        }
+        if let Some(due_date) = self.due_date {
+            item.due_date = due_date;
+        }
+        if let Some(completed) = self.completed {
+            item.completed = completed;
+        }
+        if let Some(priority) = self.priority {
+            item.priority = priority;
+        }
+        if let Some(tags) = &self.tags {
+            item.tags = tags.clone();
+        }
+        if let Some(parent_id) = self.parent_id {
+            item.parent_id = parent_id;
+        }
+        if let Some(blocked_by) = &self.blocked_by {
+            item.blocked_by = blocked_by.clone();
+        }
+        if let Some(recurrence) = &self.recurrence {
+            item.recurrence = recurrence.clone();
+        }
+        if let Some(list_id) = self.list_id {
+            item.list_id = list_id;
+        }
+        if let Some(assigned_to) = self.assigned_to {
+            item.assigned_to = assigned_to;
+        }
+    }
+}
+
+/// What `DELETE /delete/{id}?children=...` does with the subtasks of the deleted todo. Without
+/// it, deleting a todo that has subtasks is refused.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChildrenOnDelete {
+    /// Delete all subtasks with it, all the way down
+    Cascade,
+    /// Move the direct subtasks up to the deleted todo's own parent
+    Reparent,
+}
+
+impl std::str::FromStr for ChildrenOnDelete {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cascade" => Ok(ChildrenOnDelete::Cascade),
+            "reparent" => Ok(ChildrenOnDelete::Reparent),
+            _ => Err(format!("unknown children mode '{}', expected cascade or reparent", s)),
+        }
+    }
+}
+
+/// Query parameters of `DELETE /delete/{id}`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+pub struct DeleteQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<ChildrenOnDelete>,
+}
+
+/// The list every todo starts on. It always exists, and can be renamed but not deleted.
+pub const INBOX_ID: u32 = 0;
+
+/// A named list of todos, like "Work" or "Home". Also the body for `POST /lists` and
+/// `PUT /lists/{id}`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TodoList {
+    pub name: String,
+}
+
+/// How the backend hands out a list: with its id, the number of todos on it and what the
+/// caller may do with it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoListEntry {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub count: u32,
+    #[serde(default)]
+    pub role: ListRole,
+}
+
+/// What a user may do with a list. Its owner may do anything, and shares it with others as
+/// an editor, who may change its todos, or a viewer, who may only look at them.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ListRole {
+    #[default]
+    Owner,
+    Editor,
+    Viewer,
+}
+
+impl ListRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ListRole::Owner => "owner",
+            ListRole::Editor => "editor",
+            ListRole::Viewer => "viewer",
+        }
+    }
+}
+
+impl fmt::Display for ListRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for ListRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(ListRole::Owner),
+            "editor" => Ok(ListRole::Editor),
+            "viewer" => Ok(ListRole::Viewer),
+            _ => Err(format!("unknown role '{}', expected owner, editor or viewer", s)),
+        }
+    }
+}
+
+/// Body for `PUT /lists/{id}/shares`, sharing the list with a user or changing their role.
+/// `role` is `editor` or `viewer`, a list has only the one owner.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListShare {
+    pub username: String,
+    pub role: ListRole,
+}
+
+/// One entry of `GET /lists/{id}/members`: the owner, and everyone the list is shared with.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ListMember {
+    pub id: u32,
+    pub username: String,
+    pub role: ListRole,
+}
+
+/// Query parameters of `DELETE /lists/{id}`. A list that still has todos is only deleted when
+/// they can be moved to `move_to`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+pub struct ListDeleteQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub move_to: Option<u32>,
+}
+
+/// Body for `POST /auth/register` and `POST /auth/login`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// An account, as the backend hands it out. The password hash never leaves the backend.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UserInfo {
+    pub id: u32,
+    pub username: String,
+}
+
+/// Returned by `POST /auth/login`. Send the token as `Authorization: Bearer <token>`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginResponse {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: UserInfo,
+}
+
+/// What a personal API token may do. `read` covers every `GET`, `write` everything else.
+/// Logging in gives every scope.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    Read,
+    Write,
+}
+
+impl TokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+        }
+    }
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "write" => Ok(TokenScope::Write),
+            _ => Err(format!("unknown scope '{}', expected read or write", s)),
+        }
+    }
+}
+
+/// Body for `POST /auth/tokens`. Without `expires_in_days` the token is valid until it's revoked.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NewApiToken {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_days: Option<u32>,
+}
+
+/// A personal API token, as `GET /auth/tokens` lists it. The token itself is only shown once,
+/// when it's created.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiTokenInfo {
+    pub id: u32,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Returned by `POST /auth/tokens`, the only time `token` can be seen.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    pub info: ApiTokenInfo,
+    pub token: String,
+}
+
+/// What a webhook can be told about. `completed` is a todo going from open to completed, and
+/// `overdue` its due date passing while it's still open.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Created,
+    Completed,
+    Overdue,
+    Deleted,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Created => "created",
+            WebhookEvent::Completed => "completed",
+            WebhookEvent::Overdue => "overdue",
+            WebhookEvent::Deleted => "deleted",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Body for `POST /webhooks`: where to send the events, and which ones.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+/// A webhook, as `GET /webhooks` lists it. Its secret is only shown once, when it's created.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookInfo {
+    pub id: u32,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned by `POST /webhooks`. Every delivery is signed with `secret`, see `WebhookPayload`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub info: WebhookInfo,
+    pub secret: String,
+}
+
+/// The body the backend POSTs to a webhook. Its `X-Todo-Signature` header is
+/// `sha256=<hex>`, the HMAC-SHA256 of the body under the webhook's secret. For `deleted`,
+/// `item` is the todo as it was.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub id: u32,
+    pub item: TodoItem,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Where a delivery stands. A pending one is retried until it goes through or runs out of attempts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// One try at delivering to a webhook. `status` is missing when no response came back at all.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeliveryAttempt {
+    pub at: DateTime<Utc>,
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// One entry of `GET /webhooks/{id}/deliveries`, newest first.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookDelivery {
+    pub id: u32,
+    pub event: WebhookEvent,
+    pub todo_id: u32,
+    pub status: DeliveryStatus,
+    pub created_at: DateTime<Utc>,
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+/// What a change did to a todo.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl HistoryAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryAction::Created => "created",
+            HistoryAction::Updated => "updated",
+            HistoryAction::Deleted => "deleted",
+        }
+    }
+}
+
+impl fmt::Display for HistoryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One field an update changed, with its value before and after as JSON. A field that's only
+/// there on one side is `null` on the other.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+/// One entry of `GET /todos/{id}/history`, oldest first. `item` is the todo right after the
+/// change, or as it was when it was deleted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub action: HistoryAction,
+    pub revision: u64,
+    pub at: DateTime<Utc>,
+    /// Who made the change, missing when nobody was logged in
+    #[serde(default)]
+    pub actor_id: Option<u32>,
+    #[serde(default)]
+    pub actor: Option<String>,
+    /// Empty unless `action` is `updated`
+    #[serde(default)]
+    pub changes: Vec<FieldChange>,
+    /// Made by `POST /undo`, taking back an earlier change
+    #[serde(default)]
+    pub undo: bool,
+    pub item: TodoItem,
+}
+
+/// Query parameters of `POST /todos/{id}/revert`, the revision to put the todo back to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RevertQuery {
+    pub to: u64,
+}
+
+/// Query parameters of `POST /undo`. Without an operation, the caller's last one is undone.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+pub struct UndoQuery {
+    pub operation: Option<u64>,
+}
+
+/// Response header naming the operation a request's changes were recorded as, the one to pass
+/// to `POST /undo` to take back exactly those.
+pub const OPERATION_HEADER: &str = "operation-id";
+
+/// Returned by `POST /undo`: the todos the undone operation had changed or deleted, as they
+/// are now, and the ones it had created, which are gone again.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct UndoResponse {
+    pub restored: Vec<TodoEntry>,
+    pub deleted: Vec<u32>,
+}
+
+/// Query parameters of `GET /tags`, to only count the tags on one list.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+pub struct TagQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list: Option<u32>,
+}
+
+/// Body returned by `POST /insert`, so clients know which id the backend allocated.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct InsertResponse {
+    pub id: u32,
+}
+
+/// Filters, sorting and paging for `GET /list`, sent as query parameters. Fields left as `None`
+/// don't filter anything. Build one with the methods below:
+///
+/// ```
+/// # use todo_list_common::{SortField, SortOrder, TodoQuery};
+/// let query = TodoQuery::new().completed(false).search("milk").sort(SortField::DueDate, SortOrder::Asc).limit(20);
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TodoQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+    /// Only todos due strictly before this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_before: Option<DateTime<Utc>>,
+    /// Only todos due strictly after this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_after: Option<DateTime<Utc>>,
+    /// Case insensitive text search over the title and description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    /// Only todos with this tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only todos on the list with this id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list: Option<u32>,
+    /// Only todos assigned to the user with this id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assigned_to: Option<u32>,
+    /// Only open todos whose blockers are all completed (`true`), or only open todos still
+    /// waiting on one (`false`). This depends on the other todos, so `matches` leaves it to
+    /// the backend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actionable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+    /// At most this many todos per page. Without it, everything comes back in one page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// The `next_cursor` of the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+impl TodoQuery {
+    pub fn new() -> Self {
+        TodoQuery::default()
+    }
+
+    pub fn completed(mut self, completed: bool) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+
+    pub fn due_before(mut self, due_before: DateTime<Utc>) -> Self {
+        self.due_before = Some(due_before);
+        self
+    }
+
+    pub fn due_after(mut self, due_after: DateTime<Utc>) -> Self {
+        self.due_after = Some(due_after);
+        self
+    }
+
+    pub fn search(mut self, text: impl Into<String>) -> Self {
+        self.q = Some(text.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    pub fn list(mut self, list_id: u32) -> Self {
+        self.list = Some(list_id);
+        self
+    }
+
+    pub fn assigned_to(mut self, user_id: u32) -> Self {
+        self.assigned_to = Some(user_id);
+        self
+    }
+
+    pub fn actionable(mut self, actionable: bool) -> Self {
+        self.actionable = Some(actionable);
+        self
+    }
+
+    pub fn sort(mut self, field: SortField, order: SortOrder) -> Self {
+        self.sort = Some(field);
+        self.order = Some(order);
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.cursor = Some(cursor.into());
+        self
+    }
+
+    /// Whether `item` passes the filters. Sorting and paging are left to the backend.
+    pub fn matches(&self, item: &TodoItem) -> bool {
+        self.completed.is_none_or(|completed| item.completed == completed)
+            && self.due_before.is_none_or(|due_before| item.due_date < due_before)
+            && self.due_after.is_none_or(|due_after| item.due_date > due_after)
+            && self.q.as_deref().is_none_or(|q| {
+                let q = q.to_lowercase();
+                item.title.to_lowercase().contains(&q) || item.description.to_lowercase().contains(&q)
+            })
+            && self.tag.as_deref().is_none_or(|tag| {
+                let tag = normalize_tag(tag);
+                item.tags.contains(&tag)
+            })
+            && self.list.is_none_or(|list_id| item.list_id == list_id)
+            && self.assigned_to.is_none_or(|user_id| item.assigned_to == Some(user_id))
+    }
+}
+
+/// Tags are stored trimmed and lowercase, so `Work` and ` work` end up as the same tag.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// One entry of `GET /tags`: a tag and how many todos carry it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u32,
+}
+
+/// Body for `POST /tags/rename`. Renaming onto a tag that already exists merges the two.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TagRename {
+    pub from: String,
+    pub to: String,
+    /// Only rename the tag on the todos of this list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list: Option<u32>,
+}
+
+/// Returned by `POST /tags/rename`, with the number of todos that were changed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TagRenameResponse {
+    pub renamed: u32,
+}
+
+/// What `GET /list` sorts by. Ties are always broken by id, so the order is stable.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Id,
+    DueDate,
+    CreatedAt,
+    Title,
+    /// What to work on next: open todos before completed ones, then by a score that weighs
+    /// the priority against how overdue or close to due a todo is, then by due date.
+    Smart,
+}
+
+impl std::str::FromStr for SortField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortField::Id),
+            "due_date" => Ok(SortField::DueDate),
+            "created_at" => Ok(SortField::CreatedAt),
+            "title" => Ok(SortField::Title),
+            "smart" => Ok(SortField::Smart),
+            _ => Err(format!("unknown sort field '{}', expected id, due_date, created_at, title or smart", s)),
+        }
+    }
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// One page of `GET /list`. Send `next_cursor` back as the `cursor` of the same query to get
+/// the page after it, it's `None` on the last one.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct TodoPage {
+    pub items: Vec<TodoEntry>,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+/// What went wrong, in a form clients can match on. Each code maps to one HTTP status.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request itself is malformed or fails validation (400)
+    Validation,
+    /// No valid bearer token came with a request that needs one, or the login was wrong (401)
+    Unauthorized,
+    /// The token is valid, but lacks the scope the request needs (403)
+    Forbidden,
+    /// The todo (or route) doesn't exist (404)
+    NotFound,
+    /// The request clashes with the current state of the data (409)
+    Conflict,
+    /// The item changed since the revision given in `If-Match` (412)
+    PreconditionFailed,
+    /// Reading or writing the storage failed (500)
+    Storage,
+}
+
+impl ErrorCode {
+    pub fn status_code(self) -> u16 {
+        match self {
+            ErrorCode::Validation => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::Conflict => 409,
+            ErrorCode::PreconditionFailed => 412,
+            ErrorCode::Storage => 500,
+        }
+    }
+}
+
+/// Body of every error response from the backend, e.g.
+/// `{"code": "not_found", "message": "Todo item with id 3 not found", "details": null}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Validation, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Unauthorized, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Forbidden, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Conflict, message)
+    }
+
+    pub fn precondition_failed(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::PreconditionFailed, message)
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        ApiError::new(ErrorCode::Storage, message)
+    }
+
+    pub fn with_details(mut self, details: impl Into<serde_json::Value>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    /// Decodes an error response. Anything that isn't one of ours (a proxy's error page, say)
+    /// still becomes an `ApiError`, with the code picked from the status.
+    pub fn from_response(status: u16, body: &str) -> Self {
+        serde_json::from_str(body).unwrap_or_else(|_| {
+            let code = match status {
+                401 => ErrorCode::Unauthorized,
+                403 => ErrorCode::Forbidden,
+                404 => ErrorCode::NotFound,
+                409 => ErrorCode::Conflict,
+                412 => ErrorCode::PreconditionFailed,
+                400..=499 => ErrorCode::Validation,
+                _ => ErrorCode::Storage,
+            };
+            let message = if body.trim().is_empty() {
+                format!("request failed with status {}", status)
+            } else {
+                body.trim().to_string()
+            };
+            ApiError::new(code, message)
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match &self.details {
+            None | Some(serde_json::Value::Null) => Ok(()),
+            Some(serde_json::Value::String(details)) => write!(f, " ({})", details),
+            Some(details) => write!(f, " ({})", details),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -24,4 +1063,54 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn patch_can_clear_the_parent() {
+        let mut item = TodoItem { parent_id: Some(3), ..Default::default() };
+
+        serde_json::from_str::<TodoPatch>(r#"{"title": "x"}"#).unwrap().apply(&mut item);
+        assert_eq!(item.parent_id, Some(3));
+        serde_json::from_str::<TodoPatch>(r#"{"parent_id": null}"#).unwrap().apply(&mut item);
+        assert_eq!(item.parent_id, None);
+        serde_json::from_str::<TodoPatch>(r#"{"parent_id": 5}"#).unwrap().apply(&mut item);
+        assert_eq!(item.parent_id, Some(5));
+    }
+
+    #[test]
+    fn update_response_reads_as_an_entry() {
+        let response = UpdateResponse {
+            entry: TodoEntry { id: 2, item: TodoItem { title: "x".to_string(), ..Default::default() } },
+            unblocked: vec![4],
+            next_occurrence: None,
+        };
+        let json = serde_json::to_string(&response).unwrap();
+
+        let entry: TodoEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!((entry.id, entry.item.title.as_str()), (2, "x"));
+        assert_eq!(serde_json::from_str::<UpdateResponse>(&json).unwrap().unblocked, [4]);
+    }
+
+    #[test]
+    fn query_filters() {
+        let item = TodoItem {
+            title: "Buy groceries".to_string(),
+            description: "Milk, eggs, bread".to_string(),
+            due_date: "2025-11-15T18:00:00Z".parse().unwrap(),
+            ..Default::default()
+        };
+
+        assert!(TodoQuery::new().matches(&item));
+        assert!(TodoQuery::new().search("MILK").completed(false).matches(&item));
+        assert!(!TodoQuery::new().search("butter").matches(&item));
+        assert!(TodoQuery::new().due_before("2025-11-16T00:00:00Z".parse().unwrap()).matches(&item));
+        assert!(!TodoQuery::new().due_after(item.due_date).matches(&item));
+
+        let tagged = TodoItem { tags: vec!["errands".to_string()], ..item };
+        assert!(TodoQuery::new().tag(" Errands").matches(&tagged));
+        assert!(!TodoQuery::new().tag("work").matches(&tagged));
+        assert!(TodoQuery::new().list(INBOX_ID).matches(&tagged));
+        assert!(!TodoQuery::new().list(3).matches(&tagged));
+        assert!(!TodoQuery::new().assigned_to(2).matches(&tagged));
+        assert!(TodoQuery::new().assigned_to(2).matches(&TodoItem { assigned_to: Some(2), ..tagged }));
+    }
 }
@@ -0,0 +1,287 @@
+use std::fmt;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// How a todo repeats, e.g. `{"freq": "weekly", "weekdays": ["Mon", "Thu"], "count": 5}`.
+/// It can also be written as an iCalendar style rule, `FREQ=WEEKLY;BYDAY=MO,TH;COUNT=5`.
+///
+/// Everything happens in UTC, so an occurrence is due at the same time of day as the one
+/// before it, daylight saving time never shifts it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    #[serde(flatten)]
+    pub rule: RecurrenceRule,
+    /// No occurrence is due after this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// How many occurrences are left, the current one included. Without it the todo repeats
+    /// forever (or until `until`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "freq", rename_all = "snake_case")]
+pub enum RecurrenceRule {
+    Daily,
+    EveryNDays { days: u32 },
+    /// On each of the weekdays
+    Weekly { weekdays: Vec<Weekday> },
+    /// On this day of every month, or on the last day of months that are shorter
+    Monthly { day: u32 },
+}
+
+impl Recurrence {
+    pub fn new(rule: RecurrenceRule) -> Self {
+        Recurrence { rule, until: None, count: None }
+    }
+
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn count(mut self, count: u32) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Refuses rules that could never produce a due date.
+    pub fn check(&self) -> Result<(), String> {
+        match &self.rule {
+            RecurrenceRule::EveryNDays { days: 0 } => Err("every_n_days needs at least 1 day".to_string()),
+            RecurrenceRule::Weekly { weekdays } if weekdays.is_empty() => Err("weekly needs at least one weekday".to_string()),
+            RecurrenceRule::Monthly { day } if !(1..=31).contains(day) => Err("monthly needs a day between 1 and 31".to_string()),
+            _ if self.count == Some(0) => Err("count must be at least 1".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// When the occurrence after the one due at `due` is due, and the recurrence it carries on
+    /// with. `None` once `count` or `until` ends the series.
+    pub fn next(&self, due: DateTime<Utc>) -> Option<(DateTime<Utc>, Recurrence)> {
+        let count = match self.count {
+            Some(count) if count <= 1 => return None,
+            count => count.map(|count| count - 1),
+        };
+        let next = self.rule.next_after(due)?;
+        if self.until.is_some_and(|until| next > until) {
+            return None;
+        }
+        Some((next, Recurrence { count, ..self.clone() }))
+    }
+}
+
+impl RecurrenceRule {
+    /// The first date after `due` that fits the rule, at the same time of day.
+    pub fn next_after(&self, due: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            RecurrenceRule::Daily => due.checked_add_signed(Duration::days(1)),
+            RecurrenceRule::EveryNDays { days } => due.checked_add_signed(Duration::days(i64::from(*days))),
+            RecurrenceRule::Weekly { weekdays } => (1..=7)
+                .filter_map(|days| due.checked_add_signed(Duration::days(days)))
+                .find(|date| weekdays.contains(&date.weekday())),
+            RecurrenceRule::Monthly { day } => {
+                // Later this month if the day is still ahead, otherwise next month
+                let this_month = on_day(due.year(), due.month(), *day)?.and_time(due.time()).and_utc();
+                if this_month > due {
+                    return Some(this_month);
+                }
+                let (year, month) = if due.month() == 12 { (due.year() + 1, 1) } else { (due.year(), due.month() + 1) };
+                Some(on_day(year, month, *day)?.and_time(due.time()).and_utc())
+            }
+        }
+    }
+}
+
+// `day` of the month, moved back to the month's last day if it doesn't have that many
+fn on_day(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let last_day = NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()?.day();
+    NaiveDate::from_ymd_opt(year, month, day.min(last_day))
+}
+
+const WEEKDAY_CODES: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "MO"),
+    (Weekday::Tue, "TU"),
+    (Weekday::Wed, "WE"),
+    (Weekday::Thu, "TH"),
+    (Weekday::Fri, "FR"),
+    (Weekday::Sat, "SA"),
+    (Weekday::Sun, "SU"),
+];
+
+const UNTIL_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rule {
+            RecurrenceRule::Daily => write!(f, "FREQ=DAILY")?,
+            RecurrenceRule::EveryNDays { days } => write!(f, "FREQ=DAILY;INTERVAL={}", days)?,
+            RecurrenceRule::Weekly { weekdays } => {
+                let codes: Vec<&str> = WEEKDAY_CODES
+                    .iter()
+                    .filter(|(weekday, _)| weekdays.contains(weekday))
+                    .map(|(_, code)| *code)
+                    .collect();
+                write!(f, "FREQ=WEEKLY;BYDAY={}", codes.join(","))?
+            }
+            RecurrenceRule::Monthly { day } => write!(f, "FREQ=MONTHLY;BYMONTHDAY={}", day)?,
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format(UNTIL_FORMAT))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the subset of iCalendar RRULEs the rules above can express: `FREQ` is `DAILY` (with an
+/// optional `INTERVAL` in days), `WEEKLY` with `BYDAY` or `MONTHLY` with `BYMONTHDAY`, and either
+/// can end with `UNTIL` (`20251231T235959Z`, or `20251231` for the end of that day) or `COUNT`.
+impl std::str::FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut freq, mut interval, mut weekdays, mut month_day) = (None, None, None, None);
+        let (mut until, mut count) = (None, None);
+        for part in s.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got '{}'", part))?;
+            let value = value.to_ascii_uppercase();
+            let number = |value: &str| value.parse::<u32>().map_err(|_| format!("{} must be a number, got '{}'", key, value));
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => freq = Some(value),
+                "INTERVAL" => interval = Some(number(&value)?),
+                "BYMONTHDAY" => month_day = Some(number(&value)?),
+                "COUNT" => count = Some(number(&value)?),
+                "BYDAY" => {
+                    let days = value
+                        .split(',')
+                        .map(|code| {
+                            WEEKDAY_CODES
+                                .iter()
+                                .find(|(_, known)| *known == code)
+                                .map(|(weekday, _)| *weekday)
+                                .ok_or_else(|| format!("unknown weekday '{}', expected MO, TU, WE, TH, FR, SA or SU", code))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    weekdays = Some(days);
+                }
+                "UNTIL" => {
+                    let end_of_day = || Some(NaiveDate::parse_from_str(&value, "%Y%m%d").ok()?.and_time(NaiveTime::from_hms_opt(23, 59, 59)?));
+                    let parsed = NaiveDateTime::parse_from_str(&value, UNTIL_FORMAT).ok().or_else(end_of_day);
+                    until = Some(parsed.ok_or_else(|| format!("UNTIL must look like 20251231T235959Z, got '{}'", value))?.and_utc());
+                }
+                other => return Err(format!("unsupported rule part '{}'", other)),
+            }
+        }
+
+        let rule = match (freq.as_deref(), interval) {
+            (Some("DAILY"), None | Some(1)) => RecurrenceRule::Daily,
+            (Some("DAILY"), Some(days)) => RecurrenceRule::EveryNDays { days },
+            (Some("WEEKLY"), None | Some(1)) => RecurrenceRule::Weekly {
+                weekdays: weekdays.ok_or("FREQ=WEEKLY needs BYDAY")?,
+            },
+            (Some("MONTHLY"), None | Some(1)) => RecurrenceRule::Monthly {
+                day: month_day.ok_or("FREQ=MONTHLY needs BYMONTHDAY")?,
+            },
+            (Some("WEEKLY" | "MONTHLY"), Some(_)) => return Err("INTERVAL is only supported with FREQ=DAILY".to_string()),
+            (Some(other), _) => return Err(format!("unsupported FREQ '{}', expected DAILY, WEEKLY or MONTHLY", other)),
+            (None, _) => return Err("missing FREQ".to_string()),
+        };
+        let recurrence = Recurrence { rule, until, count };
+        recurrence.check()?;
+        Ok(recurrence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    fn next_dates(recurrence: Recurrence, mut due: DateTime<Utc>, n: usize) -> Vec<DateTime<Utc>> {
+        let mut recurrence = Some(recurrence);
+        let mut dates = Vec::new();
+        while let Some((next, rest)) = recurrence.and_then(|recurrence| recurrence.next(due)) {
+            if dates.len() == n {
+                break;
+            }
+            dates.push(next);
+            (due, recurrence) = (next, Some(rest));
+        }
+        dates
+    }
+
+    #[test]
+    fn monthly_sticks_to_the_day_past_short_months() {
+        let end_of_month = Recurrence::new(RecurrenceRule::Monthly { day: 31 });
+        assert_eq!(
+            next_dates(end_of_month, at("2025-01-31T08:00:00Z"), 4),
+            [at("2025-02-28T08:00:00Z"), at("2025-03-31T08:00:00Z"), at("2025-04-30T08:00:00Z"), at("2025-05-31T08:00:00Z")]
+        );
+
+        // Leap years, and the turn of the year
+        let on_the_30th = Recurrence::new(RecurrenceRule::Monthly { day: 30 });
+        assert_eq!(on_the_30th.rule.next_after(at("2024-01-30T00:00:00Z")), Some(at("2024-02-29T00:00:00Z")));
+        assert_eq!(on_the_30th.rule.next_after(at("2024-12-30T23:59:59Z")), Some(at("2025-01-30T23:59:59Z")));
+
+        // Still ahead this month
+        let on_the_15th = RecurrenceRule::Monthly { day: 15 };
+        assert_eq!(on_the_15th.next_after(at("2025-06-05T12:00:00Z")), Some(at("2025-06-15T12:00:00Z")));
+    }
+
+    #[test]
+    fn days_and_weekdays() {
+        // Europe moves its clocks on March 30th 2025, which makes no difference in UTC
+        assert_eq!(RecurrenceRule::Daily.next_after(at("2025-03-29T09:30:00Z")), Some(at("2025-03-30T09:30:00Z")));
+        assert_eq!(RecurrenceRule::Daily.next_after(at("2024-12-31T22:00:00Z")), Some(at("2025-01-01T22:00:00Z")));
+        assert_eq!(RecurrenceRule::EveryNDays { days: 10 }.next_after(at("2024-02-25T00:00:00Z")), Some(at("2024-03-06T00:00:00Z")));
+
+        // 2025-11-14 is a Friday
+        let mondays_and_thursdays = Recurrence::new(RecurrenceRule::Weekly { weekdays: vec![Weekday::Thu, Weekday::Mon] });
+        assert_eq!(
+            next_dates(mondays_and_thursdays, at("2025-11-14T18:00:00Z"), 3),
+            [at("2025-11-17T18:00:00Z"), at("2025-11-20T18:00:00Z"), at("2025-11-24T18:00:00Z")]
+        );
+        let fridays = RecurrenceRule::Weekly { weekdays: vec![Weekday::Fri] };
+        assert_eq!(fridays.next_after(at("2025-11-14T18:00:00Z")), Some(at("2025-11-21T18:00:00Z")));
+    }
+
+    #[test]
+    fn count_and_until_end_the_series() {
+        let three_times = Recurrence::new(RecurrenceRule::Daily).count(3);
+        assert_eq!(next_dates(three_times, at("2025-01-01T00:00:00Z"), 10).len(), 2);
+
+        let until = Recurrence::new(RecurrenceRule::EveryNDays { days: 7 }).until(at("2025-01-15T00:00:00Z"));
+        assert_eq!(
+            next_dates(until, at("2025-01-01T00:00:00Z"), 10),
+            [at("2025-01-08T00:00:00Z"), at("2025-01-15T00:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn rrule_text() {
+        let weekly: Recurrence = "FREQ=WEEKLY;BYDAY=TH,MO;UNTIL=20251231".parse().unwrap();
+        assert_eq!(weekly.rule, RecurrenceRule::Weekly { weekdays: vec![Weekday::Thu, Weekday::Mon] });
+        assert_eq!(weekly.until, Some(at("2025-12-31T23:59:59Z")));
+        assert_eq!(weekly.to_string(), "FREQ=WEEKLY;BYDAY=MO,TH;UNTIL=20251231T235959Z");
+
+        for text in ["FREQ=DAILY", "FREQ=DAILY;INTERVAL=3;COUNT=4", "FREQ=MONTHLY;BYMONTHDAY=31"] {
+            assert_eq!(text.parse::<Recurrence>().unwrap().to_string(), text);
+        }
+        for text in ["", "FREQ=YEARLY", "FREQ=WEEKLY", "FREQ=MONTHLY;BYMONTHDAY=32", "FREQ=DAILY;INTERVAL=0", "FREQ=DAILY;COUNT=0"] {
+            assert!(text.parse::<Recurrence>().is_err(), "{}", text);
+        }
+
+        let json = serde_json::to_string(&weekly).unwrap();
+        assert_eq!(json, r#"{"freq":"weekly","weekdays":["Thu","Mon"],"until":"2025-12-31T23:59:59Z"}"#);
+        assert_eq!(serde_json::from_str::<Recurrence>(&json).unwrap(), weekly);
+    }
+}
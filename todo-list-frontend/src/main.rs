@@ -1,146 +1,715 @@
-use std::collections::BTreeMap;
+mod todo_client;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
 use eframe::{App, egui};
 use egui::Ui;
 use egui_extras::{Column, TableBuilder};
-use todo_list_common::TodoItem;
+use todo_list_common::{
+    ChecklistItem, ChildrenOnDelete, Credentials, HistoryEntry, INBOX_ID, Priority, SortField, SortOrder, TodoEntry, TodoEvent, TodoItem, TodoListEntry, TodoQuery,
+    UserInfo,
+};
+
+// How many tasks are fetched at a time, "Load more" gets the next batch
+const PAGE_SIZE: u32 = 50;
+
+// How long to wait before following the backend's events again, after the stream broke off
+const RECONNECT_DELAY: Duration = Duration::from_secs(3);
+
+// How long the "Undo" toast stays up after a save or delete
+const UNDO_TOAST_DURATION: Duration = Duration::from_secs(8);
 
 #[derive(Debug, Clone)]
 struct ShowWindowData {
     show_window: bool,
     working_data: Option<TodoItem>,
-    working_data_id: Option<u32>
+    working_data_id: Option<u32>,
+    // Set when the backend refused a save or delete because the task changed in the meantime
+    conflict: Option<Conflict>,
+    tab: TaskTab,
+    // Loaded every time the History tab is opened
+    history: Option<Vec<HistoryEntry>>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskTab {
+    Details,
+    History
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PendingAction {
+    Save,
+    // With what should happen to the subtasks, if there are any
+    Delete(Option<ChildrenOnDelete>)
 }
 
+/// Offers to take back the save or delete it announces, until it times out.
 #[derive(Debug, Clone)]
+struct UndoToast {
+    message: String,
+    // What the backend recorded the change as, so undo takes back this one and no other
+    operation: u64,
+    shown_at: Instant
+}
+
+impl UndoToast {
+    fn new(message: String, operation: Option<u64>) -> Option<Self> {
+        Some(UndoToast { message, operation: operation?, shown_at: Instant::now() })
+    }
+}
+
+/// The lists with their counts, and the usernames of everyone on them, or why they couldn't be loaded.
+type LoadedLists = Result<(Vec<TodoListEntry>, BTreeMap<u32, String>), String>;
+
+/// What's typed into the login form, until it's sent.
+#[derive(Debug, Clone, Default)]
+struct LoginForm {
+    username: String,
+    password: String
+}
+
+/// The backend's current version of the task, and what the user tried to do with theirs.
+#[derive(Debug, Clone)]
+struct Conflict {
+    theirs: TodoItem,
+    action: PendingAction
+}
+
+#[derive(Debug)]
 struct TodoApp {
-    todo_entries: BTreeMap<u32, TodoItem>,
+    // In the order the backend sorted them
+    todo_entries: Vec<TodoEntry>,
     first_run: bool,
-    show_window_data: ShowWindowData
+    // Who the requests are sent as, nobody when the backend doesn't require logging in
+    user: Option<UserInfo>,
+    login_form: LoginForm,
+    // Filters and sorting picked in the filter bar, and the list picked in the sidebar
+    query: TodoQuery,
+    lists: Vec<TodoListEntry>,
+    // Usernames by user id, of everyone on the lists, for the assignee column
+    members: BTreeMap<u32, String>,
+    // The lists are loaded on a thread of their own, one load at a time, see `load_lists`
+    lists_loading: Option<Receiver<LoadedLists>>,
+    // Set when the counts may be out of date, the next load starts once the one running is done
+    lists_stale: bool,
+    // What's typed into the sidebar's "Add list" box
+    new_list_name: String,
+    // Where the next page starts, when the backend has more tasks than it sent
+    next_cursor: Option<String>,
+    show_window_data: ShowWindowData,
+    // Shows subtasks indented under their parent instead of as a flat list
+    tree_view: bool,
+    // Tasks whose subtasks are folded away in the tree view
+    collapsed: BTreeSet<u32>,
+    // Last error from the backend, shown until the next successful request
+    error_message: Option<String>,
+    // Changes the backend streams to us, see `follow_events`
+    events: Receiver<TodoEvent>,
+    undo_toast: Option<UndoToast>
 }
 
 impl App for TodoApp {
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        for event in self.events.try_iter() {
+            // Any change to a task may change the counts in the sidebar
+            self.lists_stale = true;
+            if !apply_event(&mut self.todo_entries, &self.query, event) {
+                refresh_entities(&mut self.todo_entries, &mut self.next_cursor, &self.query, &mut self.first_run, &mut self.error_message);
+            }
+        }
+
+        let session_changed = egui::TopBottomPanel::top("session")
+            .show(ctx, |ui| render_session(ui, &mut self.user, &mut self.login_form, &mut self.error_message))
+            .inner;
+        if session_changed {
+            // Everything shown belonged to the previous user, lists still on their way included
+            self.members.clear();
+            self.lists_loading = None;
+            self.query.list = None;
+            self.show_window_data.show_window = false;
+            self.undo_toast = None;
+            self.first_run = true;
+        }
+
+        let list_changed = egui::SidePanel::left("lists")
+            .show(ctx, |ui| render_lists(ui, &self.lists, &mut self.query.list, &mut self.new_list_name, &mut self.error_message))
+            .inner;
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Tasks Todo:");
             ui.separator();
 
-            if ui.button("⟳ Refresh Table").clicked() || self.first_run == true {
-                refresh_entities(&mut self.todo_entries, &mut self.first_run);
+            let filters_changed = render_filters(ui, &mut self.query);
+            let refresh_clicked = ui.horizontal(|ui| {
+                let clicked = ui.button("⟳ Refresh Table").clicked();
+                ui.checkbox(&mut self.tree_view, "Tree view");
+                clicked
+            }).inner;
+            if refresh_clicked || self.first_run || filters_changed || list_changed {
+                // The counts in the sidebar change along with the tasks
+                self.lists_stale = true;
+                refresh_entities(&mut self.todo_entries, &mut self.next_cursor, &self.query, &mut self.first_run, &mut self.error_message);
+            }
+
+            if let Some(error_message) = &self.error_message {
+                ui.colored_label(egui::Color32::RED, error_message);
             }
 
             // Render table
-            render_table(ui, &mut self.todo_entries, &mut self.show_window_data);
+            let collapsed = self.tree_view.then_some(&mut self.collapsed);
+            if render_table(ui, &self.todo_entries, &self.members, &mut self.show_window_data, &mut self.query.tag, collapsed) {
+                refresh_entities(&mut self.todo_entries, &mut self.next_cursor, &self.query, &mut self.first_run, &mut self.error_message);
+            }
+
+            if self.next_cursor.is_some() && ui.button("Load more").clicked() {
+                load_next_page(&mut self.todo_entries, &mut self.next_cursor, &self.query, &mut self.error_message);
+            }
 
             // Render window for task if user is editing the data
-            if let Some(working_id) = self.show_window_data.working_data_id {
-                if let Some(working_data) = self.show_window_data.working_data.as_mut() {
-                    egui::Window::new(format!("Task {}", working_id))
-                        .resizable(true)
-                        .show(ctx, |ui| {
-                            render_task_window(ui, &working_id, working_data, &mut self.show_window_data.show_window);
+            if self.show_window_data.show_window
+                && let Some(working_data) = self.show_window_data.working_data.as_mut()
+            {
+                // A task without an id hasn't been inserted yet, the backend hands us the id on save
+                let window_title = match self.show_window_data.working_data_id {
+                    Some(working_id) => format!("Task {}", working_id),
+                    None => "New Task".to_string(),
+                };
+                egui::Window::new(window_title)
+                    .resizable(true)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut self.show_window_data.tab, TaskTab::Details, "Details");
+                            // A task that hasn't been saved yet has no history
+                            if self.show_window_data.working_data_id.is_some()
+                                && ui.selectable_value(&mut self.show_window_data.tab, TaskTab::History, "History").clicked()
+                            {
+                                self.show_window_data.history = None;
+                            }
                         });
-                }
+                        ui.separator();
+
+                        match (self.show_window_data.tab, self.show_window_data.working_data_id) {
+                            (TaskTab::History, Some(id)) => {
+                                render_history(ui, id, &mut self.show_window_data.history, &mut self.error_message);
+                                if ui.button("Close window").clicked() {
+                                    self.show_window_data.show_window = false;
+                                }
+                            }
+                            _ => {
+                                render_list_picker(ui, &self.lists, &mut working_data.list_id);
+                                let done = render_task_window(ui, &mut self.show_window_data.working_data_id, working_data, &mut self.show_window_data.conflict, &mut self.show_window_data.show_window, &self.todo_entries, &mut self.error_message);
+                                if let Some(toast) = done {
+                                    self.undo_toast = Some(toast);
+                                }
+                            }
+                        }
+                    });
             }
 
-            // This would be more fleshed out and likely using insert instead of update if I had more time
-            // mainly doing this not because the backend is incapable, but front-end is consuming a lot of time
-            // with various bugs and constraints on data storage on the client side
+            render_undo_toast(ctx, &mut self.undo_toast, &mut self.show_window_data, &mut self.error_message);
+
             if ui.button("Add new task...").clicked() {
                 self.show_window_data.working_data = Some(TodoItem {
                     title: "Title".to_string(),
                     description: "Description".to_string(),
                     due_date: Utc::now(),
-                    created_at: Utc::now(),
                     completed: false,
+                    // Added to the list that's being looked at
+                    list_id: self.query.list.unwrap_or(INBOX_ID),
+                    ..Default::default()
                 });
 
-                self.show_window_data.working_data_id = Some(self.todo_entries.len() as u32); // YES this can cause issues being this simple! (IE update instead of create!)
+                self.show_window_data.working_data_id = None;
+                self.show_window_data.conflict = None;
+                self.show_window_data.tab = TaskTab::Details;
                 self.show_window_data.show_window = true;
 
             }
 
         
         });
+
+        refresh_lists(ctx, &mut self.lists, &mut self.members, &mut self.lists_loading, &mut self.lists_stale, &mut self.error_message);
+    }
+}
+
+// Returns whether anything was changed, so the table can be reloaded right away
+fn render_filters(ui: &mut Ui, query: &mut TodoQuery) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Search:");
+        let mut search = query.q.clone().unwrap_or_default();
+        if ui.text_edit_singleline(&mut search).changed() {
+            query.q = (!search.is_empty()).then_some(search);
+            changed = true;
+        }
+
+        ui.label("Show:");
+        let completed_label = |completed: Option<bool>| match completed {
+            None => "All",
+            Some(false) => "Open",
+            Some(true) => "Done",
+        };
+        egui::ComboBox::from_id_salt("completed_filter")
+            .selected_text(completed_label(query.completed))
+            .show_ui(ui, |ui| {
+                for completed in [None, Some(false), Some(true)] {
+                    changed |= ui.selectable_value(&mut query.completed, completed, completed_label(completed)).changed();
+                }
+            });
+
+        ui.label("Sort by:");
+        let sort_label = |field: SortField| match field {
+            SortField::Id => "ID",
+            SortField::DueDate => "Due Date",
+            SortField::CreatedAt => "Created On",
+            SortField::Title => "Title",
+            SortField::Smart => "Smart (what's next)",
+        };
+        let mut sort = query.sort.unwrap_or_default();
+        egui::ComboBox::from_id_salt("sort_field")
+            .selected_text(sort_label(sort))
+            .show_ui(ui, |ui| {
+                for field in [SortField::Smart, SortField::Id, SortField::DueDate, SortField::CreatedAt, SortField::Title] {
+                    changed |= ui.selectable_value(&mut sort, field, sort_label(field)).changed();
+                }
+            });
+
+        let mut order = query.order.unwrap_or_default();
+        let order_label = match order {
+            SortOrder::Asc => "⬆ Ascending",
+            SortOrder::Desc => "⬇ Descending",
+        };
+        if ui.button(order_label).clicked() {
+            order = match order {
+                SortOrder::Asc => SortOrder::Desc,
+                SortOrder::Desc => SortOrder::Asc,
+            };
+            changed = true;
+        }
+
+        // Set by clicking one of the tags in the table
+        if let Some(tag) = query.tag.clone() {
+            ui.label(format!("Tag: {}", tag));
+            if ui.small_button("✖").on_hover_text("Show all tags").clicked() {
+                query.tag = None;
+                changed = true;
+            }
+        }
+
+        if changed {
+            *query = query.clone().sort(sort, order);
+        }
+    });
+
+    changed
+}
+
+// Returns whether the user logged in or out
+fn render_session(ui: &mut Ui, user: &mut Option<UserInfo>, login_form: &mut LoginForm, error_message: &mut Option<String>) -> bool {
+    ui.horizontal(|ui| {
+        if let Some(logged_in) = user.as_ref() {
+            ui.label(format!("Logged in as {}", logged_in.username));
+            if ui.button("Log out").clicked() {
+                todo_client::logout();
+                *user = None;
+                return true;
+            }
+            return false;
+        }
+
+        ui.label("Username:");
+        ui.text_edit_singleline(&mut login_form.username);
+        ui.label("Password:");
+        ui.add(egui::TextEdit::singleline(&mut login_form.password).password(true));
+        if !ui.button("Log in").clicked() {
+            return false;
+        }
+        let credentials = Credentials { username: login_form.username.clone(), password: std::mem::take(&mut login_form.password) };
+        match todo_client::login(&credentials) {
+            Ok(logged_in) => {
+                *user = Some(logged_in);
+                *error_message = None;
+                true
+            }
+            Err(err) => {
+                *error_message = Some(format!("Failed to log in: {}", err));
+                false
+            }
+        }
+    }).inner
+}
+
+// Whoever the token the app started with belongs to. One that expired is as good as none.
+fn session_user() -> Option<UserInfo> {
+    match todo_client::whoami() {
+        Ok(user) => user,
+        Err(err) => {
+            eprintln!("Not logged in: {}", err);
+            None
+        }
+    }
+}
+
+// Returns whether another list was picked, or one was added or deleted
+fn render_lists(
+    ui: &mut Ui,
+    lists: &[TodoListEntry],
+    selected: &mut Option<u32>,
+    new_list_name: &mut String,
+    error_message: &mut Option<String>
+) -> bool {
+    let mut changed = false;
+    ui.heading("Lists");
+    changed |= ui.selectable_value(selected, None, "All tasks").changed();
+
+    for list in lists {
+        ui.horizontal(|ui| {
+            changed |= ui.selectable_value(selected, Some(list.id), format!("{} ({})", list.name, list.count)).changed();
+            if list.id != INBOX_ID
+                && ui.small_button("✖").on_hover_text("Delete the list, its tasks move to the Inbox").clicked()
+            {
+                match todo_client::delete_list(list.id, INBOX_ID) {
+                    Ok(()) => {
+                        if *selected == Some(list.id) {
+                            *selected = None;
+                        }
+                        changed = true;
+                    }
+                    Err(err) => *error_message = Some(format!("Failed to delete list {}: {}", list.name, err)),
+                }
+            }
+        });
     }
+
+    ui.separator();
+    ui.text_edit_singleline(new_list_name);
+    if ui.button("Add list").clicked() {
+        match todo_client::create_list(new_list_name) {
+            Ok(list) => {
+                *selected = Some(list.id);
+                new_list_name.clear();
+                changed = true;
+            }
+            Err(err) => *error_message = Some(format!("Failed to add list: {}", err)),
+        }
+    }
+
+    changed
+}
+
+fn render_list_picker(ui: &mut Ui, lists: &[TodoListEntry], list_id: &mut u32) {
+    let name_of = |id: u32| lists.iter().find(|list| list.id == id).map_or_else(|| format!("List {}", id), |list| list.name.clone());
+    ui.horizontal(|ui| {
+        ui.label("List:");
+        egui::ComboBox::from_id_salt("list")
+            .selected_text(name_of(*list_id))
+            .show_ui(ui, |ui| {
+                for list in lists {
+                    ui.selectable_value(list_id, list.id, &list.name);
+                }
+            });
+    });
+    ui.separator();
+}
+
+// Takes in the lists once they're loaded, and starts loading them again if they went stale
+// in the meantime. Loading takes a request per list, which the UI shouldn't wait on.
+fn refresh_lists(
+    ctx: &egui::Context,
+    lists: &mut Vec<TodoListEntry>,
+    members: &mut BTreeMap<u32, String>,
+    lists_loading: &mut Option<Receiver<LoadedLists>>,
+    lists_stale: &mut bool,
+    error_message: &mut Option<String>
+) {
+    if let Some(receiver) = lists_loading {
+        match receiver.try_recv() {
+            Ok(Ok((loaded, loaded_members))) => {
+                *lists = loaded;
+                *members = loaded_members;
+                *lists_loading = None;
+            }
+            Ok(Err(err)) => {
+                *error_message = Some(err);
+                *lists_loading = None;
+            }
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => *lists_loading = None,
+        }
+    }
+    if *lists_stale {
+        *lists_stale = false;
+        *lists_loading = Some(load_lists(ctx.clone()));
+    }
+}
+
+fn load_lists(ctx: egui::Context) -> Receiver<LoadedLists> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let loaded = todo_client::list_lists().map(|loaded| {
+            let mut members = BTreeMap::new();
+            // The Inbox is only ever the user's own, the other lists may be shared
+            for list in loaded.iter().filter(|list| list.id != INBOX_ID) {
+                match todo_client::list_members(list.id) {
+                    Ok(list_members) => members.extend(list_members.into_iter().map(|member| (member.id, member.username))),
+                    Err(err) => eprintln!("Failed to load the members of list {}: {}", list.id, err),
+                }
+            }
+            (loaded, members)
+        });
+        let loaded = loaded.map_err(|err| {
+            eprintln!("Failed to load lists: {}", err);
+            format!("Failed to load lists: {}", err)
+        });
+        // The app may have been closed in the meantime
+        if sender.send(loaded).is_ok() {
+            ctx.request_repaint();
+        }
+    });
+    receiver
 }
 
 fn refresh_entities (
-    todo_entries: &mut BTreeMap<u32, TodoItem>,
-    first_run: &mut bool
+    todo_entries: &mut Vec<TodoEntry>,
+    next_cursor: &mut Option<String>,
+    query: &TodoQuery,
+    first_run: &mut bool,
+    error_message: &mut Option<String>
 ) {
+    // Only try once on startup, the user can hit refresh once the backend is up
+    *first_run = false;
 
     // Blocking request
-    match reqwest::blocking::get("http://127.0.0.1:8081/list") {
-        Ok(resp) => match resp.json::<BTreeMap<String, TodoItem>>() {
-            Ok(items_map) => {
-                todo_entries.clear();
-                for (id_str, item) in items_map {
-                    if let Ok(id) = id_str.parse::<u32>() {
-                        todo_entries.insert(id, item);
-                    } else {
-                        eprintln!("Invalid ID in response: {}", id_str);
-                    }
+    match todo_client::list_todos(&query.clone().limit(PAGE_SIZE)) {
+        Ok(page) => {
+            *todo_entries = page.items;
+            *next_cursor = page.next_cursor;
+            *error_message = None;
+        }
+        Err(err) => {
+            eprintln!("Failed to load tasks: {}", err);
+            *error_message = Some(format!("Failed to load tasks: {}", err));
+        }
+    }
+}
+
+// Keeps the table in step with a change made anywhere, by this window or another client.
+// New tasks that pass the filters go at the end, the next refresh puts them in order.
+// Returns false when the event can't be applied and everything has to be loaded again.
+fn apply_event(todo_entries: &mut Vec<TodoEntry>, query: &TodoQuery, event: TodoEvent) -> bool {
+    match event {
+        TodoEvent::Created { id, item } | TodoEvent::Updated { id, item } => {
+            let position = todo_entries.iter().position(|entry| entry.id == id);
+            match (position, query.matches(&item)) {
+                (Some(i), true) => todo_entries[i].item = item,
+                (Some(i), false) => {
+                    todo_entries.remove(i);
                 }
-                *first_run = false;
+                (None, true) => todo_entries.push(TodoEntry { id, item }),
+                (None, false) => {}
             }
-            Err(err) => {
-                eprintln!("Failed to parse JSON: {:?}", err);
+        }
+        TodoEvent::Deleted { id } => todo_entries.retain(|entry| entry.id != id),
+        TodoEvent::Resync => return false,
+    }
+    true
+}
+
+// Runs for as long as the app does, following the backend's events and waking the UI up for
+// every one. Should the backend go away, it tries again every few seconds.
+fn follow_events(ctx: egui::Context) -> Receiver<TodoEvent> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reconnecting = false;
+        loop {
+            let result = todo_client::subscribe().and_then(|events| {
+                // Whatever happened while we weren't listening has to be loaded again
+                let first = reconnecting.then_some(Ok(TodoEvent::Resync));
+                for event in first.into_iter().chain(events) {
+                    if sender.send(event?).is_err() {
+                        return Ok(());
+                    }
+                    ctx.request_repaint();
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                eprintln!("Not getting live updates: {}", err);
             }
-        },
+            reconnecting = true;
+            thread::sleep(RECONNECT_DELAY);
+        }
+    });
+    receiver
+}
+
+fn load_next_page(
+    todo_entries: &mut Vec<TodoEntry>,
+    next_cursor: &mut Option<String>,
+    query: &TodoQuery,
+    error_message: &mut Option<String>
+) {
+    let Some(cursor) = next_cursor.clone() else { return };
+
+    match todo_client::list_todos(&query.clone().limit(PAGE_SIZE).cursor(cursor)) {
+        Ok(page) => {
+            todo_entries.extend(page.items);
+            *next_cursor = page.next_cursor;
+            *error_message = None;
+        }
         Err(err) => {
-            eprintln!("HTTP request failed: {:?}", err);
+            eprintln!("Failed to load more tasks: {}", err);
+            *error_message = Some(format!("Failed to load more tasks: {}", err));
+        }
+    }
+}
+
+fn priority_color(priority: Priority) -> egui::Color32 {
+    match priority {
+        Priority::None => egui::Color32::GRAY,
+        Priority::Low => egui::Color32::LIGHT_BLUE,
+        Priority::Medium => egui::Color32::YELLOW,
+        Priority::High => egui::Color32::ORANGE,
+        Priority::Urgent => egui::Color32::RED,
+    }
+}
+
+/// The rows of the tree view as (index into `todo_entries`, depth, has subtasks), every task
+/// right below its parent. Tasks whose parent isn't loaded are shown at the top level, and
+/// the subtasks of collapsed tasks are left out.
+fn tree_rows(todo_entries: &[TodoEntry], collapsed: &BTreeSet<u32>) -> Vec<(usize, usize, bool)> {
+    let loaded: BTreeSet<u32> = todo_entries.iter().map(|entry| entry.id).collect();
+    let children_of = |parent_id: Option<u32>| -> Vec<usize> {
+        (0..todo_entries.len())
+            .filter(|&i| match todo_entries[i].item.parent_id {
+                Some(id) if loaded.contains(&id) => Some(id) == parent_id,
+                _ => parent_id.is_none(),
+            })
+            .collect()
+    };
+
+    let mut rows = Vec::new();
+    // Depth first, children are pushed in reverse so they come off the stack in order
+    let mut stack: Vec<(usize, usize)> = children_of(None).into_iter().rev().map(|i| (i, 0)).collect();
+    while let Some((i, depth)) = stack.pop() {
+        let id = todo_entries[i].id;
+        let children = children_of(Some(id));
+        rows.push((i, depth, !children.is_empty()));
+        if !collapsed.contains(&id) {
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
         }
     }
+    rows
 }
 
+// Returns whether a tag was clicked, which makes it the table's tag filter.
+// `collapsed` is only passed for the tree view.
 fn render_table(
     ui: &mut Ui,
-    todo_entries: &mut BTreeMap<u32, TodoItem>,
-    show_window: &mut ShowWindowData
-) {
-    let tasks_table = TableBuilder::new(ui)
+    todo_entries: &[TodoEntry],
+    members: &BTreeMap<u32, String>,
+    show_window: &mut ShowWindowData,
+    tag_filter: &mut Option<String>,
+    mut collapsed: Option<&mut BTreeSet<u32>>
+) -> bool {
+    let mut tag_clicked = false;
+    let rows = match &collapsed {
+        Some(collapsed) => tree_rows(todo_entries, collapsed),
+        None => (0..todo_entries.len()).map(|i| (i, 0, false)).collect(),
+    };
+
+    TableBuilder::new(ui)
         .striped(true)
         .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-        .columns(Column::auto(), 7)
+        .columns(Column::auto(), 11)
         .header(20.0, |mut header| {
             header.col(|col| { col.strong("Done"); });
             header.col(|col| { col.strong("ID"); });
+            header.col(|col| { col.strong("Priority"); });
             header.col(|col| { col.strong("Title"); });
             header.col(|col| { col.strong("Description"); });
             header.col(|col| { col.strong("Due Date"); });
             header.col(|col| { col.strong("Created On"); });
+            header.col(|col| { col.strong("Checklist"); });
+            header.col(|col| { col.strong("Tags"); });
+            header.col(|col| { col.strong("Assignee"); });
             header.col(|col| { col.strong("View Details"); });
         })
         .body(|mut body| {
-            for (id, item) in todo_entries {
+            for (i, depth, has_children) in rows {
+                let TodoEntry { id, item } = &todo_entries[i];
                 body.row(20.0, |mut row| {
                     row.col(|ui| { ui.label(if item.completed { "✅" } else { "❌" }); });
                     row.col(|ui| { ui.label(id.to_string()); });
-                    row.col(|ui| { ui.label(&item.title); });
+                    row.col(|ui| { ui.colored_label(priority_color(item.priority), item.priority.to_string()); });
+                    row.col(|ui| {
+                        if let Some(collapsed) = collapsed.as_deref_mut() {
+                            ui.add_space(depth as f32 * 16.0);
+                            if has_children {
+                                let folded = collapsed.contains(id);
+                                if ui.small_button(if folded { "▶" } else { "▼" }).clicked() && !collapsed.remove(id) {
+                                    collapsed.insert(*id);
+                                }
+                            }
+                        }
+                        ui.colored_label(priority_color(item.priority), &item.title);
+                    });
                     row.col(|ui| { ui.label(&item.description); });
                     row.col(|ui| { ui.label(item.due_date.to_rfc3339()); });
                     row.col(|ui| { ui.label(item.created_at.to_rfc3339()); });
+                    row.col(|ui| {
+                        if !item.checklist.is_empty() {
+                            let (done, total) = item.checklist_progress();
+                            ui.label(format!("{}/{}", done, total));
+                        }
+                    });
+                    row.col(|ui| {
+                        for tag in &item.tags {
+                            if ui.small_button(tag).on_hover_text("Only show tasks with this tag").clicked() {
+                                *tag_filter = Some(tag.clone());
+                                tag_clicked = true;
+                            }
+                        }
+                    });
+                    row.col(|ui| {
+                        // Someone who isn't on any of the lists anymore still has an id
+                        if let Some(user_id) = item.assigned_to {
+                            ui.label(members.get(&user_id).cloned().unwrap_or_else(|| format!("#{}", user_id)));
+                        }
+                    });
                     row.col(|ui| { 
 
                         if ui.button("View Details").clicked() {
-                            *show_window = ShowWindowData { show_window: true, working_data: Some(item.clone()), working_data_id: Some(id.clone()) }
+                            *show_window = ShowWindowData { show_window: true, working_data: Some(item.clone()), working_data_id: Some(*id), conflict: None, tab: TaskTab::Details, history: None }
                         }
                         
                     });
                 });
             }
         });
+
+    tag_clicked
 }
 
 fn render_task_window(
     ui: &mut Ui,
-    working_id: &u32,
+    working_id: &mut Option<u32>,
     working_data: &mut TodoItem,
-    show_window: &mut bool
-) {
+    conflict: &mut Option<Conflict>,
+    show_window: &mut bool,
+    todo_entries: &[TodoEntry],
+    error_message: &mut Option<String>
+) -> Option<UndoToast> {
+    // What went through, for the undo toast
+    let mut done = None;
     ui.vertical(|ui| {
         ui.label("Title:");
         ui.text_edit_singleline(&mut working_data.title);
@@ -154,75 +723,348 @@ fn render_task_window(
 
         ui.label("Due Date (ISO 8601):");
         let mut due_date_str = working_data.due_date.to_rfc3339();
-        if ui.text_edit_singleline(&mut due_date_str).changed() {
-            if let Ok(parsed) = due_date_str.parse::<chrono::DateTime<chrono::Utc>>() {
-                working_data.due_date = parsed;
-            }
+        if ui.text_edit_singleline(&mut due_date_str).changed()
+            && let Ok(parsed) = due_date_str.parse::<chrono::DateTime<chrono::Utc>>()
+        {
+            working_data.due_date = parsed;
         }
 
         ui.separator();
 
-        ui.label("Created At (ISO 8601):");
-        let mut created_at_str = working_data.created_at.to_rfc3339();
-        if ui.text_edit_singleline(&mut created_at_str).changed() {
-            if let Ok(parsed) = created_at_str.parse::<chrono::DateTime<chrono::Utc>>() {
-                working_data.created_at = parsed;
-            }
+        ui.label("Priority:");
+        egui::ComboBox::from_id_salt("priority")
+            .selected_text(egui::RichText::new(working_data.priority.to_string()).color(priority_color(working_data.priority)))
+            .show_ui(ui, |ui| {
+                for priority in Priority::ALL {
+                    ui.selectable_value(&mut working_data.priority, priority, priority.to_string());
+                }
+            });
+
+        ui.separator();
+
+        ui.label("Tags (comma separated):");
+        // Splitting and joining on "," gives back the exact text, so typing isn't disturbed.
+        // The backend trims the tags and drops empty ones on save.
+        let mut tags_str = working_data.tags.join(",");
+        if ui.text_edit_singleline(&mut tags_str).changed() {
+            working_data.tags = tags_str.split(',').map(String::from).collect();
         }
 
         ui.separator();
 
+        render_checklist_editor(ui, &mut working_data.checklist);
+
+        ui.separator();
+
+        // Only loaded tasks can be picked, and the backend refuses a parent that would make a loop
+        ui.label("Subtask of:");
+        let task_label = |parent_id: Option<u32>| match parent_id {
+            None => "(none)".to_string(),
+            Some(parent_id) => match todo_entries.iter().find(|entry| entry.id == parent_id) {
+                Some(entry) => format!("{}: {}", parent_id, entry.item.title),
+                None => format!("Task {}", parent_id),
+            },
+        };
+        egui::ComboBox::from_id_salt("parent")
+            .selected_text(task_label(working_data.parent_id))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut working_data.parent_id, None, task_label(None));
+                for entry in todo_entries.iter().filter(|entry| Some(entry.id) != *working_id) {
+                    ui.selectable_value(&mut working_data.parent_id, Some(entry.id), task_label(Some(entry.id)));
+                }
+            });
+
+        ui.separator();
+
         ui.checkbox(&mut working_data.completed, "Completed");
+
+        // Set with the CLI or the API, completing the task adds the next occurrence
+        if let Some(recurrence) = working_data.recurrence.as_ref().map(ToString::to_string) {
+            ui.horizontal(|ui| {
+                ui.label(format!("Repeats: {}", recurrence));
+                if ui.small_button("✖").on_hover_text("Stop repeating, once saved").clicked() {
+                    working_data.recurrence = None;
+                }
+            });
+        }
+
+        // Kept by the backend, so these are only shown once the task has been saved
+        if working_id.is_some() {
+            ui.separator();
+            ui.label(format!("Created At: {}", working_data.created_at.to_rfc3339()));
+            ui.label(format!("Updated At: {}", working_data.updated_at.to_rfc3339()));
+            if let Some(completed_at) = working_data.completed_at {
+                ui.label(format!("Completed At: {}", completed_at.to_rfc3339()));
+            }
+        }
     });
 
+    if let Some(Conflict { theirs, action }) = conflict.clone() {
+        done = render_conflict(ui, working_id, working_data, &theirs, action, conflict, error_message);
+    }
+
     // Buttons
     ui.horizontal(|ui| {
-        if ui.button("Save Changes").clicked() {
-            // Send PUT request to backend
-            let url = format!("http://127.0.0.1:8081/update/{}", working_id);
-            match reqwest::blocking::Client::new()
-                .put(&url)
-                .json(&working_data)
-                .send()
-            {
-                Ok(resp) => {
-                    if resp.status().is_success() {
-                        println!("Task {} updated successfully", working_id);
-                    } else {
-                        eprintln!("Failed to update task {}: {:?}", working_id, resp.status());
-                    }
+        // Until the conflict is resolved, saving would only be refused again
+        if ui.add_enabled(conflict.is_none(), egui::Button::new("Save Changes")).clicked() {
+            done = save_task(working_id, working_data, conflict, error_message);
+        }
+
+        if let Some(id) = *working_id {
+            // The backend won't delete a task with subtasks without being told what to do with them
+            let has_children = todo_entries.iter().any(|entry| entry.item.parent_id == Some(id));
+            let buttons: &[(&str, Option<ChildrenOnDelete>)] = if has_children {
+                &[
+                    ("Delete with subtasks", Some(ChildrenOnDelete::Cascade)),
+                    ("Delete, keep subtasks", Some(ChildrenOnDelete::Reparent)),
+                ]
+            } else {
+                &[("Delete", None)]
+            };
+            for (label, children) in buttons {
+                if ui.add_enabled(conflict.is_none(), egui::Button::new(*label)).clicked() {
+                    done = delete_task(id, *children, working_data, conflict, error_message);
                 }
-                Err(err) => {
-                    eprintln!("HTTP request failed: {:?}", err);
+            }
+        }
+
+        if ui.button("Close window").clicked() {
+            *show_window = false;
+        }
+    });
+    done
+}
+
+// Newest change first, with what every update changed underneath it
+fn render_history(ui: &mut Ui, id: u32, history: &mut Option<Vec<HistoryEntry>>, error_message: &mut Option<String>) {
+    let entries = history.get_or_insert_with(|| match todo_client::todo_history(id) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Failed to load the history of task {}: {}", id, err);
+            *error_message = Some(format!("Failed to load the history of task {}: {}", id, err));
+            Vec::new()
+        }
+    });
+
+    egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+        for entry in entries.iter().rev() {
+            let actor = match (&entry.actor, entry.actor_id) {
+                (Some(username), _) => username.clone(),
+                (None, Some(user_id)) => format!("user {}", user_id),
+                (None, None) => "anonymous".to_string(),
+            };
+            let undo = if entry.undo { " (undo)" } else { "" };
+            ui.strong(format!("Revision {}: {}{} by {}", entry.revision, entry.action, undo, actor));
+            ui.label(entry.at.to_rfc3339());
+            for change in &entry.changes {
+                ui.label(format!("{}: {} → {}", change.field, change.before, change.after));
+            }
+            ui.separator();
+        }
+    });
+
+    if ui.button("⟳ Reload").clicked() {
+        *history = None;
+    }
+}
+
+// Changes only reach the backend with the rest of the task, when it's saved
+fn render_checklist_editor(ui: &mut Ui, checklist: &mut Vec<ChecklistItem>) {
+    let (done, total) = (checklist.iter().filter(|step| step.done).count(), checklist.len());
+    ui.label(format!("Checklist ({}/{}):", done, total));
+
+    let mut swap = None;
+    let mut remove = None;
+    for (i, step) in checklist.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut step.done, "");
+            ui.text_edit_singleline(&mut step.text);
+            if ui.add_enabled(i > 0, egui::Button::new("⬆").small()).clicked() {
+                swap = Some((i - 1, i));
+            }
+            if ui.add_enabled(i + 1 < total, egui::Button::new("⬇").small()).clicked() {
+                swap = Some((i, i + 1));
+            }
+            if ui.small_button("✖").on_hover_text("Remove this step").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+
+    if let Some((a, b)) = swap {
+        checklist.swap(a, b);
+    }
+    if let Some(i) = remove {
+        checklist.remove(i);
+    }
+    // The backend gives the new step its id on save
+    if ui.button("Add step").clicked() {
+        checklist.push(ChecklistItem { text: "New step".to_string(), ..Default::default() });
+    }
+}
+
+fn render_conflict(
+    ui: &mut Ui,
+    working_id: &mut Option<u32>,
+    working_data: &mut TodoItem,
+    theirs: &TodoItem,
+    action: PendingAction,
+    conflict: &mut Option<Conflict>,
+    error_message: &mut Option<String>
+) -> Option<UndoToast> {
+    let mut done = None;
+    ui.separator();
+    ui.colored_label(egui::Color32::YELLOW, "This task was changed by someone else since you opened it. Their version:");
+    ui.label(format!("Title: {}", theirs.title));
+    ui.label(format!("Description: {}", theirs.description));
+    ui.label(format!("Due Date: {}", theirs.due_date.to_rfc3339()));
+    ui.label(format!("Completed: {}", if theirs.completed { "✅" } else { "❌" }));
+
+    ui.horizontal(|ui| {
+        let overwrite_label = match action {
+            PendingAction::Save => "Overwrite with my changes",
+            PendingAction::Delete(_) => "Delete anyway",
+        };
+        if ui.button(overwrite_label).clicked() {
+            // Claim their revision as the one we've seen, so the backend lets the retry through
+            working_data.revision = theirs.revision;
+            *conflict = None;
+            done = match (action, *working_id) {
+                (PendingAction::Save, _) => save_task(working_id, working_data, conflict, error_message),
+                (PendingAction::Delete(children), Some(id)) => {
+                    delete_task(id, children, working_data, conflict, error_message)
                 }
+                (PendingAction::Delete(_), None) => None,
+            };
+        }
+
+        if ui.button("Reload their version").clicked() {
+            *working_data = theirs.clone();
+            *conflict = None;
+        }
+    });
+    ui.separator();
+    done
+}
+
+fn save_task(
+    working_id: &mut Option<u32>,
+    working_data: &mut TodoItem,
+    conflict: &mut Option<Conflict>,
+    error_message: &mut Option<String>
+) -> Option<UndoToast> {
+    let result = match *working_id {
+        Some(id) => todo_client::update_todo(id, working_data).map(|(stored, operation)| (id, stored, operation)),
+        // New task, so insert it and remember the id the backend allocated for later saves
+        None => todo_client::insert_todo(working_data)
+            .inspect(|(id, _)| *working_id = Some(*id))
+            .and_then(|(id, operation)| Ok((id, todo_client::view_todo(id)?, operation))),
+    };
+
+    match result {
+        Ok((id, stored, operation)) => {
+            println!("Task {} saved successfully", id);
+            // Picks up the new revision and timestamps, otherwise the next save would conflict with this one
+            *working_data = stored;
+            *error_message = None;
+            return UndoToast::new(format!("Task {} saved", id), operation);
+        }
+        Err(err) => match err.conflict() {
+            Some(theirs) => {
+                *conflict = Some(Conflict { theirs, action: PendingAction::Save });
+                *error_message = None;
             }
+            None => {
+                eprintln!("Failed to save task: {}", err);
+                *error_message = Some(format!("Failed to save task: {}", err));
+            }
+        },
+    }
+    None
+}
+
+fn delete_task(
+    id: u32,
+    children: Option<ChildrenOnDelete>,
+    working_data: &mut TodoItem,
+    conflict: &mut Option<Conflict>,
+    error_message: &mut Option<String>
+) -> Option<UndoToast> {
+    match todo_client::delete_todo(id, working_data.revision, children) {
+        Ok(operation) => {
+            println!("Task {} deleted successfully", id);
+            working_data.title.clear();
+            *error_message = None;
+            return UndoToast::new(format!("Task {} deleted", id), operation);
         }
+        Err(err) => match err.conflict() {
+            Some(theirs) => {
+                *conflict = Some(Conflict { theirs, action: PendingAction::Delete(children) });
+                *error_message = None;
+            }
+            None => {
+                eprintln!("Failed to delete task {}: {}", id, err);
+                *error_message = Some(format!("Failed to delete task {}: {}", id, err));
+            }
+        },
+    }
+    None
+}
 
-        if ui.button("Delete").clicked() {
-            // Send DELETE request to backend
-            let url = format!("http://127.0.0.1:8081/delete/{}", working_id);
-            match reqwest::blocking::Client::new()
-                .delete(&url)
-                .send()
-            {
-                Ok(resp) => {
-                    if resp.status().is_success() {
-                        println!("Task {} deleted successfully", working_id);
-                        working_data.title.clear();
-                    } else {
-                        eprintln!("Failed to delete task {}: {:?}", working_id, resp.status());
-                    }
+// Down in the corner after a save or delete. Undoing takes back that change on the backend, and
+// only that one, so a change made since from elsewhere isn't undone by mistake. The table hears
+// about it like about any other change.
+fn render_undo_toast(
+    ctx: &egui::Context,
+    undo_toast: &mut Option<UndoToast>,
+    show_window_data: &mut ShowWindowData,
+    error_message: &mut Option<String>
+) {
+    let Some(toast) = undo_toast.as_ref() else {
+        return;
+    };
+    let Some(remaining) = UNDO_TOAST_DURATION.checked_sub(toast.shown_at.elapsed()) else {
+        *undo_toast = None;
+        return;
+    };
+    // Nothing else might happen to repaint in time to take it down
+    ctx.request_repaint_after(remaining);
+
+    let undo_clicked = egui::Area::new(egui::Id::new("undo_toast"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-16.0, -16.0])
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(&toast.message);
+                    ui.button("Undo").clicked()
+                }).inner
+            }).inner
+        }).inner;
+    if !undo_clicked {
+        return;
+    }
+
+    let operation = toast.operation;
+    *undo_toast = None;
+    match todo_client::undo(operation) {
+        Ok(undone) => {
+            // The open task would otherwise be saved over what was just restored
+            if let Some(id) = show_window_data.working_data_id {
+                if let Some(entry) = undone.restored.iter().find(|entry| entry.id == id) {
+                    show_window_data.working_data = Some(entry.item.clone());
+                    show_window_data.conflict = None;
                 }
-                Err(err) => {
-                    eprintln!("HTTP request failed: {:?}", err);
+                if undone.deleted.contains(&id) {
+                    show_window_data.show_window = false;
                 }
             }
+            *error_message = None;
         }
-
-        if ui.button("Close window").clicked() {
-            *show_window = false; // Bug somewhere with this...
+        Err(err) => {
+            eprintln!("Failed to undo: {}", err);
+            *error_message = Some(format!("Failed to undo: {}", err));
         }
-    });
+    }
 }
 
 
@@ -232,10 +1074,24 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "My Todo List",
         options,
-        Box::new(|_cc| Ok(Box::new(TodoApp {
-            todo_entries: BTreeMap::new(),
+        Box::new(|cc| Ok(Box::new(TodoApp {
+            todo_entries: Vec::new(),
             first_run: true,
-            show_window_data: ShowWindowData { show_window: false, working_data: Option::None, working_data_id: Option::None }
+            user: session_user(),
+            login_form: LoginForm::default(),
+            query: TodoQuery::new(),
+            lists: Vec::new(),
+            members: BTreeMap::new(),
+            lists_loading: None,
+            lists_stale: false,
+            new_list_name: String::new(),
+            next_cursor: None,
+            show_window_data: ShowWindowData { show_window: false, working_data: Option::None, working_data_id: Option::None, conflict: None, tab: TaskTab::Details, history: None },
+            tree_view: false,
+            collapsed: BTreeSet::new(),
+            error_message: None,
+            events: follow_events(cc.egui_ctx.clone()),
+            undo_toast: None
         }))),
     )
 }
\ No newline at end of file
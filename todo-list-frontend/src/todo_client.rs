@@ -0,0 +1,250 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::sync::{LazyLock, PoisonError, RwLock};
+use reqwest::blocking::{Client, ClientBuilder, Response};
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue, IF_MATCH};
+use todo_list_common::{
+    credentials, ApiError, ChildrenOnDelete, Credentials, DeleteQuery, ErrorCode, HistoryEntry, InsertResponse, ListDeleteQuery, ListMember, LoginResponse, TodoEntry, TodoEvent, TodoItem, TodoList,
+    TodoListEntry, TodoPage, TodoQuery, UndoQuery, UndoResponse, UserInfo, OPERATION_HEADER,
+};
+
+const BASE_URL: &str = "http://127.0.0.1:8081";
+
+// Sent with every request. It starts out as whatever the CLI would send, $TODO_TOKEN or the
+// token in the credentials file, and changes when the user logs in or out here.
+static TOKEN: LazyLock<RwLock<Option<String>>> = LazyLock::new(|| RwLock::new(credentials::token()));
+
+fn token() -> Option<String> {
+    TOKEN.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+fn set_token(token: Option<String>) {
+    *TOKEN.write().unwrap_or_else(PoisonError::into_inner) = token;
+}
+
+fn client_builder() -> ClientBuilder {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token()
+        && let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", token))
+    {
+        headers.insert(AUTHORIZATION, value);
+    }
+    Client::builder().default_headers(headers)
+}
+
+fn client() -> Client {
+    client_builder().build().unwrap_or_default()
+}
+
+/// Either the backend couldn't be reached at all, or it answered with an error.
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Api(ApiError),
+    /// The connection broke off while reading a stream
+    Io(io::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "Could not talk to the backend at {}: {}", BASE_URL, err),
+            ClientError::Api(err) => write!(f, "{}", err),
+            ClientError::Io(err) => write!(f, "Lost the connection to the backend at {}: {}", BASE_URL, err),
+        }
+    }
+}
+
+impl ClientError {
+    /// When the item changed on the backend since we loaded it, this is its current version.
+    pub fn conflict(&self) -> Option<TodoItem> {
+        match self {
+            ClientError::Api(err) if err.code == ErrorCode::PreconditionFailed => err
+                .details
+                .clone()
+                .and_then(|details| serde_json::from_value::<TodoEntry>(details).ok())
+                .map(|entry| entry.item),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+// Turns an error status into the ApiError the backend sent along with it
+// The operation a change was recorded as, for undoing exactly that one
+fn operation(resp: &Response) -> Option<u64> {
+    resp.headers().get(OPERATION_HEADER)?.to_str().ok()?.parse().ok()
+}
+
+fn check(resp: Response) -> Result<Response, ClientError> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status().as_u16();
+    let body = resp.text().unwrap_or_default();
+    Err(ClientError::Api(ApiError::from_response(status, &body)))
+}
+
+pub fn list_todos(query: &TodoQuery) -> Result<TodoPage, ClientError> {
+    let resp = client()
+        .get(format!("{}/list", BASE_URL))
+        .query(query)
+        .send()?;
+    Ok(check(resp)?.json::<TodoPage>()?)
+}
+
+pub fn view_todo(id: u32) -> Result<TodoItem, ClientError> {
+    let resp = client().get(format!("{}/view/{}", BASE_URL, id)).send()?;
+    Ok(check(resp)?.json::<TodoItem>()?)
+}
+
+/// Returns the new id, and the operation to pass to `undo` to take the insert back.
+pub fn insert_todo(item: &TodoItem) -> Result<(u32, Option<u64>), ClientError> {
+    let resp = client()
+        .post(format!("{}/insert", BASE_URL))
+        .json(item)
+        .send()?;
+    let resp = check(resp)?;
+    let operation = operation(&resp);
+    Ok((resp.json::<InsertResponse>()?.id, operation))
+}
+
+/// Only saves if nobody changed the item since `item.revision`, and returns it as stored, along
+/// with the operation to pass to `undo`.
+pub fn update_todo(id: u32, item: &TodoItem) -> Result<(TodoItem, Option<u64>), ClientError> {
+    let resp = client()
+        .put(format!("{}/update/{}", BASE_URL, id))
+        .header(IF_MATCH, item.etag())
+        .json(item)
+        .send()?;
+    let resp = check(resp)?;
+    let operation = operation(&resp);
+    Ok((resp.json::<TodoEntry>()?.item, operation))
+}
+
+/// Only deletes if nobody changed the item since `revision`. A task with subtasks needs
+/// `children` to say what happens to them. Returns the operation to pass to `undo`.
+pub fn delete_todo(id: u32, revision: u64, children: Option<ChildrenOnDelete>) -> Result<Option<u64>, ClientError> {
+    let resp = client()
+        .delete(format!("{}/delete/{}", BASE_URL, id))
+        .header(IF_MATCH, format!("\"{}\"", revision))
+        .query(&DeleteQuery { children })
+        .send()?;
+    let resp = check(resp)?;
+    Ok(operation(&resp))
+}
+
+pub fn list_lists() -> Result<Vec<TodoListEntry>, ClientError> {
+    let resp = client().get(format!("{}/lists", BASE_URL)).send()?;
+    Ok(check(resp)?.json::<Vec<TodoListEntry>>()?)
+}
+
+pub fn create_list(name: &str) -> Result<TodoListEntry, ClientError> {
+    let resp = client()
+        .post(format!("{}/lists", BASE_URL))
+        .json(&TodoList { name: name.to_string() })
+        .send()?;
+    Ok(check(resp)?.json::<TodoListEntry>()?)
+}
+
+/// The tasks on the list are moved to `move_to`.
+pub fn delete_list(id: u32, move_to: u32) -> Result<(), ClientError> {
+    let resp = client()
+        .delete(format!("{}/lists/{}", BASE_URL, id))
+        .query(&ListDeleteQuery { move_to: Some(move_to) })
+        .send()?;
+    check(resp)?;
+    Ok(())
+}
+
+pub fn list_members(id: u32) -> Result<Vec<ListMember>, ClientError> {
+    let resp = client().get(format!("{}/lists/{}/members", BASE_URL, id)).send()?;
+    Ok(check(resp)?.json::<Vec<ListMember>>()?)
+}
+
+pub fn todo_history(id: u32) -> Result<Vec<HistoryEntry>, ClientError> {
+    let resp = client().get(format!("{}/todos/{}/history", BASE_URL, id)).send()?;
+    Ok(check(resp)?.json::<Vec<HistoryEntry>>()?)
+}
+
+/// Takes back the change recorded as `operation`, as long as nothing happened to its todos
+/// since, see `POST /undo`.
+pub fn undo(operation: u64) -> Result<UndoResponse, ClientError> {
+    let resp = client()
+        .post(format!("{}/undo", BASE_URL))
+        .query(&UndoQuery { operation: Some(operation) })
+        .send()?;
+    Ok(check(resp)?.json::<UndoResponse>()?)
+}
+
+/// Logs in and keeps the token for the requests after it, in the credentials file as well.
+pub fn login(credentials: &Credentials) -> Result<UserInfo, ClientError> {
+    let resp = client().post(format!("{}/auth/login", BASE_URL)).json(credentials).send()?;
+    let LoginResponse { token, expires_at, user } = check(resp)?.json::<LoginResponse>()?;
+    let stored = credentials::StoredCredentials { username: user.username.clone(), token: token.clone(), expires_at };
+    // The session works without the file, it only won't outlast the app
+    if let Err(err) = credentials::save(&stored) {
+        eprintln!("Failed to save the credentials: {}", err);
+    }
+    set_token(Some(token));
+    Ok(user)
+}
+
+/// Ends the session, forgetting the token even if the backend can't be told.
+pub fn logout() {
+    if token().is_some()
+        && let Err(err) = client().post(format!("{}/auth/logout", BASE_URL)).send().map_err(ClientError::from).and_then(check)
+    {
+        eprintln!("Failed to end the session on the backend: {}", err);
+    }
+    if let Err(err) = credentials::remove() {
+        eprintln!("Failed to remove the credentials file: {}", err);
+    }
+    set_token(None);
+}
+
+/// Who the token belongs to, `None` when there's no token to send.
+pub fn whoami() -> Result<Option<UserInfo>, ClientError> {
+    if token().is_none() {
+        return Ok(None);
+    }
+    let resp = client().get(format!("{}/auth/me", BASE_URL)).send()?;
+    Ok(Some(check(resp)?.json::<UserInfo>()?))
+}
+
+/// Subscribes to `GET /events`. The events come in as the backend sends them, so iterating
+/// blocks, until the backend closes the stream. Meant for a thread of its own.
+pub fn subscribe() -> Result<impl Iterator<Item = Result<TodoEvent, ClientError>>, ClientError> {
+    // The stream stays open for as long as the backend runs, a timeout would cut it off
+    let client = client_builder().timeout(None).build()?;
+    let resp = check(client.get(format!("{}/events", BASE_URL)).send()?)?;
+    // The events are only those of whoever was logged in when subscribing
+    let subscribed_as = token();
+
+    // The data of an event may be split over several lines, a blank line ends it. The event
+    // name is in the data as well, and lines starting with ':' are keep-alive comments.
+    let mut lines = BufReader::new(resp).lines();
+    Ok(std::iter::from_fn(move || {
+        let mut data = String::new();
+        loop {
+            // Checked at least as often as the backend sends a keep-alive
+            if token() != subscribed_as {
+                return None;
+            }
+            let line = match lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(ClientError::Io(err))),
+            };
+            if let Some(chunk) = line.strip_prefix("data:") {
+                data.push_str(chunk.trim_start());
+            } else if line.is_empty() && !data.is_empty() {
+                return Some(serde_json::from_str::<TodoEvent>(&data).map_err(|err| ClientError::Io(err.into())));
+            }
+        }
+    }))
+}